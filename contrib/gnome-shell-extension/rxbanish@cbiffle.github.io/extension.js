@@ -0,0 +1,84 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+// Offers rxbanish a way to hide the pointer, which only the Shell can do
+// under Wayland. rxbanish decides when; this only does as it's told, and
+// brings the pointer back if whoever hid it goes away without doing so.
+
+import Gio from 'gi://Gio';
+
+import {Extension} from 'resource:///org/gnome/shell/extensions/extension.js';
+
+const INTERFACE = `
+<node>
+  <interface name="io.github.cbiffle.Rxbanish1">
+    <method name="HidePointer"/>
+    <method name="ShowPointer"/>
+  </interface>
+</node>`;
+
+class Pointer {
+    constructor() {
+        this._tracker = global.backend.get_cursor_tracker();
+        // The caller who last hid the pointer, and our watch on its name.
+        this._owner = null;
+        this._watch = 0;
+    }
+
+    HidePointerAsync(params, invocation) {
+        this._watchOwner(invocation.get_sender());
+        this._tracker.set_pointer_visible(false);
+        invocation.return_value(null);
+    }
+
+    ShowPointerAsync(params, invocation) {
+        this.show();
+        invocation.return_value(null);
+    }
+
+    show() {
+        this._unwatchOwner();
+        this._tracker.set_pointer_visible(true);
+    }
+
+    // Keeps an eye on `name`, so that if it leaves the bus with the pointer
+    // hidden, by crashing, say, the pointer doesn't stay that way.
+    _watchOwner(name) {
+        if (name === this._owner)
+            return;
+        this._unwatchOwner();
+        this._owner = name;
+        this._watch = Gio.bus_watch_name(
+            Gio.BusType.SESSION,
+            name,
+            Gio.BusNameWatcherFlags.NONE,
+            null,
+            () => this.show());
+    }
+
+    _unwatchOwner() {
+        if (this._watch) {
+            Gio.bus_unwatch_name(this._watch);
+            this._watch = 0;
+        }
+        this._owner = null;
+    }
+}
+
+export default class RxbanishExtension extends Extension {
+    enable() {
+        this._pointer = new Pointer();
+        this._object = Gio.DBusExportedObject.wrapJSObject(
+            INTERFACE, this._pointer);
+        this._object.export(Gio.DBus.session, '/io/github/cbiffle/Rxbanish');
+    }
+
+    disable() {
+        this._object.unexport();
+        this._object = null;
+        // Don't leave the pointer hidden with nobody to bring it back.
+        this._pointer.show();
+        this._pointer = null;
+    }
+}
@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Having the X server keep time for us, by way of SYNC alarms.
+//!
+//! The SYNC extension has counters the server keeps up to date, and alarms
+//! that send an event when a counter reaches a given value. Two of the
+//! counters are clocks: SERVERTIME counts milliseconds, and IDLETIME counts
+//! milliseconds since the user last touched any input device. Setting an
+//! alarm on one of those lets a timeout arrive as an event like any other, so
+//! there's nothing else to wait on. Going by IDLETIME has the bonus that
+//! input we don't hear about still holds off the idle timeout, which is how
+//! the server's own screen saver does it.
+//!
+//! Servers without the extension (or without those counters) leave us to keep
+//! time ourselves.
+
+use std::time::Instant;
+
+use anyhow::Result;
+use log::debug;
+use xcb::{sync, Connection, Extension};
+
+/// Keeps the one alarm we need.
+pub struct SyncAlarm {
+    /// The IDLETIME and SERVERTIME counters, if the server has them.
+    counters: Option<Counters>,
+    /// Our alarm, once we've made it.
+    alarm: Option<sync::Alarm>,
+    /// What the alarm is set for, if it's set: when it goes off, and whether
+    /// that's by the idle clock.
+    armed: Option<(Instant, bool)>,
+}
+
+#[derive(Copy, Clone)]
+struct Counters {
+    idle: sync::Counter,
+    time: sync::Counter,
+}
+
+impl SyncAlarm {
+    /// Finds the counters we need, if the server has them.
+    pub fn new(conn: &Connection) -> Result<Self> {
+        let mut alarm = Self {
+            counters: None,
+            alarm: None,
+            armed: None,
+        };
+        if !conn.active_extensions().any(|e| e == Extension::Sync) {
+            return Ok(alarm);
+        }
+        conn.wait_for_reply(conn.send_request(&sync::Initialize {
+            desired_major_version: 3,
+            desired_minor_version: 1,
+        }))?;
+        let reply = conn
+            .wait_for_reply(conn.send_request(&sync::ListSystemCounters {}))?;
+        let find = |name: &[u8]| {
+            reply
+                .counters()
+                .find(|c| c.name().as_bytes() == name)
+                .map(|c| c.counter())
+        };
+        let (idle, time) = (find(b"IDLETIME"), find(b"SERVERTIME"));
+        if let (Some(idle), Some(time)) = (idle, time) {
+            alarm.counters = Some(Counters { idle, time });
+        }
+        Ok(alarm)
+    }
+
+    /// Whether the server can keep time for us.
+    pub fn available(&self) -> bool {
+        self.counters.is_some()
+    }
+
+    /// Sets the alarm to go off no later than `deadline`. If `idle` is set,
+    /// the deadline is pushed back by however much input the user gives in
+    /// the meantime, as far as the server can tell.
+    ///
+    /// Going off early does no harm, since the banisher checks the time for
+    /// itself, so the alarm is only moved when it would otherwise be late.
+    /// Deadlines move later with every bit of pointer motion, and this saves
+    /// us a round trip to the server for each of those.
+    pub fn set(
+        &mut self,
+        conn: &Connection,
+        deadline: Option<Instant>,
+        idle: bool,
+    ) -> Result<()> {
+        let Some(counters) = self.counters else {
+            return Ok(());
+        };
+        let Some(deadline) = deadline else {
+            return Ok(());
+        };
+        if let Some((armed, armed_idle)) = self.armed {
+            if armed_idle == idle && armed <= deadline {
+                return Ok(());
+            }
+        }
+        self.armed = Some((deadline, idle));
+        // The counters count from whenever the server started, so the alarm
+        // goes relative to wherever they are now. For IDLETIME, that means
+        // any input between now and then resets the clock, and it goes off
+        // once the user has been idle for the rest of the time in one go.
+        let ms = deadline.saturating_duration_since(Instant::now()).as_millis();
+        let ms = u32::try_from(ms).unwrap_or(u32::MAX);
+        let values = [
+            sync::Ca::Counter(if idle { counters.idle } else { counters.time }),
+            sync::Ca::ValueType(sync::Valuetype::Relative),
+            sync::Ca::Value(sync::Int64 { hi: 0, lo: ms }),
+            sync::Ca::TestType(sync::Testtype::PositiveComparison),
+            sync::Ca::Delta(sync::Int64 { hi: 0, lo: 0 }),
+            sync::Ca::Events(1),
+        ];
+        match self.alarm {
+            Some(id) => conn.send_and_check_request(&sync::ChangeAlarm {
+                id,
+                value_list: &values,
+            })?,
+            None => {
+                let id = conn.generate_id();
+                conn.send_and_check_request(&sync::CreateAlarm {
+                    id,
+                    value_list: &values,
+                })?;
+                self.alarm = Some(id);
+            }
+        }
+        debug!(ms, idle; "set SYNC alarm");
+        Ok(())
+    }
+
+    /// Checks whether `event` is our alarm going off. Once it has, it needs
+    /// setting again.
+    pub fn went_off(&mut self, event: &sync::AlarmNotifyEvent) -> bool {
+        if self.alarm != Some(event.alarm()) {
+            return false;
+        }
+        self.armed = None;
+        true
+    }
+}
@@ -0,0 +1,1269 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The state machine that decides when the pointer should be hidden.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use anyhow::Result;
+use log::{debug, info, trace, warn};
+use xcb::{screensaver, sync, x, xfixes, xinput, Event};
+
+use crate::focus::class_under_pointer;
+use crate::input::{
+    describe_devices, device_names, is_scroll, key_change, master_pointers,
+    modifier_state, source_device, touchscreens, KeyChange,
+};
+use crate::keysym::Keymap;
+use crate::park::Parking;
+use crate::pointer::{self, Position};
+use crate::policy::{Action, Change, Command, Policy, Reason, State, Stats};
+use crate::{
+    Banish, DevicePolicy, HideScope, Input, Method, Session, Settings,
+    Suspension,
+};
+
+/// Decides when to hide and show the pointer, and does it.
+///
+/// This doesn't do any waiting of its own. Feed it events from the X server
+/// with `handle_event`, call `handle_timeout` once `deadline` has passed, and
+/// pass along any `Command`s from the user. What to do about them, apart from
+/// the parts that need X, is up to its `Policy`.
+pub struct Banisher {
+    settings: Settings,
+    policy: Policy,
+    /// Keys held down, by device and keycode, for spotting auto-repeat.
+    held: HashSet<(u16, u32)>,
+    /// The last key to come back up, and when, for the same.
+    last_release: Option<((u16, u32), x::Timestamp)>,
+
+    // The rest is about the pointer on the current connection, and starts
+    // over when we reconnect.
+    /// Time of the last input of any kind, for keeping an eye on DPMS.
+    last_input: Instant,
+    /// Where the pointer was last hidden, if we're watching for jitter.
+    hidden_at: Option<Position>,
+    /// How we hid the pointer, so we can undo the right thing even if the
+    /// settings change while it's hidden.
+    banished_by: Banish,
+    /// The windows we hid the pointer on with XFixes, if we did.
+    hidden_on: Vec<x::Window>,
+    /// Where the pointer was before we moved it into the corner, if we did.
+    banished_from: Option<Position>,
+    /// Barriers keeping the pointer in the corner, if we put any up.
+    parking: Option<Parking>,
+    /// Master pointers we've hidden with a blank cursor, rather than with
+    /// XFixes. This only happens when there's more than one master pointer,
+    /// and it's the only way other master pointers than ours get hidden.
+    cloaked: HashMap<u16, Cloaked>,
+}
+
+impl Banisher {
+    /// Makes a banisher that'll follow `settings`.
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            banished_by: settings.banish,
+            settings,
+            policy: Policy::default(),
+            held: HashSet::new(),
+            last_release: None,
+            last_input: Instant::now(),
+            hidden_at: None,
+            hidden_on: vec![],
+            banished_from: None,
+            parking: None,
+            cloaked: HashMap::new(),
+        }
+    }
+
+    /// The settings we're following.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Starts following `settings` instead. If we're connected, follow this
+    /// with `settings_changed` to catch up.
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    /// Catches up with new settings on `x`, by grabbing the new hotkeys and
+    /// checking whether the pointer should still be hidden.
+    pub fn settings_changed(&mut self, x: &Session) -> Result<()> {
+        x.grab_hotkeys(&self.settings)?;
+        self.change(x, self.policy.state(), Reason::Other, None)
+    }
+
+    /// What's been decided about the pointer, apart from what needs X.
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Whether someone has paused us.
+    pub fn paused(&self) -> bool {
+        self.policy.paused()
+    }
+
+    /// Whether the pointer is currently hidden.
+    pub fn hidden(&self) -> bool {
+        self.policy.hidden()
+    }
+
+    /// Whether we're leaving the pointer alone for now because of what's
+    /// going on in `x`, e.g. a fullscreen window or a screen locker.
+    pub fn suspended(&self, x: &Session) -> bool {
+        self.settings.suspended_for(x)
+    }
+
+    /// Why we're leaving the pointer alone for now, if we are.
+    pub fn suspension(&self, x: &Session) -> Option<Suspension> {
+        self.settings.suspension_for(x)
+    }
+
+    /// Names the input devices on `x` that we're listening to, in order.
+    pub fn devices(&self, x: &Session) -> Vec<String> {
+        let mut names: Vec<String> = x
+            .devices
+            .values()
+            .filter(|name| {
+                self.settings.device_policy(name) != DevicePolicy::Ignore
+            })
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Our running totals.
+    pub fn stats(&self) -> &Stats {
+        self.policy.stats()
+    }
+
+    /// Collects the most recent request to switch profiles, if there's been
+    /// one since last time. `None` inside means back to the usual settings.
+    pub fn take_profile_switch(&mut self) -> Option<Option<String>> {
+        self.policy.take_profile_switch()
+    }
+
+    /// Collects the times we've hidden or shown the pointer since last time,
+    /// oldest first. Only the most recent few are kept if nobody asks.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        self.policy.take_changes()
+    }
+
+    /// Gets going on a freshly connected `x`. This needs doing after each
+    /// `Session::connect`, before anything else.
+    pub fn start(&mut self, x: &Session) -> Result<()> {
+        // A freshly connected server always starts out showing the pointer,
+        // and anything we'd set up on the last one is gone.
+        self.policy.restart();
+        self.last_input = Instant::now();
+        self.hidden_at = None;
+        self.banished_by = self.settings.banish;
+        self.hidden_on.clear();
+        self.banished_from = None;
+        self.parking = None;
+        self.cloaked.clear();
+        self.held.clear();
+        self.last_release = None;
+
+        self.report(x)?;
+        if self.settings.park && !x.barriers {
+            warn!("this X server can't do pointer barriers, so not parking");
+        }
+        if !x.xfixes {
+            warn!("this X server can't hide the pointer, so blanking it");
+        }
+        // Monitors come and go, so this is only worth a mention.
+        for name in &self.settings.only_outputs {
+            if !x.outputs.exists(name) {
+                warn!("there's no output called {name:?} right now");
+            }
+        }
+
+        x.grab_hotkeys(&self.settings)?;
+
+        if self.settings.start_hidden {
+            self.change(x, State::Hidden, Reason::Start, None)?;
+        }
+        Ok(())
+    }
+
+    /// Sums up what the server on `x` can do, and how we're going about
+    /// things there, so that it's in the log for anyone tracking down a
+    /// problem.
+    fn report(&self, x: &Session) -> Result<()> {
+        let setup = x.conn.get_setup();
+        let vendor = format!(
+            "{} {}",
+            setup.vendor().to_utf8(),
+            setup.release_number()
+        );
+        let xfixes = format!("{}.{}", x.xfixes_version.0, x.xfixes_version.1);
+        let xinput = format!("{}.{}", x.xinput_version.0, x.xinput_version.1);
+        let method = match self.settings.method {
+            Method::Xfixes if x.xfixes && !x.mpx() => "xfixes",
+            // See `banish` for why these fall back on a blank cursor.
+            Method::Xfixes => "blank-cursor",
+            Method::WindowCursor => "window-cursor",
+            Method::CoverWindow => "cover-window",
+            Method::Command => "command",
+        };
+        let devices = describe_devices(&x.conn, &self.settings)?
+            .into_iter()
+            .filter(|d| d.snoop.is_some() && d.policy != DevicePolicy::Ignore)
+            .count();
+        let input = match x.input {
+            Input::Auto | Input::Xi2 | Input::Legacy => "xinput",
+            Input::Xrecord => "xrecord",
+            Input::Evdev => "evdev",
+        };
+        info!(
+            vendor, xwayland = x.xwayland, xfixes, xinput, raw = x.raw, input,
+            method, devices;
+            "connected to X server"
+        );
+        Ok(())
+    }
+
+    /// Works out when `handle_timeout` next needs calling, if ever.
+    pub fn deadline(&self, x: &Session) -> Option<Instant> {
+        // Devices need snooping on whatever we're up to.
+        let retry = x.registrations.next_retry();
+        // While we're suspended the pointer stays put, so there's no point in
+        // waking up otherwise.
+        if self.policy.paused() || self.settings.suspended_for(x) {
+            return retry;
+        }
+        let dpms = x.dpms.next_check(self.last_input);
+        let timeout = self.policy.timeout(&self.settings);
+        [timeout, dpms, retry].into_iter().flatten().min()
+    }
+
+    /// Works out when `handle_timeout` next needs calling, like `deadline`.
+    /// If the settings ask for SYNC alarms and the server can do them, this
+    /// sets one for then instead, which turns up as an event for
+    /// `handle_event`, and returns `None`.
+    pub fn schedule(&self, x: &mut Session) -> Result<Option<Instant>> {
+        let deadline = self.deadline(x);
+        if !self.settings.sync_alarms || !x.alarm.available() {
+            return Ok(deadline);
+        }
+        // The idle timeout goes by the server's idle clock, which hears about
+        // all input, not just what we listen for. Everything else goes by the
+        // ordinary kind.
+        let idle = !self.policy.hidden()
+            && deadline.is_some()
+            && deadline == self.policy.timeout(&self.settings);
+        x.alarm.set(&x.conn, deadline, idle)?;
+        Ok(None)
+    }
+
+    /// Acts on the deadline having passed. It's harmless to call this early,
+    /// or when there's no deadline at all.
+    pub fn handle_timeout(&mut self, x: &mut Session) -> Result<()> {
+        x.registrations.retry(&x.conn, &x.roots, x.raw)?;
+        if self.policy.paused() || self.settings.suspended_for(x) {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if x.dpms.next_check(self.last_input).is_some_and(|d| d <= now) {
+            x.dpms.refresh(&x.conn)?;
+            if x.dpms.blanked() {
+                debug!("monitors have powered down");
+                // Which counts as a suspension, so this shows the pointer.
+                let state = self.policy.state();
+                return self.change(x, state, Reason::Other, None);
+            }
+        }
+        if self.policy.expired(&self.settings, now).is_none() {
+            return Ok(());
+        }
+        if !self.policy.hidden() {
+            // Our idea of when the pointer last moved only decides when to
+            // look; the server has the final say on how long the user has
+            // really been idle, since it sees input we don't.
+            if let Some(idle) = x.screensaver.idle(&x.conn)? {
+                let since = now.checked_sub(idle).unwrap_or(now);
+                self.policy.active_since(since);
+            }
+        }
+        match self.policy.expired(&self.settings, now) {
+            Some((target, reason)) => self.change(x, target, reason, None),
+            None => Ok(()),
+        }
+    }
+
+    /// Drops whatever we know about input device `device`, which has been
+    /// disabled or unplugged.
+    fn forget_device(&mut self, device: u16) {
+        // Keys it was holding down will never come back up.
+        self.held.retain(|&(d, _)| d != device);
+        if self.last_release.is_some_and(|((d, _), _)| d == device) {
+            self.last_release = None;
+        }
+        // Nor will a master pointer's keyboard type any more.
+        self.policy.forget_seat(device);
+    }
+
+    /// Carries out `cmd`.
+    pub fn run(&mut self, x: &mut Session, cmd: Command) -> Result<()> {
+        if let Command::Resync = cmd {
+            return self.resync(x);
+        }
+        let target = self.policy.command(cmd);
+        self.change(x, target, Reason::Command, None)
+    }
+
+    /// Carries out `Command::Resync`.
+    fn resync(&mut self, x: &mut Session) -> Result<()> {
+        // Everyone's pointer comes back, which is what they'll expect to see
+        // after waking the machine up. Do this before refreshing, since the
+        // master pointers may not be what they were.
+        let others: Vec<u16> = self
+            .cloaked
+            .keys()
+            .copied()
+            .filter(|&m| m != x.client_pointer)
+            .collect();
+        for master in others {
+            self.change_other(x, master, State::Shown, Reason::Wake, None)?;
+        }
+        self.change(x, State::Shown, Reason::Wake, None)?;
+        x.refresh()?;
+        x.grab_hotkeys(&self.settings)
+    }
+
+    /// Acts on an event from the X server. Returns `true` if another rxbanish
+    /// has taken over from us, and it's time to exit.
+    pub fn handle_event(
+        &mut self,
+        x: &mut Session,
+        event: Event,
+    ) -> Result<bool> {
+        if let Event::Sync(sync::Event::AlarmNotify(e)) = &event {
+            if x.alarm.went_off(e) {
+                self.handle_timeout(x)?;
+            }
+            return Ok(false);
+        }
+        let conn = &x.conn;
+        let reason = reason_of(&event);
+        let (device, key) = match &event {
+            Event::Input(e) => (source_device(e), key_change(e)),
+            _ => (None, None),
+        };
+        // Input from devices belonging to master pointers other than ours
+        // only affects their own pointer. On a multiseat setup, those belong
+        // to someone else entirely, so nothing they do should affect ours.
+        let other = device
+            .and_then(|id| x.masters.get(&id))
+            .copied()
+            .filter(|&m| m != x.client_pointer);
+        let master = other.unwrap_or(x.client_pointer);
+        let state = match other {
+            Some(m) if self.cloaked.contains_key(&m) => State::Hidden,
+            Some(_) => State::Shown,
+            None => self.policy.state(),
+        };
+        trace!(device; "{event:?}");
+        // Work out what to say about the event now, since deciding what to do
+        // about it uses it up.
+        let traced = if self.settings.trace_events {
+            Some(self.describe(x, &event, master)?)
+        } else {
+            None
+        };
+        if let Some(device) = device {
+            *self.policy.stats_mut().events.entry(device).or_default() += 1;
+            self.last_input = Instant::now();
+            // Input wakes up monitors that DPMS has powered down.
+            if x.dpms.blanked() {
+                x.dpms.refresh(conn)?;
+            }
+        }
+        // Input from devices the user has told us to ignore doesn't count,
+        // and nor do touchscreens if they've asked. Other devices may only be
+        // allowed to do half the job.
+        let policy = device
+            .and_then(|id| x.devices.get(&id))
+            .map(|name| self.settings.device_policy(name))
+            .unwrap_or_default();
+        let touch = device.is_some_and(|id| x.touchscreens.contains(&id));
+        let repeat = match (device, key) {
+            (Some(device), Some(key)) => self.repeated(device, key),
+            _ => false,
+        };
+        let ignored = match action(&event, key, repeat) {
+            Some(action) => {
+                !Policy::admits(&self.settings, policy, action, touch)
+            }
+            None => false,
+        };
+        let target = match event {
+            _ if ignored => state,
+            Event::Input(
+                xinput::Event::RawMotion(_)
+                | xinput::Event::DeviceValuator(_)
+                | xinput::Event::DeviceMotionNotify(_)
+            ) => {
+                // Movement reveals the cursor, unless it's just jitter, or
+                // we've been asked to wait for a button.
+                let hidden_at = match other {
+                    Some(m) => self.cloaked.get(&m).and_then(|c| c.at),
+                    None => self.hidden_at,
+                };
+                let jitter = match (self.settings.jitter, hidden_at) {
+                    (Some(limit), Some(from))
+                        if self.settings.motion_reveals =>
+                    {
+                        let to = match other {
+                            Some(m) => {
+                                Position::query_master(conn, from.root, m)?
+                            }
+                            None => Position::query(conn, x.roots[0])?,
+                        };
+                        !from.moved_beyond(to, limit)
+                    }
+                    _ => false,
+                };
+                if jitter {
+                    state
+                } else {
+                    self.moved(master, other.is_none(), Action::Motion, state)
+                }
+            }
+            Event::Input(xinput::Event::ProximityIn(_)) => {
+                // A pen coming into range is as good as movement, and comes
+                // before it, so that the pointer is back by the time the pen
+                // touches down.
+                if self.settings.motion_reveals {
+                    self.moved(master, other.is_none(), Action::Motion, state)
+                } else {
+                    state
+                }
+            }
+            Event::Input(xinput::Event::ProximityOut(_)) => state,
+            Event::Input(
+                xinput::Event::RawButtonPress(_)
+                | xinput::Event::DeviceButtonPress(_)
+                | xinput::Event::DeviceButtonRelease(_)
+            ) => {
+                // Any button is enough to reveal the cursor.
+                let action = match &event {
+                    Event::Input(e) if is_scroll(e) => Action::Scroll,
+                    _ => Action::Button,
+                };
+                self.moved(master, other.is_none(), action, state)
+            }
+            // Only the end of a keystroke that the settings say gets this
+            // far; see `Policy::admits`.
+            Event::Input(
+                xinput::Event::RawKeyPress(e) | xinput::Event::RawKeyRelease(e)
+            ) => self.raw_key_target(x, &e, state, master)?,
+            // Servers without XInput 2 send us these instead.
+            Event::Input(
+                xinput::Event::DeviceKeyPress(e)
+                | xinput::Event::DeviceKeyRelease(e)
+            ) => {
+                let (keycode, mods) = (e.detail(), e.state());
+                self.key_target(&x.keymap, keycode, mods, state, master)
+            }
+            Event::Input(xinput::Event::Hierarchy(e)) => {
+                // New devices need snooping on, for the things raw events
+                // don't cover. The master devices, which we can't open, are
+                // already covered by raw events. Devices that have gone away
+                // don't need anything from us anymore, and over a long
+                // session with lots of hotplugging, that adds up.
+                for info in e.infos() {
+                    let device = info.device().id();
+                    let flags = info.flags();
+                    if flags.intersects(
+                        xinput::HierarchyMask::SLAVE_REMOVED
+                            | xinput::HierarchyMask::MASTER_REMOVED
+                            | xinput::HierarchyMask::DEVICE_DISABLED,
+                    ) {
+                        debug!(device; "input device has gone away");
+                        if let Ok(id) = u8::try_from(device) {
+                            x.registrations.forget(id);
+                        }
+                        self.forget_device(device);
+                        continue;
+                    }
+                    let slave = matches!(
+                        info.r#type(),
+                        xinput::DeviceType::SlavePointer
+                            | xinput::DeviceType::SlaveKeyboard
+                            | xinput::DeviceType::FloatingSlave
+                    );
+                    let enabled =
+                        flags.contains(xinput::HierarchyMask::DEVICE_ENABLED);
+                    if !slave || !enabled {
+                        continue;
+                    }
+                    if let Ok(id) = u8::try_from(device) {
+                        if !x.registrations.registered(id) {
+                            x.registrations
+                                .register(conn, &x.roots, x.raw, id)?;
+                        }
+                    }
+                }
+                // Devices may have come, gone, been renamed, or moved from
+                // one master to another.
+                x.devices = device_names(conn)?;
+                x.touchscreens = touchscreens(conn)?;
+                x.masters = master_pointers(conn)?;
+                // A master pointer that's gone took its cursor with it.
+                self.cloaked.retain(|m, _| x.masters.values().any(|v| v == m));
+                state
+            }
+            Event::X(x::Event::SelectionClear(e)) if x.instance.lost(&e) => {
+                info!("another rxbanish has taken over; exiting");
+                return Ok(true);
+            }
+            Event::X(x::Event::KeyPress(e)) => {
+                // We only get core key events for our hotkeys.
+                let cmd = self.settings.hotkey_command(
+                    &x.keymap,
+                    e.detail(),
+                    e.state(),
+                );
+                match cmd {
+                    Some(cmd) => self.policy.command(cmd),
+                    None => state,
+                }
+            }
+            Event::X(x::Event::KeyRelease(_)) => state,
+            Event::X(x::Event::MapNotify(e)) => {
+                x.tools.handle_map(
+                    conn,
+                    e.window(),
+                    &self.settings.screenshot_tools,
+                )?;
+                state
+            }
+            Event::X(x::Event::UnmapNotify(e)) => {
+                x.tools.handle_unmap(e.window());
+                state
+            }
+            Event::X(x::Event::DestroyNotify(e)) => {
+                x.tools.handle_unmap(e.window());
+                state
+            }
+            // The rest of what SubstructureNotify gets us, which we don't care
+            // about.
+            Event::X(
+                x::Event::CreateNotify(_)
+                | x::Event::ConfigureNotify(_)
+                | x::Event::ReparentNotify(_)
+                | x::Event::GravityNotify(_)
+                | x::Event::CirculateNotify(_)
+            ) => state,
+            Event::X(x::Event::PropertyNotify(e)) => {
+                if x.focus.handle_property_notify(conn, &e)? {
+                    debug!(
+                        class = x.focus.class().map(|c| c.class.as_str()),
+                        title = x.focus.title(),
+                        window_type:? = x.focus.window_type();
+                        "focus changed"
+                    );
+                    self.follow_focus(x)?;
+                }
+                x.inhibit.handle_property_notify(conn, &e)?;
+                state
+            }
+            Event::ScreenSaver(screensaver::Event::Notify(e)) => {
+                x.screensaver.handle_notify(&e);
+                state
+            }
+            Event::XFixes(xfixes::Event::CursorNotify(_)) => {
+                // If we hid our pointer with a blank cursor, and someone has
+                // since given it a visible one, it's not hidden anymore,
+                // whatever we thought. Tidy up after ourselves to match.
+                if matches!(state, State::Hidden)
+                    && self.cloaked.contains_key(&x.client_pointer)
+                    && pointer::cursor_visible(conn)?
+                {
+                    debug!("another client has brought the pointer back");
+                    State::Shown
+                } else {
+                    state
+                }
+            }
+            Event::RandR(_) => {
+                debug!("outputs have changed");
+                x.outputs.refresh(conn)?;
+                // Any barriers we put up are fencing in a corner that may
+                // well not be one anymore.
+                if let Some(parking) = self.parking.take() {
+                    parking.release(conn)?;
+                }
+                state
+            }
+            Event::X(x::Event::MappingNotify(e)) => {
+                // We appear to get these as a side effect of device changes,
+                // as well as when someone runs xmodmap or the like. We only
+                // care about the keyboard and modifier mappings, but
+                // refetching them on a spurious notification is cheap.
+                if e.request() != x::Mapping::Pointer {
+                    x.keymap = Keymap::fetch(conn)?;
+                    // Hotkeys are grabbed by keycode, which may well have
+                    // changed.
+                    x.grab_hotkeys(&self.settings)?;
+                }
+                state
+            }
+            e => {
+                // This is _really_ not supposed to happen if I did the X event
+                // registration correctly...
+                warn!("unexpected event {e:?}");
+                state
+            }
+        };
+        // The reveal keys trump everything, so that there's
+        // always a way to get the pointer back. Letting go of one mustn't
+        // hide it again, either.
+        let target = match key {
+            Some(key) if self.reveals(&x.keymap, key) => {
+                if key.pressed {
+                    debug!(device; "reveal key pressed");
+                    State::Shown
+                } else {
+                    state
+                }
+            }
+            _ => target,
+        };
+        // Hotkeys we haven't grabbed turn up as ordinary key presses, and
+        // they get the last word too, since they're asking for something.
+        let hotkey = match key {
+            Some(key) if key.pressed && !repeat => {
+                self.ungrabbed_hotkey(x, key, master)?
+            }
+            _ => None,
+        };
+        let (target, reason) = match hotkey {
+            Some(cmd) => (self.policy.command(cmd), Reason::Command),
+            None => (target, reason),
+        };
+        if let Some((event, detail, mods)) = traced {
+            let name = device.and_then(|id| x.devices.get(&id));
+            info!(
+                event,
+                device,
+                name = name.map(String::as_str),
+                master,
+                detail,
+                mods = mods.as_deref(),
+                policy = policy.as_str(),
+                ignored,
+                from = state.as_str(),
+                to = target.as_str();
+                "event"
+            );
+        }
+        match other {
+            Some(master) => {
+                self.change_other(x, master, target, reason, device)?
+            }
+            None => self.change(x, target, reason, device)?,
+        }
+        Ok(false)
+    }
+
+    /// Names `event` for `Settings::trace_events`, along with the key or
+    /// button it's about, and the modifiers that were down on `master`'s
+    /// keyboard, where those make sense.
+    fn describe(
+        &self,
+        x: &Session,
+        event: &Event,
+        master: u16,
+    ) -> Result<(&'static str, Option<u32>, Option<String>)> {
+        // Raw key events don't say which modifiers are down, so we have to
+        // ask, the same as `raw_key_target` does.
+        let raw_mods = || modifier_state(&x.conn, x.roots[0], master);
+        let (name, detail, mods) = match event {
+            Event::Input(xinput::Event::RawMotion(_)) => {
+                ("raw-motion", None, None)
+            }
+            Event::Input(xinput::Event::RawButtonPress(e)) => {
+                ("raw-button-press", Some(e.detail()), None)
+            }
+            Event::Input(xinput::Event::RawKeyPress(e)) => {
+                ("raw-key-press", Some(e.detail()), Some(raw_mods()?))
+            }
+            Event::Input(xinput::Event::RawKeyRelease(e)) => {
+                ("raw-key-release", Some(e.detail()), Some(raw_mods()?))
+            }
+            Event::Input(xinput::Event::DeviceValuator(_)) => {
+                ("device-valuator", None, None)
+            }
+            Event::Input(xinput::Event::DeviceMotionNotify(e)) => {
+                ("device-motion", None, Some(e.state()))
+            }
+            Event::Input(xinput::Event::DeviceButtonPress(e)) => (
+                "device-button-press",
+                Some(u32::from(e.detail())),
+                Some(e.state()),
+            ),
+            Event::Input(xinput::Event::DeviceButtonRelease(e)) => (
+                "device-button-release",
+                Some(u32::from(e.detail())),
+                Some(e.state()),
+            ),
+            Event::Input(xinput::Event::DeviceKeyPress(e)) => (
+                "device-key-press",
+                Some(u32::from(e.detail())),
+                Some(e.state()),
+            ),
+            Event::Input(xinput::Event::DeviceKeyRelease(e)) => (
+                "device-key-release",
+                Some(u32::from(e.detail())),
+                Some(e.state()),
+            ),
+            Event::Input(xinput::Event::ProximityIn(_)) => {
+                ("proximity-in", None, None)
+            }
+            Event::Input(xinput::Event::ProximityOut(_)) => {
+                ("proximity-out", None, None)
+            }
+            Event::Input(xinput::Event::Hierarchy(_)) => {
+                ("hierarchy", None, None)
+            }
+            Event::X(x::Event::KeyPress(e)) => {
+                ("hotkey-press", Some(u32::from(e.detail())), Some(e.state()))
+            }
+            Event::X(x::Event::KeyRelease(e)) => (
+                "hotkey-release",
+                Some(u32::from(e.detail())),
+                Some(e.state()),
+            ),
+            Event::X(x::Event::PropertyNotify(_)) => {
+                ("property-notify", None, None)
+            }
+            Event::X(x::Event::MapNotify(_)) => ("map-notify", None, None),
+            Event::X(x::Event::UnmapNotify(_)) => ("unmap-notify", None, None),
+            Event::X(x::Event::DestroyNotify(_)) => {
+                ("destroy-notify", None, None)
+            }
+            Event::X(x::Event::MappingNotify(_)) => {
+                ("mapping-notify", None, None)
+            }
+            Event::X(x::Event::SelectionClear(_)) => {
+                ("selection-clear", None, None)
+            }
+            Event::ScreenSaver(_) => ("screensaver-notify", None, None),
+            Event::RandR(_) => ("randr-notify", None, None),
+            Event::XFixes(_) => ("cursor-notify", None, None),
+            _ => ("other", None, None),
+        };
+        Ok((name, detail, mods.map(|m| format!("{m:?}"))))
+    }
+
+    /// Works out which hotkey `key` being pressed on `master`'s keyboard is,
+    /// if it's one at all and we haven't grabbed the hotkeys. Grabbed ones
+    /// come to us separately.
+    fn ungrabbed_hotkey(
+        &self,
+        x: &Session,
+        key: KeyChange,
+        master: u16,
+    ) -> Result<Option<Command>> {
+        let settings = &self.settings;
+        if settings.grab_hotkeys || settings.hotkeys().next().is_none() {
+            return Ok(None);
+        }
+        let Ok(keycode) = u8::try_from(key.keycode) else {
+            return Ok(None);
+        };
+        // Raw events don't come with the modifiers, so we ask.
+        let mods = match key.mods {
+            Some(mods) => mods,
+            None => modifier_state(&x.conn, x.roots[0], master)?,
+        };
+        Ok(self.settings.hotkey_command(&x.keymap, keycode, mods))
+    }
+
+    /// Checks whether `key` is one of the reveal keys.
+    fn reveals(&self, keymap: &Keymap, key: KeyChange) -> bool {
+        let Ok(keycode) = u8::try_from(key.keycode) else {
+            return false;
+        };
+        keymap
+            .keysyms(keycode)
+            .any(|sym| self.settings.reveal_keys.contains(&sym))
+    }
+
+    /// Keeps track of which keys are held down, and works out whether `key`
+    /// on `device` is only the keyboard repeating one of them. Servers repeat
+    /// a key either with another press while it's held, or (without
+    /// detectable auto-repeat) with a release and a press at the same moment.
+    fn repeated(&mut self, device: u16, key: KeyChange) -> bool {
+        let id = (device, key.keycode);
+        if !key.pressed {
+            self.held.remove(&id);
+            self.last_release = Some((id, key.time));
+            return key.repeat;
+        }
+        let paired = self.last_release == Some((id, key.time));
+        !self.held.insert(id) || paired || key.repeat
+    }
+
+    /// Works out what state `master`'s pointer should be in after `action`,
+    /// given that it's in `state` now. It's `ours` if it's the one the policy
+    /// keeps the timeouts for.
+    fn moved(
+        &mut self,
+        master: u16,
+        ours: bool,
+        action: Action,
+        state: State,
+    ) -> State {
+        self.policy.pointer(&self.settings, master, ours, action, state)
+    }
+
+    /// Works out what state `master`'s pointer should be in after a keystroke
+    /// on its keyboard, given that it's in `state` now. Most keys hide it, but
+    /// the user may have asked us to ignore some, or to wait for more.
+    fn key_target(
+        &mut self,
+        keymap: &Keymap,
+        keycode: u8,
+        mods: x::KeyButMask,
+        state: State,
+        master: u16,
+    ) -> State {
+        let hotkey = self.settings.hotkey_command(keymap, keycode, mods);
+        let ignored_key = keymap
+            .keysyms(keycode)
+            .any(|sym| self.settings.ignores_key(sym));
+        if hotkey.is_some() {
+            state
+        } else if ignored_key {
+            self.policy.stats_mut().ignored_keys += 1;
+            state
+        } else {
+            let own = keymap.modifier_mask(keycode);
+            self.policy
+                .keystroke(&self.settings, master, mods, own)
+                .unwrap_or(state)
+        }
+    }
+
+    /// Like `key_target`, for raw key events. These don't come with the
+    /// modifier state, so we ask for it. By the time we ask, a modifier key
+    /// being released no longer counts as held, so we add whatever the key
+    /// itself is bound to, to match what XInput 1 would have told us.
+    fn raw_key_target(
+        &mut self,
+        x: &Session,
+        e: &xinput::RawKeyPressEvent,
+        state: State,
+        master: u16,
+    ) -> Result<State> {
+        let Ok(keycode) = u8::try_from(e.detail()) else {
+            return Ok(state);
+        };
+        let mods = modifier_state(&x.conn, x.roots[0], master)?
+            | x.keymap.modifier_mask(keycode);
+        Ok(self.key_target(&x.keymap, keycode, mods, state, master))
+    }
+
+    /// Moves the pointer to `target`, for `reason`, unless something means we
+    /// should be leaving it alone. `device` is the input device responsible,
+    /// if any, for the logs.
+    fn change(
+        &mut self,
+        x: &Session,
+        target: State,
+        reason: Reason,
+        device: Option<u16>,
+    ) -> Result<()> {
+        let conn = &x.conn;
+        // If we've been paused, or the focus has moved to somewhere we're
+        // meant to stay out of, make sure we don't leave the pointer hidden
+        // behind us.
+        let suspended = self.settings.suspended_for(x);
+        let (target, reason) = self.policy.settle(target, reason, suspended);
+        if self.policy.paused() || suspended {
+            // Everyone else's pointers come back too.
+            let others: Vec<u16> = self
+                .cloaked
+                .keys()
+                .copied()
+                .filter(|&m| m != x.client_pointer)
+                .collect();
+            for master in others {
+                let reason = reason.as_str();
+                if self.settings.dry_run {
+                    info!(reason, master; "would show pointer");
+                } else {
+                    debug!(reason, master; "showing pointer");
+                }
+                self.policy.stats_mut().shows += 1;
+                self.uncloak(x, master)?;
+            }
+        }
+        // Pulling the pointer out from under a grab breaks things like drags
+        // and region selectors, so wait until it's over.
+        let hiding = !self.policy.hidden() && target == State::Hidden;
+        let target = if hiding
+            && self.settings.grab_detect
+            && pointer::grabbed(conn, x.roots[0])?
+        {
+            debug!("not hiding pointer while it's grabbed");
+            State::Shown
+        } else {
+            target
+        };
+        // Likewise while a button's held, since not every drag comes with a
+        // grab we can see.
+        let target = if hiding
+            && target == State::Hidden
+            && pointer::buttons_held(conn, x.roots[0])?
+        {
+            debug!("not hiding pointer while a button is held");
+            State::Shown
+        } else {
+            target
+        };
+        let target = if hiding
+            && target == State::Hidden
+            && !self.on_chosen_output(x)?
+        {
+            debug!("not hiding pointer on this output");
+            State::Shown
+        } else {
+            target
+        };
+        let target = if hiding
+            && target == State::Hidden
+            && self.settings.only_over_focus
+            && !x.focus.under(conn, Position::query(conn, x.roots[0])?)?
+        {
+            debug!("not hiding pointer away from the focused window");
+            State::Shown
+        } else {
+            target
+        };
+        let target = if hiding
+            && target == State::Hidden
+            && self.over_excluded_class(x)?
+        {
+            debug!("not hiding pointer over an excluded application");
+            State::Shown
+        } else {
+            target
+        };
+        let name = device.and_then(|id| x.devices.get(&id));
+        let class = x.focus.class().map(|c| c.class.as_str());
+        let dry_run = self.settings.dry_run;
+        match self.policy.commit(
+            &self.settings,
+            target,
+            reason,
+            name.map(String::as_str),
+            class,
+        ) {
+            Some(true) => {
+                if !dry_run {
+                    self.banish(x)?;
+                }
+                self.hidden_at = match self.settings.jitter {
+                    Some(_) => Some(Position::query(conn, x.roots[0])?),
+                    None => None,
+                };
+            }
+            Some(false) if !dry_run => self.unbanish(x)?,
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Checks whether our pointer is on one of the outputs the settings
+    /// restrict us to, if they do.
+    fn on_chosen_output(&self, x: &Session) -> Result<bool> {
+        let only = &self.settings.only_outputs;
+        if only.is_empty() {
+            return Ok(true);
+        }
+        let pos = Position::query(&x.conn, x.roots[0])?;
+        Ok(x.outputs.at(pos).is_some_and(|o| only.contains(&o.name)))
+    }
+
+    /// Checks whether our pointer is over an application the settings say
+    /// not to hide it over.
+    fn over_excluded_class(&self, x: &Session) -> Result<bool> {
+        let excluded = &self.settings.excluded_hover_classes;
+        if excluded.is_empty() {
+            return Ok(false);
+        }
+        let root = Position::query(&x.conn, x.roots[0])?.root;
+        let class = class_under_pointer(&x.conn, root)?;
+        Ok(class.is_some_and(|c| excluded.iter().any(|n| c.matches(n))))
+    }
+
+    /// Gets our pointer out of the way, however the settings say to.
+    fn banish(&mut self, x: &Session) -> Result<()> {
+        // The caller takes care of this one; see `Method::Command`.
+        if self.settings.method == Method::Command {
+            return Ok(());
+        }
+        let conn = &x.conn;
+        // XFixes would hide everyone else's pointers along with ours, so if
+        // there are any, we only use it to move ours, and give it a blank
+        // cursor instead. The same goes if XFixes can't hide it at all, or
+        // the user would rather we didn't.
+        let banish = self.settings.banish;
+        let cloak = (x.mpx()
+            || !x.xfixes
+            || self.settings.method != Method::Xfixes)
+            && banish != Banish::Corner;
+        self.banished_by = if cloak { Banish::Corner } else { banish };
+        let windows = self.hide_on(x);
+        self.banished_from = if cloak && banish == Banish::Hide {
+            None
+        } else {
+            pointer::hide(
+                conn,
+                &windows,
+                &x.outputs,
+                self.banished_by,
+                self.settings.corner,
+            )?
+        };
+        if self.banished_by != Banish::Corner {
+            self.hidden_on = windows;
+        }
+        if cloak {
+            self.cloak(x, x.client_pointer)?;
+        }
+        // If we moved the pointer, it's now in the corner of the output it
+        // started on.
+        let park_on = self
+            .banished_from
+            .filter(|_| self.settings.park && x.barriers)
+            .and_then(|from| x.outputs.at(from));
+        if let Some(output) = park_on {
+            self.parking =
+                Some(Parking::new(conn, output, self.settings.corner)?);
+        }
+        Ok(())
+    }
+
+    /// Undoes `banish`.
+    fn unbanish(&mut self, x: &Session) -> Result<()> {
+        let conn = &x.conn;
+        if let Some(parking) = self.parking.take() {
+            parking.release(conn)?;
+        }
+        // Put the pointer back before showing it, so that it doesn't flash up
+        // in the corner first.
+        if let Some(from) = self.banished_from.take() {
+            if self.settings.restore {
+                from.warp(conn)?;
+            }
+        }
+        let windows = std::mem::take(&mut self.hidden_on);
+        pointer::show(conn, &windows, self.banished_by)?;
+        self.uncloak(x, x.client_pointer)
+    }
+
+    /// Works out which windows XFixes should hide the pointer on, going by
+    /// `Settings::hide_scope`. Without a focused window, that's the roots.
+    fn hide_on(&self, x: &Session) -> Vec<x::Window> {
+        match (self.settings.hide_scope, x.focus.active()) {
+            (HideScope::Focused, Some(window)) => vec![window],
+            _ => x.roots.clone(),
+        }
+    }
+
+    /// Keeps the pointer hidden on the focused window, if that's where we
+    /// hid it, when the focus moves.
+    fn follow_focus(&mut self, x: &Session) -> Result<()> {
+        if self.hidden_on.is_empty() {
+            return Ok(());
+        }
+        let windows = self.hide_on(x);
+        if windows == self.hidden_on {
+            return Ok(());
+        }
+        debug!(window:? = windows[0]; "moving hidden pointer to new focus");
+        // Hide it on the new window before showing it on the old, so that it
+        // doesn't flash up in between.
+        pointer::hide(
+            &x.conn,
+            &windows,
+            &x.outputs,
+            Banish::Hide,
+            self.settings.corner,
+        )?;
+        pointer::show(&x.conn, &self.hidden_on, Banish::Hide)?;
+        self.hidden_on = windows;
+        Ok(())
+    }
+
+    /// Like `change`, for a master pointer other than ours. These can only be
+    /// hidden and shown, and don't have timeouts of their own, but they do
+    /// respect pauses and suspensions.
+    fn change_other(
+        &mut self,
+        x: &Session,
+        master: u16,
+        target: State,
+        reason: Reason,
+        device: Option<u16>,
+    ) -> Result<()> {
+        // Let `change` take care of suspensions, for ours and everyone else's.
+        self.change(x, self.policy.state(), reason, device)?;
+        let hidden = self.cloaked.contains_key(&master);
+        let reason = reason.as_str();
+        match target {
+            State::Hidden
+                if !hidden
+                    && !self.policy.paused()
+                    && !self.policy.was_suspended() =>
+            {
+                if self.settings.dry_run {
+                    info!(reason, device, master; "would hide pointer");
+                } else {
+                    debug!(reason, device, master; "hiding pointer");
+                }
+                self.policy.stats_mut().hides += 1;
+                self.cloak(x, master)?;
+            }
+            State::Shown if hidden => {
+                if self.settings.dry_run {
+                    info!(reason, device, master; "would show pointer");
+                } else {
+                    debug!(reason, device, master; "showing pointer");
+                }
+                self.policy.stats_mut().shows += 1;
+                self.uncloak(x, master)?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Hides `master` with a blank cursor. On a dry run, we only pretend to.
+    fn cloak(&mut self, x: &Session, master: u16) -> Result<()> {
+        let conn = &x.conn;
+        // Without XInput 2, there's only the one pointer, and we have to
+        // change the windows' own cursors.
+        let device = x.raw.then_some(master);
+        // Other master pointers only get the basic treatment. We only get
+        // here if XFixes won't do, so for `Method::Xfixes` that's a blank
+        // cursor on the window under the pointer.
+        let method = if master == x.client_pointer {
+            self.settings.method
+        } else {
+            Method::Xfixes
+        };
+        let windows = match method {
+            _ if self.settings.dry_run => vec![],
+            Method::Xfixes => {
+                vec![pointer::cloak(conn, x.roots[0], x.blank, device)?]
+            }
+            Method::WindowCursor => {
+                pointer::cloak_all(conn, &x.roots, x.blank, device)?
+            }
+            Method::CoverWindow => {
+                vec![pointer::cover(conn, x.roots[0], x.blank)?]
+            }
+            // `banish` never gets this far for our own pointer, and other
+            // pointers don't use it.
+            Method::Command => vec![],
+        };
+        let covered = method == Method::CoverWindow;
+        // Our own pointer's jitter is taken care of by `hidden_at`.
+        let at = match self.settings.jitter {
+            Some(_) if master != x.client_pointer => {
+                Some(Position::query_master(conn, x.roots[0], master)?)
+            }
+            _ => None,
+        };
+        self.cloaked.insert(master, Cloaked { windows, covered, at });
+        Ok(())
+    }
+
+    /// Undoes `cloak`, if we did it.
+    fn uncloak(&mut self, x: &Session, master: u16) -> Result<()> {
+        if let Some(cloaked) = self.cloaked.remove(&master) {
+            let device = x.raw.then_some(master);
+            for window in cloaked.windows {
+                if cloaked.covered {
+                    pointer::uncover(&x.conn, window)?;
+                } else {
+                    pointer::uncloak(&x.conn, window, device)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A master pointer we've hidden with a blank cursor.
+#[derive(Clone, Debug)]
+struct Cloaked {
+    /// The windows we gave the blank cursor to.
+    windows: Vec<x::Window>,
+    /// Whether those are windows of our own, from `pointer::cover`.
+    covered: bool,
+    /// Where the pointer was, if we're watching for jitter.
+    at: Option<Position>,
+}
+
+
+/// Works out why we might be changing state after `event`.
+fn reason_of(event: &Event) -> Reason {
+    match event {
+        Event::Input(
+            xinput::Event::RawKeyPress(_)
+            | xinput::Event::RawKeyRelease(_)
+            | xinput::Event::DeviceKeyPress(_)
+            | xinput::Event::DeviceKeyRelease(_)
+        ) => Reason::Key,
+        Event::Input(_) => Reason::Motion,
+        Event::X(x::Event::KeyPress(_)) => Reason::Command,
+        Event::X(
+            x::Event::PropertyNotify(_)
+            | x::Event::MapNotify(_)
+            | x::Event::UnmapNotify(_)
+            | x::Event::DestroyNotify(_)
+        ) => Reason::Focus,
+        Event::XFixes(xfixes::Event::CursorNotify(_)) => Reason::Cursor,
+        _ => Reason::Other,
+    }
+}
+
+/// Works out what the user did, as far as `Policy::admits` is concerned, if
+/// `event` is input at all. `key` is the key it's about, if any, and `repeat`
+/// says whether that's only auto-repeat.
+fn action(
+    event: &Event,
+    key: Option<KeyChange>,
+    repeat: bool,
+) -> Option<Action> {
+    let Event::Input(e) = event else {
+        return None;
+    };
+    if let Some(key) = key {
+        let pressed = key.pressed;
+        return Some(Action::Key { pressed, repeat });
+    }
+    match e {
+        _ if is_scroll(e) => Some(Action::Scroll),
+        xinput::Event::RawMotion(_)
+        | xinput::Event::DeviceValuator(_)
+        | xinput::Event::DeviceMotionNotify(_)
+        | xinput::Event::ProximityIn(_) => Some(Action::Motion),
+        xinput::Event::RawButtonPress(_)
+        | xinput::Event::DeviceButtonPress(_)
+        | xinput::Event::DeviceButtonRelease(_) => Some(Action::Button),
+        _ => None,
+    }
+}
@@ -0,0 +1,99 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Staying out of the way while the monitors are powered down.
+//!
+//! When DPMS turns the monitors off, we show the pointer, so that it's where
+//! the user left it when they come back, and leave it alone until they do.
+//!
+//! DPMS doesn't tell anyone when it changes the power level, so we have to
+//! ask. It only powers down after the user has been idle for one of its
+//! timeouts, so we ask once they've been idle that long; and once it has
+//! powered down, any input wakes the monitors back up, so we ask again then.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use xcb::{dpms, Connection, Extension};
+
+/// Keeps track of whether DPMS has powered down the monitors.
+pub struct Dpms {
+    /// Whether the server can do DPMS at all.
+    capable: bool,
+    /// Whether the monitors were powered down, last we checked.
+    blanked: bool,
+    /// The shortest idle time after which DPMS powers anything down, if it's
+    /// enabled.
+    timeout: Option<Duration>,
+    /// When we last checked.
+    checked_at: Instant,
+}
+
+impl Dpms {
+    /// Checks whether the server can do DPMS, and if so, what it's up to.
+    pub fn new(conn: &Connection) -> Result<Self> {
+        let mut dpms = Self {
+            capable: false,
+            blanked: false,
+            timeout: None,
+            checked_at: Instant::now(),
+        };
+        if !conn.active_extensions().any(|e| e == Extension::Dpms) {
+            return Ok(dpms);
+        }
+        conn.wait_for_reply(conn.send_request(&dpms::GetVersion {
+            client_major_version: 1,
+            client_minor_version: 1,
+        }))?;
+        dpms.capable = conn
+            .wait_for_reply(conn.send_request(&dpms::Capable {}))?
+            .capable();
+        if dpms.capable {
+            dpms.refresh(conn)?;
+        }
+        Ok(dpms)
+    }
+
+    /// Whether the monitors were powered down, last we checked.
+    pub fn blanked(&self) -> bool {
+        self.blanked
+    }
+
+    /// Works out when it's next worth checking whether the monitors have
+    /// powered down, given that the user's last input was at `last_input`.
+    /// There's no need while they're already powered down, since we check
+    /// again on the next input instead.
+    pub fn next_check(&self, last_input: Instant) -> Option<Instant> {
+        if self.blanked {
+            return None;
+        }
+        self.timeout.map(|t| last_input.max(self.checked_at) + t)
+    }
+
+    /// Asks the server whether the monitors are powered down, and when they
+    /// might be. The user can change the timeouts whenever they like, so we
+    /// ask for those too.
+    pub fn refresh(&mut self, conn: &Connection) -> Result<()> {
+        if !self.capable {
+            return Ok(());
+        }
+        let info = conn.wait_for_reply(conn.send_request(&dpms::Info {}))?;
+        let timeouts =
+            conn.wait_for_reply(conn.send_request(&dpms::GetTimeouts {}))?;
+        // A timeout of zero means that level is never used.
+        self.timeout = [
+            timeouts.standby_timeout(),
+            timeouts.suspend_timeout(),
+            timeouts.off_timeout(),
+        ]
+        .into_iter()
+        .filter(|&t| t != 0)
+        .min()
+        .filter(|_| info.state())
+        .map(|t| Duration::from_secs(t.into()));
+        self.blanked = info.state() && info.power_level() != dpms::DpmsMode::On;
+        self.checked_at = Instant::now();
+        Ok(())
+    }
+}
@@ -0,0 +1,512 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Listening to input straight from the kernel, by way of evdev, for when
+//! snooping through X misbehaves, or misses input to Wayland clients under
+//! Xwayland. We still hide the pointer through X.
+//!
+//! Each input device has a file in /dev/input that anyone who can read it can
+//! hear everything from, whoever it's going to. Usually that's root and the
+//! `input` group, so reading them means being in that group, with all that
+//! implies. New devices turn up as new files there, which we watch for with
+//! inotify; devices that go away stop being readable.
+//!
+//! A `Reader` boils what the devices say down to `Activity`, which is all we
+//! need to know, and which doesn't need X to make sense of. On an X server,
+//! `Evdev` passes that off as XInput 1 device events, as with RECORD. X
+//! drivers name their devices after the kernel's, so we credit each event to
+//! the X device with the same name, if there is one, so that device settings
+//! still apply. Otherwise it goes down to the core pointer or keyboard.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use log::debug;
+use xcb::{x, Connection, Event};
+
+use crate::input::{
+    self, BUTTON_PRESS, BUTTON_RELEASE, CORE_KEYBOARD, CORE_POINTER,
+    EVENT_SIZE, KEY_PRESS, KEY_RELEASE, MOTION_NOTIFY,
+};
+
+const DIR: &str = "/dev/input";
+
+/// Event types and codes, from linux/input-event-codes.h.
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0x00;
+const BTN_MISC: u16 = 0x100;
+const BTN_MOUSE: u16 = 0x110;
+const BTN_JOYSTICK: u16 = 0x120;
+const KEY_OK: u16 = 0x160;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const ABS_MT_POSITION_X: u16 = 0x35;
+const ABS_MT_POSITION_Y: u16 = 0x36;
+
+/// How long device names can be, for reading them.
+const NAME_LEN: usize = 256;
+
+/// EVIOCGNAME(NAME_LEN), which reads a device's name. There's no libc
+/// constant for this, since the length is part of it.
+const EVIOCGNAME: libc::c_ulong = (2 << 30)
+    | ((NAME_LEN as libc::c_ulong) << 16)
+    | ((b'E' as libc::c_ulong) << 8)
+    | 0x06;
+
+/// X keycodes are evdev ones shifted up by this much, at least with the
+/// evdev and libinput drivers, which is nearly everyone.
+const KEYCODE_OFFSET: u16 = 8;
+
+/// An input device we're reading from.
+struct Device {
+    file: File,
+    path: PathBuf,
+    name: String,
+    /// Whether it's moved since the last batch of events, which can mention
+    /// several axes.
+    moved: bool,
+}
+
+/// Something the user did on an input device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Activity {
+    /// A key going down, repeating, or coming back up. `code` is the
+    /// kernel's keycode, and `time` is in milliseconds, from whenever.
+    Key {
+        code: u16,
+        pressed: bool,
+        repeat: bool,
+        time: u32,
+    },
+    /// A mouse button going down or coming back up, numbered the way X does.
+    Button { button: u8, pressed: bool },
+    /// A turn of a wheel, numbered as X's wheel buttons, 4 to 7.
+    Scroll(u8),
+    /// The pointer moving, or a finger or pen on a touchpad, touchscreen, or
+    /// tablet.
+    Motion,
+}
+
+/// Reads input from every device in /dev/input we're allowed to.
+pub struct Reader {
+    devices: Vec<Device>,
+    /// Tells us when files come and go in /dev/input.
+    inotify: OwnedFd,
+    /// Becomes readable when any of the above does, so that there's only one
+    /// file descriptor to wait on, however many devices there are.
+    epoll: OwnedFd,
+}
+
+impl Reader {
+    /// Opens every input device we can read, and starts watching for more.
+    pub fn new() -> Result<Self> {
+        // Safety: neither of these have memory safety requirements, and we
+        // take ownership of the descriptors they return, if they do.
+        let (epoll, inotify) = unsafe {
+            let epoll = libc::epoll_create1(libc::EPOLL_CLOEXEC);
+            if epoll < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            let epoll = OwnedFd::from_raw_fd(epoll);
+            let inotify =
+                libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if inotify < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            (epoll, OwnedFd::from_raw_fd(inotify))
+        };
+        let dir = CString::new(DIR)?;
+        // Safety: `dir` is a valid C string for the duration of the call.
+        // Files are created before their permissions are set, so we keep an
+        // eye out for both.
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                inotify.as_raw_fd(),
+                dir.as_ptr(),
+                libc::IN_CREATE | libc::IN_ATTRIB,
+            )
+        };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        let mut reader = Self {
+            devices: vec![],
+            inotify,
+            epoll,
+        };
+        reader.watch(reader.inotify.as_raw_fd())?;
+        let denied = reader.scan()?;
+        if reader.devices.is_empty() {
+            if denied {
+                bail!(
+                    "not allowed to read any input devices in {DIR} (are you \
+                     in the input group?)"
+                );
+            }
+            bail!("can't find any input devices in {DIR}");
+        }
+        debug!(devices = reader.devices.len(); "reading input with evdev");
+        Ok(reader)
+    }
+
+    /// Adds `fd` to what `epoll` waits on.
+    fn watch(&self, fd: RawFd) -> Result<()> {
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: 0,
+        };
+        // Safety: epoll_ctl only reads `event`, during the call.
+        let rc = unsafe {
+            libc::epoll_ctl(
+                self.epoll.as_raw_fd(),
+                libc::EPOLL_CTL_ADD,
+                fd,
+                &mut event,
+            )
+        };
+        if rc < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Opens any devices in /dev/input we haven't already. Returns whether
+    /// we were refused any.
+    fn scan(&mut self) -> Result<bool> {
+        let mut denied = false;
+        for entry in std::fs::read_dir(DIR)? {
+            let path = entry?.path();
+            let is_device = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("event"));
+            if !is_device || self.devices.iter().any(|d| d.path == path) {
+                continue;
+            }
+            match open(&path) {
+                Ok(device) => {
+                    debug!(
+                        path:? = device.path, name = device.name;
+                        "reading input device"
+                    );
+                    self.watch(device.file.as_raw_fd())?;
+                    self.devices.push(device);
+                }
+                Err(e) => {
+                    denied |= e.kind() == ErrorKind::PermissionDenied;
+                    debug!(path:?; "can't read input device: {e}");
+                }
+            }
+        }
+        Ok(denied)
+    }
+
+    /// Names the devices we're reading.
+    pub fn devices(&self) -> Vec<String> {
+        self.devices.iter().map(|d| d.name.clone()).collect()
+    }
+
+    /// Collects whatever's happened since last time, without blocking, and
+    /// passes it to `f` along with the name of the device it happened on.
+    pub fn poll(&mut self, mut f: impl FnMut(&str, Activity)) -> Result<()> {
+        if self.changed()? {
+            self.scan()?;
+        }
+        let mut gone = vec![];
+        for (i, device) in self.devices.iter_mut().enumerate() {
+            let read = match read(&mut device.file) {
+                Ok(read) => read,
+                Err(e) => {
+                    debug!(
+                        name = device.name;
+                        "input device has gone away: {e}"
+                    );
+                    gone.push(i);
+                    continue;
+                }
+            };
+            for event in read {
+                if let Some(activity) = translate(device, &event) {
+                    f(&device.name, activity);
+                }
+            }
+        }
+        // Closing a device's file takes it out of the epoll set too.
+        for i in gone.into_iter().rev() {
+            self.devices.remove(i);
+        }
+        Ok(())
+    }
+
+    /// Collects everything inotify has for us, and says whether there was
+    /// anything.
+    fn changed(&self) -> Result<bool> {
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            // Safety: we're asking read for no more bytes than buf holds.
+            let n = unsafe {
+                libc::read(
+                    self.inotify.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == ErrorKind::WouldBlock {
+                    return Ok(changed);
+                }
+                return Err(err.into());
+            }
+            changed = true;
+        }
+    }
+}
+
+impl AsRawFd for Reader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.epoll.as_raw_fd()
+    }
+}
+
+/// Reads input from every device we're allowed to, on behalf of an X server.
+pub(crate) struct Evdev {
+    reader: Reader,
+    /// The root window, for events to be on.
+    root: x::Window,
+    /// Where XInput's events start, for passing events off as its own.
+    xinput_base: u8,
+}
+
+impl Evdev {
+    /// Opens every input device we can read, and starts watching for more.
+    /// `root` is the root window of the default screen on `conn`.
+    pub fn new(conn: &Connection, root: x::Window) -> Result<Self> {
+        Ok(Self {
+            xinput_base: input::first_event(conn)?,
+            reader: Reader::new()?,
+            root,
+        })
+    }
+
+    /// Collects whatever's happened since last time, without blocking, as
+    /// XInput 1 events that `conn` can make sense of. `devices` has the names
+    /// of the X input devices, by ID, for working out which one each event
+    /// belongs to.
+    pub fn poll(
+        &mut self,
+        conn: &Connection,
+        devices: &HashMap<u16, String>,
+    ) -> Result<Vec<Event>> {
+        let mut activity = vec![];
+        self.reader.poll(|name, what| {
+            // Go by the X device with the same name, if any.
+            let id = devices
+                .iter()
+                .find(|(_, n)| *n == name)
+                .and_then(|(&id, _)| u8::try_from(id).ok());
+            activity.push((id, what));
+        })?;
+        let mut events = vec![];
+        for (id, what) in activity {
+            let (kind, detail, time) = match what {
+                Activity::Key {
+                    code,
+                    pressed,
+                    time,
+                    ..
+                } => {
+                    let Ok(keycode) = u8::try_from(code + KEYCODE_OFFSET)
+                    else {
+                        continue;
+                    };
+                    // X sends a repeating key as another press.
+                    let kind = if pressed { KEY_PRESS } else { KEY_RELEASE };
+                    (kind, keycode, time)
+                }
+                Activity::Button {
+                    button,
+                    pressed: true,
+                } => (BUTTON_PRESS, button, 0),
+                Activity::Button { button, .. } => (BUTTON_RELEASE, button, 0),
+                Activity::Scroll(button) => (BUTTON_PRESS, button, 0),
+                Activity::Motion => (MOTION_NOTIFY, 0, 0),
+            };
+            // Key events carry the modifiers, which only X knows for sure,
+            // although it may not have caught up with the very latest keys.
+            let key = kind == KEY_PRESS || kind == KEY_RELEASE;
+            let state = if key {
+                conn.wait_for_reply(conn.send_request(&x::QueryPointer {
+                    window: self.root,
+                }))?
+                .mask()
+                .bits() as u16
+            } else {
+                0
+            };
+            let core = if key { CORE_KEYBOARD } else { CORE_POINTER };
+            let wire = wire(kind, detail, time, self.root, state);
+            events.extend(input::device_event(
+                conn,
+                self.xinput_base,
+                &wire,
+                id.unwrap_or(core),
+            ));
+        }
+        Ok(events)
+    }
+}
+
+impl AsRawFd for Evdev {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
+
+/// Opens the input device at `path`, and finds out what it's called.
+fn open(path: &Path) -> std::io::Result<Device> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)?;
+    let mut name = [0u8; NAME_LEN];
+    // Safety: the kernel writes no more than NAME_LEN bytes to `name`.
+    let n = unsafe {
+        libc::ioctl(file.as_raw_fd(), EVIOCGNAME as _, name.as_mut_ptr())
+    };
+    if n < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let name = name.split(|&b| b == 0).next().unwrap_or_default();
+    Ok(Device {
+        file,
+        path: path.to_owned(),
+        name: String::from_utf8_lossy(name).into_owned(),
+        moved: false,
+    })
+}
+
+/// Reads everything `file` has for us, without blocking.
+fn read(file: &mut File) -> std::io::Result<Vec<libc::input_event>> {
+    let size = std::mem::size_of::<libc::input_event>();
+    let mut buf = vec![0u8; 64 * size];
+    let mut events = vec![];
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(events),
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            return Ok(events);
+        }
+        // Safety: the kernel only hands out whole events, and read_unaligned
+        // copes with buf being unaligned.
+        events.extend(buf[..n].chunks_exact(size).map(|chunk| unsafe {
+            std::ptr::read_unaligned(chunk.as_ptr() as *const libc::input_event)
+        }));
+    }
+}
+
+/// Works out what `event`, from `device`, means, if it's something we care
+/// about. Movement along several axes at once comes out as one `Motion`, at
+/// the end of the batch.
+fn translate(
+    device: &mut Device,
+    event: &libc::input_event,
+) -> Option<Activity> {
+    let (code, value) = (event.code, event.value);
+    match event.type_ {
+        EV_SYN if code == SYN_REPORT && device.moved => {
+            device.moved = false;
+            Some(Activity::Motion)
+        }
+        // Mouse buttons, numbered the way X does.
+        EV_KEY if (BTN_MOUSE..BTN_JOYSTICK).contains(&code) => {
+            let button = match code - BTN_MOUSE {
+                0 => 1,
+                1 => 3,
+                2 => 2,
+                n => n as u8 + 5,
+            };
+            match value {
+                0 | 1 => Some(Activity::Button {
+                    button,
+                    pressed: value == 1,
+                }),
+                _ => None,
+            }
+        }
+        // Other buttons, from joysticks, touchscreens and tablets, move the
+        // pointer (or don't) in their own right.
+        EV_KEY if (BTN_MISC..KEY_OK).contains(&code) => None,
+        // Two means the key is repeating.
+        EV_KEY => Some(Activity::Key {
+            code,
+            pressed: value != 0,
+            repeat: value == 2,
+            time: (event.time.tv_sec as u64 * 1000
+                + event.time.tv_usec as u64 / 1000) as u32,
+        }),
+        EV_REL if code == REL_WHEEL && value != 0 => {
+            Some(Activity::Scroll(if value > 0 { 4 } else { 5 }))
+        }
+        EV_REL if code == REL_HWHEEL && value != 0 => {
+            Some(Activity::Scroll(if value > 0 { 7 } else { 6 }))
+        }
+        EV_REL if code == REL_X || code == REL_Y => {
+            device.moved = true;
+            None
+        }
+        EV_ABS
+            if matches!(
+                code,
+                ABS_X | ABS_Y | ABS_MT_POSITION_X | ABS_MT_POSITION_Y
+            ) =>
+        {
+            device.moved = true;
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Lays out a core input event of type `kind` on `root`, as it would come
+/// over the wire.
+fn wire(
+    kind: u8,
+    detail: u8,
+    time: x::Timestamp,
+    root: x::Window,
+    state: u16,
+) -> [u8; EVENT_SIZE] {
+    use xcb::Xid;
+
+    let mut wire = [0u8; EVENT_SIZE];
+    let root = root.resource_id().to_ne_bytes();
+    wire[0] = kind;
+    wire[1] = detail;
+    wire[4..8].copy_from_slice(&time.to_ne_bytes());
+    // The root window, and the event window, which is also the root.
+    wire[8..12].copy_from_slice(&root);
+    wire[12..16].copy_from_slice(&root);
+    wire[28..30].copy_from_slice(&state.to_ne_bytes());
+    // same_screen
+    wire[30] = 1;
+    wire
+}
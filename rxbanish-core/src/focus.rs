@@ -0,0 +1,445 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Keeping track of which window has the focus.
+//!
+//! X itself doesn't really have a notion of "the focused application," but any
+//! EWMH-compliant window manager will maintain `_NET_ACTIVE_WINDOW` on the root
+//! window, and that's close enough for our purposes.
+//!
+//! With several screens, each has its own root window (and probably its own
+//! window manager instance), and so its own idea of the active window. We go
+//! with whichever one changed most recently, on the theory that that's where
+//! the user has been clicking.
+//!
+//! We also keep an eye on the focused window's `_NET_WM_STATE`, to notice when
+//! it goes fullscreen, and note its `_NET_WM_WINDOW_TYPE`, to tell dialogs and
+//! the like from ordinary windows, and its title, which changes as the user
+//! moves between documents or tabs.
+
+use anyhow::Result;
+use xcb::{x, Connection, Xid};
+
+use crate::pointer::Position;
+use crate::WindowType;
+
+/// The two halves of a window's `WM_CLASS` property.
+#[derive(Clone, Debug)]
+pub struct WmClass {
+    pub instance: String,
+    pub class: String,
+}
+
+impl WmClass {
+    /// Checks whether `name` matches either half of the class, ignoring case.
+    /// People are generally not consistent about capitalizing these, and nobody
+    /// wants to look it up in xprop.
+    pub fn matches(&self, name: &str) -> bool {
+        self.instance.eq_ignore_ascii_case(name)
+            || self.class.eq_ignore_ascii_case(name)
+    }
+}
+
+/// Watches the root windows to keep track of the active window.
+pub struct FocusTracker {
+    roots: Vec<x::Window>,
+    net_active_window: x::Atom,
+    net_wm_state: x::Atom,
+    net_wm_state_fullscreen: x::Atom,
+    net_wm_window_type: x::Atom,
+    net_wm_name: x::Atom,
+    utf8_string: x::Atom,
+    /// The atoms for each window type.
+    window_types: Vec<(x::Atom, WindowType)>,
+    active: Option<x::Window>,
+    class: Option<WmClass>,
+    title: Option<String>,
+    fullscreen: bool,
+    window_type: Option<WindowType>,
+}
+
+impl FocusTracker {
+    /// Starts watching `roots` for changes to the active window, and finds out
+    /// what's focused on the default screen, whose root is `default_root`,
+    /// right now. The caller is responsible for selecting PropertyChange on
+    /// `roots`, so that we hear about changes.
+    pub fn new(
+        conn: &Connection,
+        roots: &[x::Window],
+        default_root: x::Window,
+    ) -> Result<Self> {
+        let intern = |name: &[u8]| -> Result<x::Atom> {
+            Ok(conn.wait_for_reply(conn.send_request(&x::InternAtom {
+                only_if_exists: false,
+                name,
+            }))?.atom())
+        };
+        let net_active_window = intern(b"_NET_ACTIVE_WINDOW")?;
+        let net_wm_state = intern(b"_NET_WM_STATE")?;
+        let net_wm_state_fullscreen = intern(b"_NET_WM_STATE_FULLSCREEN")?;
+        let net_wm_window_type = intern(b"_NET_WM_WINDOW_TYPE")?;
+        let net_wm_name = intern(b"_NET_WM_NAME")?;
+        let utf8_string = intern(b"UTF8_STRING")?;
+        let window_types = WindowType::ALL
+            .into_iter()
+            .map(|t| Ok((intern(t.atom_name())?, t)))
+            .collect::<Result<_>>()?;
+
+        let mut tracker = Self {
+            roots: roots.to_vec(),
+            net_active_window,
+            net_wm_state,
+            net_wm_state_fullscreen,
+            net_wm_window_type,
+            net_wm_name,
+            utf8_string,
+            window_types,
+            active: None,
+            class: None,
+            title: None,
+            fullscreen: false,
+            window_type: None,
+        };
+        tracker.refresh(conn, default_root)?;
+        Ok(tracker)
+    }
+
+    /// The focused window, if there is one.
+    pub fn active(&self) -> Option<x::Window> {
+        self.active
+    }
+
+    /// The class of the focused window, if there is a focused window and it
+    /// has a class.
+    pub fn class(&self) -> Option<&WmClass> {
+        self.class.as_ref()
+    }
+
+    /// The title of the focused window, if there is a focused window and it
+    /// has a title.
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Whether the focused window is fullscreen.
+    pub fn fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    /// What kind of window the focused window is, if there is one.
+    pub fn window_type(&self) -> Option<WindowType> {
+        self.window_type
+    }
+
+    /// Checks whether `pos` is over the focused window. If we don't know what's
+    /// focused, we can't say it isn't, so that counts too.
+    pub fn under(&self, conn: &Connection, pos: Position) -> Result<bool> {
+        let Some(window) = self.active else {
+            return Ok(true);
+        };
+        let geometry = conn.wait_for_reply(conn.send_request(&x::GetGeometry {
+            drawable: x::Drawable::Window(window),
+        }));
+        let origin =
+            conn.wait_for_reply(conn.send_request(&x::TranslateCoordinates {
+                src_window: window,
+                dst_window: pos.root,
+                src_x: 0,
+                src_y: 0,
+            }));
+        // The window may have gone away since it was focused.
+        let (geometry, origin) = match (geometry, origin) {
+            (Ok(g), Ok(o)) => (g, o),
+            (Err(e), _) | (_, Err(e)) if is_bad_window(&e) => return Ok(true),
+            (Err(e), _) | (_, Err(e)) => return Err(e.into()),
+        };
+        // Windows on another screen can't be under the pointer.
+        if !origin.same_screen() {
+            return Ok(false);
+        }
+        let (x, y) = (i32::from(pos.x), i32::from(pos.y));
+        let left = i32::from(origin.dst_x());
+        let top = i32::from(origin.dst_y());
+        Ok((left..left + i32::from(geometry.width())).contains(&x)
+            && (top..top + i32::from(geometry.height())).contains(&y))
+    }
+
+    /// Updates our idea of the focused window if `event` is telling us that it
+    /// has changed, or that its fullscreen state, type, or title has. Other
+    /// property changes are ignored. Returns whether anything changed.
+    pub fn handle_property_notify(
+        &mut self,
+        conn: &Connection,
+        event: &x::PropertyNotifyEvent,
+    ) -> Result<bool> {
+        let window = event.window();
+        let atom = event.atom();
+        if self.roots.contains(&window) && atom == self.net_active_window {
+            return self.refresh(conn, window);
+        }
+        if Some(window) != self.active {
+            return Ok(false);
+        }
+        if atom == self.net_wm_state {
+            self.fullscreen = self.get_fullscreen(conn, window)?;
+        } else if atom == self.net_wm_window_type {
+            self.window_type = self.get_window_type(conn, window)?;
+        } else if atom == self.net_wm_name || atom == x::ATOM_WM_NAME {
+            self.title = self.get_title(conn, window)?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Reads the active window from `root`. Returns whether it's changed.
+    fn refresh(&mut self, conn: &Connection, root: x::Window) -> Result<bool> {
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window: root,
+            property: self.net_active_window,
+            r#type: x::ATOM_WINDOW,
+            long_offset: 0,
+            long_length: 1,
+        }))?;
+        let active = match reply.value::<x::Window>().first() {
+            Some(&window) if !window.is_none() => Some(window),
+            _ => None,
+        };
+        if active == self.active {
+            return Ok(false);
+        }
+
+        // Stop listening to the old window, and start listening to the new
+        // one, so we hear about its state changing. Either may have gone away
+        // already, which is fine.
+        if let Some(old) = self.active {
+            ignore_bad_window(conn.send_and_check_request(
+                &x::ChangeWindowAttributes {
+                    window: old,
+                    value_list: &[x::Cw::EventMask(x::EventMask::empty())],
+                },
+            ))?;
+        }
+        self.active = active;
+        (self.class, self.fullscreen) = match active {
+            Some(window) => {
+                ignore_bad_window(conn.send_and_check_request(
+                    &x::ChangeWindowAttributes {
+                        window,
+                        value_list: &[x::Cw::EventMask(
+                            x::EventMask::PROPERTY_CHANGE,
+                        )],
+                    },
+                ))?;
+                (
+                    get_wm_class(conn, window)?,
+                    self.get_fullscreen(conn, window)?,
+                )
+            }
+            None => (None, false),
+        };
+        (self.window_type, self.title) = match active {
+            Some(window) => (
+                self.get_window_type(conn, window)?,
+                self.get_title(conn, window)?,
+            ),
+            None => (None, None),
+        };
+        Ok(true)
+    }
+
+    /// Checks whether `window` has `_NET_WM_STATE_FULLSCREEN` set.
+    fn get_fullscreen(
+        &self,
+        conn: &Connection,
+        window: x::Window,
+    ) -> Result<bool> {
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.net_wm_state,
+            r#type: x::ATOM_ATOM,
+            long_offset: 0,
+            long_length: 64,
+        }));
+        match reply {
+            Ok(reply) => Ok(reply
+                .value::<x::Atom>()
+                .contains(&self.net_wm_state_fullscreen)),
+            Err(xcb::Error::Protocol(xcb::ProtocolError::X(
+                x::Error::Window(_),
+                _,
+            ))) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fetches the title of `window`, preferring EWMH's UTF-8 `_NET_WM_NAME`
+    /// to the older `WM_NAME`, which is supposed to be Latin-1 but often
+    /// isn't.
+    fn get_title(
+        &self,
+        conn: &Connection,
+        window: x::Window,
+    ) -> Result<Option<String>> {
+        for (property, r#type) in [
+            (self.net_wm_name, self.utf8_string),
+            (x::ATOM_WM_NAME, x::ATOM_ANY),
+        ] {
+            let reply =
+                conn.wait_for_reply(conn.send_request(&x::GetProperty {
+                    delete: false,
+                    window,
+                    property,
+                    r#type,
+                    long_offset: 0,
+                    long_length: 256,
+                }));
+            match reply {
+                Ok(reply) if !reply.value::<u8>().is_empty() => {
+                    let title = String::from_utf8_lossy(reply.value());
+                    return Ok(Some(title.into_owned()));
+                }
+                Ok(_) => (),
+                Err(e) if is_bad_window(&e) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Works out what kind of window `window` is, or `None` if it's gone.
+    /// EWMH says to go with the first type listed that we understand, and
+    /// that windows without one are normal, unless they're transient for
+    /// another window, in which case they're dialogs.
+    fn get_window_type(
+        &self,
+        conn: &Connection,
+        window: x::Window,
+    ) -> Result<Option<WindowType>> {
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.net_wm_window_type,
+            r#type: x::ATOM_ATOM,
+            long_offset: 0,
+            long_length: 16,
+        }));
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) if is_bad_window(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let listed = reply.value::<x::Atom>().iter().find_map(|atom| {
+            self.window_types
+                .iter()
+                .find(|&&(a, _)| a == *atom)
+                .map(|&(_, t)| t)
+        });
+        if listed.is_some() {
+            return Ok(listed);
+        }
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: x::ATOM_WM_TRANSIENT_FOR,
+            r#type: x::ATOM_WINDOW,
+            long_offset: 0,
+            long_length: 1,
+        }));
+        match reply {
+            Ok(reply) if !reply.value::<x::Window>().is_empty() => {
+                Ok(Some(WindowType::Dialog))
+            }
+            Ok(_) => Ok(Some(WindowType::Normal)),
+            Err(e) if is_bad_window(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Treats a BadWindow error as success, for requests about windows that may
+/// have been destroyed behind our backs.
+pub fn ignore_bad_window(result: xcb::ProtocolResult<()>) -> Result<()> {
+    match result {
+        Err(xcb::ProtocolError::X(x::Error::Window(_), _)) => Ok(()),
+        r => Ok(r?),
+    }
+}
+
+/// Checks whether `err` is the server telling us a window doesn't exist.
+fn is_bad_window(err: &xcb::Error) -> bool {
+    matches!(
+        err,
+        xcb::Error::Protocol(xcb::ProtocolError::X(
+            x::Error::Window(_) | x::Error::Drawable(_),
+            _,
+        ))
+    )
+}
+
+/// Finds the class of the application whose window the pointer is over on
+/// the screen with root window `root`, if it's over one that has a class.
+/// Window managers put their own frames around windows, and applications
+/// can have windows inside windows, so we go down through the windows under
+/// the pointer until we reach one with a class.
+pub fn class_under_pointer(
+    conn: &Connection,
+    root: x::Window,
+) -> Result<Option<WmClass>> {
+    let mut window = root;
+    loop {
+        let reply = conn.wait_for_reply(conn.send_request(&x::QueryPointer {
+            window,
+        }));
+        let child = match reply {
+            Ok(reply) if reply.same_screen() => reply.child(),
+            Ok(_) => return Ok(None),
+            Err(e) if is_bad_window(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if child.is_none() {
+            return Ok(None);
+        }
+        if let Some(class) = get_wm_class(conn, child)? {
+            return Ok(Some(class));
+        }
+        window = child;
+    }
+}
+
+/// Fetches the `WM_CLASS` of `window`, if it has one.
+pub fn get_wm_class(
+    conn: &Connection,
+    window: x::Window,
+) -> Result<Option<WmClass>> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+        delete: false,
+        window,
+        property: x::ATOM_WM_CLASS,
+        r#type: x::ATOM_STRING,
+        long_offset: 0,
+        long_length: 256,
+    }));
+    let reply = match reply {
+        Ok(reply) => reply,
+        // The window can go away between the window manager telling us about
+        // it and us asking about it. That's fine, it's just not focused
+        // anymore.
+        Err(xcb::Error::Protocol(xcb::ProtocolError::X(
+            x::Error::Window(_),
+            _,
+        ))) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    // The property is two NUL-terminated strings back to back.
+    let mut parts = reply.value::<u8>().split(|&b| b == 0);
+    let mut next = || parts.next().map(|s| String::from_utf8_lossy(s).into());
+    match (next(), next()) {
+        (Some(instance), Some(class)) => Ok(Some(WmClass { instance, class })),
+        _ => Ok(None),
+    }
+}
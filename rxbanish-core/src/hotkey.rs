@@ -0,0 +1,206 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Global hotkeys, for controlling rxbanish from the keyboard.
+//!
+//! We grab hotkeys on the root windows, which means the server sends them to
+//! us rather than to whichever window has focus. Grabs are by keycode and exact
+//! modifier state, so we have to redo them when the keyboard mapping changes,
+//! and grab each key several times over so that Caps Lock and Num Lock don't
+//! get in the way.
+
+use std::fmt;
+
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use xcb::{x, Connection};
+use xkeysym::Keysym;
+
+use crate::keysym::{self, Keymap};
+
+/// Modifiers that people tend to leave switched on, and that shouldn't stop a
+/// hotkey from working. Mod2 is Num Lock on just about every keyboard.
+const LOCKS: x::ModMask = x::ModMask::LOCK.union(x::ModMask::N2);
+
+/// A key plus modifiers, like "super+F9".
+#[derive(Clone, Debug)]
+pub struct Hotkey {
+    mods: x::ModMask,
+    sym: Keysym,
+    /// What the user called it, for messages.
+    name: String,
+}
+
+/// Parses a hotkey given as modifier names and a keysym name, separated by
+/// `+`, e.g. "super+F9" or "ctrl+alt+h".
+pub fn parse(text: &str) -> Result<Hotkey> {
+    let mut parts: Vec<&str> = text.split('+').collect();
+    let key = parts.pop().filter(|k| !k.is_empty());
+    let Some(key) = key else {
+        bail!("hotkey {text:?} doesn't name a key");
+    };
+
+    let mut mods = x::ModMask::empty();
+    for part in parts {
+        mods |= match part.to_ascii_lowercase().as_str() {
+            "shift" => x::ModMask::SHIFT,
+            "ctrl" | "control" => x::ModMask::CONTROL,
+            "alt" | "mod1" => x::ModMask::N1,
+            "mod2" => x::ModMask::N2,
+            "mod3" => x::ModMask::N3,
+            "super" | "mod4" => x::ModMask::N4,
+            "mod5" => x::ModMask::N5,
+            _ => return Err(anyhow!("unknown modifier {part:?} in {text:?}")),
+        };
+    }
+
+    Ok(Hotkey {
+        mods,
+        sym: keysym::parse(key)?,
+        name: text.to_string(),
+    })
+}
+
+impl Hotkey {
+    /// Checks whether a key event for `keycode`, with modifiers `state`, is
+    /// this hotkey.
+    pub fn matches(
+        &self,
+        keymap: &Keymap,
+        keycode: u8,
+        state: x::KeyButMask,
+    ) -> bool {
+        // The low bits of the state are the modifiers; the rest are buttons,
+        // which we don't care about.
+        let mods = x::ModMask::from_bits_truncate(state.bits() & 0xff);
+        mods.difference(LOCKS) == self.mods.difference(LOCKS)
+            && keymap.keysyms(keycode).any(|sym| sym == self.sym)
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Grabs `hotkeys` on each of `roots`, first letting go of anything we grabbed
+/// before.
+///
+/// A hotkey that some other program has already grabbed can't be ours too.
+/// That's worth a warning, but not worth giving up over.
+pub fn grab<'a>(
+    conn: &Connection,
+    roots: &[x::Window],
+    keymap: &Keymap,
+    hotkeys: impl IntoIterator<Item = &'a Hotkey>,
+) -> Result<()> {
+    for &root in roots {
+        conn.send_and_check_request(&x::UngrabKey {
+            key: x::GRAB_ANY,
+            grab_window: root,
+            modifiers: x::ModMask::ANY,
+        })?;
+    }
+
+    for hotkey in hotkeys {
+        let keycodes: Vec<u8> = keymap.keycodes(hotkey.sym).collect();
+        if keycodes.is_empty() {
+            warn!("no key on this keyboard produces {hotkey}");
+            continue;
+        }
+        let lock_combos = [
+            x::ModMask::empty(),
+            x::ModMask::LOCK,
+            x::ModMask::N2,
+            LOCKS,
+        ];
+        let mut taken = false;
+        for &root in roots {
+            for &key in &keycodes {
+                for locks in lock_combos {
+                    let r = conn.send_and_check_request(&x::GrabKey {
+                        owner_events: true,
+                        grab_window: root,
+                        modifiers: hotkey.mods | locks,
+                        key,
+                        pointer_mode: x::GrabMode::Async,
+                        keyboard_mode: x::GrabMode::Async,
+                    });
+                    match r {
+                        Ok(()) => (),
+                        Err(xcb::ProtocolError::X(x::Error::Access(_), _)) => {
+                            taken = true;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+        if taken {
+            warn!("can't grab {hotkey}; is something else using it?");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use xcb::x;
+    use xkeysym::{key, Keysym};
+
+    #[test]
+    fn key_alone() {
+        let hotkey = parse("F9").unwrap();
+        assert_eq!(hotkey.mods, x::ModMask::empty());
+        assert_eq!(hotkey.sym, Keysym::new(key::F9));
+        assert_eq!(hotkey.to_string(), "F9");
+    }
+
+    #[test]
+    fn modifiers() {
+        let hotkey = parse("ctrl+alt+h").unwrap();
+        assert_eq!(hotkey.mods, x::ModMask::CONTROL | x::ModMask::N1);
+        assert_eq!(hotkey.sym, Keysym::new(key::h));
+
+        let hotkey = parse("shift+super+mod5+Print").unwrap();
+        assert_eq!(
+            hotkey.mods,
+            x::ModMask::SHIFT | x::ModMask::N4 | x::ModMask::N5
+        );
+        assert_eq!(hotkey.sym, Keysym::new(key::Print));
+
+        // Aliases, and saying the same thing twice, are fine.
+        let hotkey = parse("control+mod4+super+F1").unwrap();
+        assert_eq!(hotkey.mods, x::ModMask::CONTROL | x::ModMask::N4);
+    }
+
+    #[test]
+    fn case() {
+        // Modifiers can be spelled however...
+        let hotkey = parse("CTRL+Super+h").unwrap();
+        assert_eq!(hotkey.mods, x::ModMask::CONTROL | x::ModMask::N4);
+        // ...but keysyms can't, since case tells them apart.
+        assert_eq!(parse("ctrl+H").unwrap().sym, Keysym::new(key::H));
+        assert!(parse("super+print").is_err());
+        assert!(parse("super+f9").is_err());
+    }
+
+    #[test]
+    fn unknown_modifier() {
+        assert!(parse("hyper+F9").is_err());
+        assert!(parse("ctrl+meta+h").is_err());
+        // An empty modifier isn't one either.
+        assert!(parse("ctrl++h").is_err());
+    }
+
+    #[test]
+    fn missing_key() {
+        assert!(parse("").is_err());
+        assert!(parse("super+").is_err());
+        assert!(parse("ctrl+alt+").is_err());
+        assert!(parse("+").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Letting other programs ask us to leave the pointer alone.
+//!
+//! Any program that can set a property can do this: while the
+//! `_RXBANISH_INHIBIT` property exists on any root window, we don't hide the
+//! pointer. Its type and value don't matter. From a script, that looks like:
+//!
+//! ```text
+//! xprop -root -f _RXBANISH_INHIBIT 8s -set _RXBANISH_INHIBIT recording
+//! xprop -root -remove _RXBANISH_INHIBIT
+//! ```
+//!
+//! Properties outlive the programs that set them, so anything that dies
+//! without cleaning up leaves us inhibited until someone removes it.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use xcb::{x, Connection};
+
+/// The property other programs set to inhibit us.
+const PROPERTY: &[u8] = b"_RXBANISH_INHIBIT";
+
+/// Keeps track of which root windows have the inhibit property set.
+pub struct Inhibitor {
+    atom: x::Atom,
+    roots: Vec<x::Window>,
+    inhibited: HashSet<x::Window>,
+}
+
+impl Inhibitor {
+    /// Checks which of `roots` have the property set already. The caller is
+    /// responsible for selecting PropertyChange on `roots`, so that we hear
+    /// about changes.
+    pub fn new(conn: &Connection, roots: &[x::Window]) -> Result<Self> {
+        let atom = conn.wait_for_reply(conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: PROPERTY,
+        }))?.atom();
+        let mut inhibitor = Self {
+            atom,
+            roots: roots.to_vec(),
+            inhibited: HashSet::new(),
+        };
+        for &root in roots {
+            inhibitor.refresh(conn, root)?;
+        }
+        Ok(inhibitor)
+    }
+
+    /// Whether anyone has asked us to leave the pointer alone.
+    pub fn active(&self) -> bool {
+        !self.inhibited.is_empty()
+    }
+
+    /// Updates our idea of who's inhibited us, if `event` is about the
+    /// property. Other property changes are ignored.
+    pub fn handle_property_notify(
+        &mut self,
+        conn: &Connection,
+        event: &x::PropertyNotifyEvent,
+    ) -> Result<()> {
+        if self.roots.contains(&event.window()) && event.atom() == self.atom {
+            self.refresh(conn, event.window())?;
+        }
+        Ok(())
+    }
+
+    /// Checks whether the property is set on `root`.
+    fn refresh(&mut self, conn: &Connection, root: x::Window) -> Result<()> {
+        // We only care whether it's there, so we ask for none of the value.
+        let reply = conn.wait_for_reply(conn.send_request(&x::GetProperty {
+            delete: false,
+            window: root,
+            property: self.atom,
+            r#type: x::ATOM_ANY,
+            long_offset: 0,
+            long_length: 0,
+        }))?;
+        if reply.r#type() == x::ATOM_NONE {
+            self.inhibited.remove(&root);
+        } else {
+            self.inhibited.insert(root);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,714 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Snooping on input devices.
+//!
+//! We want to hear about every key and every bit of pointer movement, no matter
+//! which window it's going to. XInput 2's raw events do that nicely, for all
+//! devices at once. Servers without XInput 2 are rare these days, but for them
+//! we fall back on XInput 1, which lets us select events from each device on
+//! the root windows, as they are when we connect. Tablets need XInput 1 either
+//! way, since XInput 2 has no proximity events, which means keeping an eye out
+//! for new devices turning up.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use serde::Serialize;
+use xcb::{
+    x,
+    xinput::{self, DeviceUse, InputClass},
+    Connection, Event, Xid,
+};
+
+use crate::{lost_connection, DevicePolicy, Input, Settings};
+
+/// Registers to be notified of all input events on certain windows, which in
+/// our case are always the root windows. Devices that won't let us snoop on
+/// them go in `registrations` to try again later. `input` says which version
+/// of XInput to use. Returns whether we're getting raw events.
+pub fn snoop_xinput(
+    conn: &Connection,
+    windows: &[x::Window],
+    registrations: &mut Registrations,
+    input: Input,
+) -> Result<bool> {
+    let mut raw = false;
+
+    let xi2 = input != Input::Legacy && query_xi2(conn);
+    if input == Input::Xi2 && !xi2 {
+        bail!("the X server doesn't have XInput 2.2");
+    }
+    if xi2 {
+        // Register for raw events. These aren't tied to any particular
+        // window, and the server sends them to every root window that asks,
+        // so we only need to ask on one.
+        //
+        // While we're at it, ask to hear about devices being added, removed,
+        // enabled and disabled. The server only sends those to clients that
+        // ask for them from all devices, rather than just the master ones.
+        conn.send_and_check_request(&xinput::XiSelectEvents {
+            window: windows[0],
+            masks: &[
+                xinput::EventMaskBuf::new(
+                    xinput::Device::AllMaster,
+                    &[xinput::XiEventMask::RAW_MOTION
+                        | xinput::XiEventMask::RAW_BUTTON_PRESS
+                        | xinput::XiEventMask::RAW_KEY_PRESS
+                        | xinput::XiEventMask::RAW_KEY_RELEASE],
+                ),
+                xinput::EventMaskBuf::new(
+                    xinput::Device::All,
+                    &[xinput::XiEventMask::HIERARCHY],
+                ),
+            ],
+        })?;
+
+        debug!("using xinput2 raw events");
+
+        raw = true;
+    }
+
+    let list_reply =
+        conn.wait_for_reply(conn.send_request(&xinput::ListInputDevices {}))?;
+    for (devinfo, _, classes) in with_classes(&list_reply) {
+        // With raw events, there's only anything left to snoop on for
+        // tablets.
+        let snoop = snoop_for(devinfo.device_use(), &classes, raw);
+        if matches!(snoop, Some(Snoop::Legacy | Snoop::Both)) {
+            let id = devinfo.device_id();
+            registrations.register(conn, windows, raw, id)?;
+        }
+    }
+
+    Ok(raw)
+}
+
+/// Checks whether the server has XInput 2, which we need for raw events. We
+/// ask for 2.2, since the server only tells clients that know about touch
+/// which devices are touchscreens.
+fn query_xi2(conn: &Connection) -> bool {
+    conn.wait_for_reply(conn.send_request(&xinput::XiQueryVersion {
+        major_version: 2,
+        minor_version: 2,
+    }))
+    .is_ok()
+}
+
+/// Finds out which version of XInput we're using: 2.2 if the server has it,
+/// or whatever it does have otherwise.
+pub fn version(conn: &Connection) -> Result<(u16, u16)> {
+    let xi2 = conn.wait_for_reply(conn.send_request(&xinput::XiQueryVersion {
+        major_version: 2,
+        minor_version: 2,
+    }));
+    if let Ok(reply) = xi2 {
+        return Ok((reply.major_version(), reply.minor_version()));
+    }
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xinput::GetExtensionVersion {
+            name: b"XInputExtension",
+        }))?;
+    Ok((reply.server_major(), reply.server_minor()))
+}
+
+/// Pairs up each device in `reply` with its name and the classes of input it
+/// has, which come one after another in one big list.
+fn with_classes(
+    reply: &xinput::ListInputDevicesReply,
+) -> Vec<(&xinput::DeviceInfo, String, Vec<InputClass>)> {
+    let mut infos = reply.infos();
+    reply
+        .devices()
+        .iter()
+        .zip(reply.names())
+        .map(|(devinfo, name)| {
+            let classes = infos
+                .by_ref()
+                .take(usize::from(devinfo.num_class_info()))
+                .map(|info| info.class_id())
+                .collect();
+            (devinfo, name.name().to_utf8().into_owned(), classes)
+        })
+        .collect()
+}
+
+/// Works out how we hear from a device that's used as `usage` and has
+/// `classes` of input, if at all. `raw` says whether we're getting raw events.
+fn snoop_for(
+    usage: DeviceUse,
+    classes: &[InputClass],
+    raw: bool,
+) -> Option<Snoop> {
+    // The core devices are the masters, whose events are all really their
+    // slaves', and anything else isn't attached to either.
+    if !matches!(
+        usage,
+        DeviceUse::IsXExtensionKeyboard | DeviceUse::IsXExtensionPointer
+    ) {
+        return None;
+    }
+    let proximity = classes.contains(&InputClass::Proximity);
+    Some(match (raw, proximity) {
+        (false, _) => Snoop::Legacy,
+        (true, false) => Snoop::Raw,
+        (true, true) => Snoop::Both,
+    })
+}
+
+/// How we hear from an input device.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Snoop {
+    /// Through XInput 2's raw events.
+    Raw,
+    /// Through XInput 1, on the root windows.
+    Legacy,
+    /// Through raw events, plus XInput 1 for proximity, which they don't
+    /// cover.
+    Both,
+}
+
+impl Snoop {
+    /// Names the way of listening, for people.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Snoop::Raw => "raw",
+            Snoop::Legacy => "legacy",
+            Snoop::Both => "both",
+        }
+    }
+}
+
+/// What `describe_devices` has to say about an input device.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceReport {
+    pub id: u16,
+    pub name: String,
+    /// What X uses the device as, e.g. "extension keyboard."
+    pub usage: &'static str,
+    /// The kinds of input the device has, e.g. "key" or "valuator."
+    pub classes: Vec<&'static str>,
+    /// How we hear from the device, if we do.
+    pub snoop: Option<Snoop>,
+    /// What the device is allowed to do to the pointer.
+    pub policy: DevicePolicy,
+}
+
+/// Describes each of the server's input devices, and how we'd listen to it
+/// with `settings`, for the benefit of users working out which devices to
+/// ignore.
+pub fn describe_devices(
+    conn: &Connection,
+    settings: &Settings,
+) -> Result<Vec<DeviceReport>> {
+    let raw = query_xi2(conn);
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xinput::ListInputDevices {}))?;
+    Ok(with_classes(&reply)
+        .into_iter()
+        .map(|(devinfo, name, classes)| DeviceReport {
+            id: u16::from(devinfo.device_id()),
+            usage: match devinfo.device_use() {
+                DeviceUse::IsXPointer => "core pointer",
+                DeviceUse::IsXKeyboard => "core keyboard",
+                DeviceUse::IsXExtensionDevice => "extension device",
+                DeviceUse::IsXExtensionKeyboard => "extension keyboard",
+                DeviceUse::IsXExtensionPointer => "extension pointer",
+            },
+            snoop: snoop_for(devinfo.device_use(), &classes, raw),
+            classes: classes
+                .iter()
+                .map(|class| match class {
+                    InputClass::Key => "key",
+                    InputClass::Button => "button",
+                    InputClass::Valuator => "valuator",
+                    InputClass::Feedback => "feedback",
+                    InputClass::Proximity => "proximity",
+                    InputClass::Focus => "focus",
+                    InputClass::Other => "other",
+                })
+                .collect(),
+            policy: settings.device_policy(&name),
+            name,
+        })
+        .collect())
+}
+
+/// Registers to snoop on a specific device given by ID, on each of `windows`,
+/// using XInput 1. If `raw` is set, we're getting raw events, and only need
+/// what they don't cover.
+pub fn snoop_device(
+    conn: &Connection,
+    windows: &[x::Window],
+    raw: bool,
+    device_id: u8,
+) -> Result<()> {
+    let dev_reply =
+        conn.wait_for_reply(conn.send_request(&xinput::OpenDevice {
+            device_id,
+        }))?;
+
+    let mut event_list = vec![];
+
+    for c in dev_reply.class_info() {
+        match c.class_id() {
+            InputClass::Key => {
+                if raw {
+                    continue;
+                }
+                // We ask for both presses and releases, so that `hide_on`
+                // can change on reload without us having to re-register.
+                event_list.push(make_event_code(
+                        device_id,
+                        c.event_type_base(),
+                ));
+
+                // Apparently event_type_base + 1 for key inputs is release?
+                // I learned this by READING C HEADERS. Not sure where
+                // you're supposed to learn it.
+                event_list.push(make_event_code(
+                        device_id,
+                        c.event_type_base() + 1,
+                ));
+            }
+            InputClass::Valuator => {
+                if raw {
+                    continue;
+                }
+                event_list.push(make_event_code(
+                        device_id,
+                        c.event_type_base(),
+                ));
+            }
+            InputClass::Button => {
+                if raw {
+                    continue;
+                }
+                event_list.push(make_event_code(
+                        device_id,
+                        c.event_type_base(),
+                ));
+                // Here again, event type base + 1 appears to be "release."
+                event_list.push(make_event_code(
+                        device_id,
+                        c.event_type_base() + 1,
+                ));
+            }
+            InputClass::Proximity => {
+                // Tablets tell us when the pen comes near the surface, before
+                // it moves. Raw events don't cover this, so we always want it.
+                // The base is "in"; we don't care about "out."
+                event_list.push(make_event_code(
+                        device_id,
+                        c.event_type_base(),
+                ));
+            }
+            _ => (),
+        }
+    }
+
+    conn.send_and_check_request(&xinput::CloseDevice {
+        device_id,
+    })?;
+
+    if event_list.is_empty() {
+        return Ok(());
+    }
+    for &window in windows {
+        conn.send_and_check_request(&xinput::SelectExtensionEvent {
+            window,
+            classes: &event_list,
+        })?;
+    }
+
+    Ok(())
+}
+
+/// How long to wait before first trying again to snoop on a device that
+/// wouldn't let us. The wait doubles after each failed attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// How many times to try snooping on a device before giving up on it.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Keeps track of the devices we're snooping on with XInput 1, and those we've
+/// failed to snoop on, and when to try them again.
+///
+/// Devices that are being plugged in can take a moment to settle down, and the
+/// server can turn us away (with BadDevice or BadMatch) in the meantime. One
+/// flaky keyboard, say a Bluetooth one dropping out halfway through, shouldn't
+/// stop us listening to all the others, so we carry on without it, and have
+/// another go later.
+#[derive(Default)]
+pub struct Registrations {
+    /// Devices we're snooping on, by ID.
+    registered: HashSet<u8>,
+    /// Devices to try again, by ID, with how many times we've tried and when
+    /// to next.
+    retries: HashMap<u8, (u32, Instant)>,
+}
+
+impl Registrations {
+    /// Snoops on `device_id`, as `snoop_device` does. If the server objects,
+    /// this arranges to try again later, rather than failing. Losing the
+    /// server is still an error.
+    pub fn register(
+        &mut self,
+        conn: &Connection,
+        windows: &[x::Window],
+        raw: bool,
+        device_id: u8,
+    ) -> Result<()> {
+        let attempts = match snoop_device(conn, windows, raw, device_id) {
+            Ok(()) => {
+                self.retries.remove(&device_id);
+                self.registered.insert(device_id);
+                return Ok(());
+            }
+            Err(e) if lost_connection(&e) => return Err(e),
+            // Devices can be unplugged between the server telling us about
+            // them and us opening them. There's no undoing whatever selection
+            // we'd made by then, but nor is there any need, since a device
+            // that's gone sends no events.
+            Err(e) if !device_exists(conn, device_id)? => {
+                debug!(device_id; "input device went away: {e}");
+                self.retries.remove(&device_id);
+                return Ok(());
+            }
+            Err(e) => {
+                let attempts = self.retries.get(&device_id).map_or(0, |r| r.0);
+                let attempts = attempts + 1;
+                if attempts >= MAX_ATTEMPTS {
+                    warn!(device_id; "not listening to input device: {e}");
+                    self.retries.remove(&device_id);
+                    return Ok(());
+                }
+                debug!(device_id, attempts; "can't snoop on device yet: {e}");
+                attempts
+            }
+        };
+        let delay = RETRY_DELAY * 2u32.pow(attempts - 1);
+        self.retries.insert(device_id, (attempts, Instant::now() + delay));
+        Ok(())
+    }
+
+    /// Checks whether we're already snooping on `device_id`.
+    pub fn registered(&self, device_id: u8) -> bool {
+        self.registered.contains(&device_id)
+    }
+
+    /// Forgets about `device_id`, which has been disabled or unplugged, and
+    /// stops trying to snoop on it. The server forgets our selection along
+    /// with the device, or at least stops sending anything for it, so there's
+    /// nothing to undo there.
+    pub fn forget(&mut self, device_id: u8) {
+        self.registered.remove(&device_id);
+        self.retries.remove(&device_id);
+    }
+
+    /// When we next need to try a device again, if ever.
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.retries.values().map(|&(_, at)| at).min()
+    }
+
+    /// Tries again on any devices whose time has come.
+    pub fn retry(
+        &mut self,
+        conn: &Connection,
+        windows: &[x::Window],
+        raw: bool,
+    ) -> Result<()> {
+        let now = Instant::now();
+        let due: Vec<u8> = self
+            .retries
+            .iter()
+            .filter(|(_, &(_, at))| at <= now)
+            .map(|(&id, _)| id)
+            .collect();
+        for device_id in due {
+            self.register(conn, windows, raw, device_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks the server which modifiers are down (or locked) right now on the
+/// keyboard paired with the master pointer `master`. Raw key events don't say,
+/// unlike XInput 1 ones. `window` can be any window.
+pub fn modifier_state(
+    conn: &Connection,
+    window: x::Window,
+    master: u16,
+) -> Result<x::KeyButMask> {
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xinput::XiQueryPointer {
+            window,
+            device: xinput::Device::from_id(master),
+        }))?;
+    Ok(x::KeyButMask::from_bits_truncate(reply.mods().effective))
+}
+
+/// Makes an operand suitable for use with SelectExtensionEvent, which appears
+/// to not be documented anywhere except C macros, hooray X11.
+fn make_event_code(device_id: u8, event_type: u8) -> u32 {
+    u32::from(device_id) << 8 | u32::from(event_type)
+}
+
+/// Looks up the names of all the input devices, by ID, so that we can tell
+/// which ones the user has asked us to listen to.
+pub fn device_names(conn: &Connection) -> Result<HashMap<u16, String>> {
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xinput::ListInputDevices {}))?;
+    Ok(reply
+        .devices()
+        .iter()
+        .zip(reply.names())
+        .map(|(info, name)| {
+            (u16::from(info.device_id()), name.name().to_utf8().into_owned())
+        })
+        .collect())
+}
+
+/// Checks whether the server still has an input device called `device_id`.
+fn device_exists(conn: &Connection, device_id: u8) -> Result<bool> {
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xinput::ListInputDevices {}))?;
+    Ok(reply.devices().iter().any(|d| d.device_id() == device_id))
+}
+
+/// Finds the touchscreens among the input devices, by ID. Touchpads have touch
+/// classes too, but they drive the pointer like a mouse does, so they don't
+/// count. This needs XInput 2.2; without it, there are no touchscreens as far
+/// as we're concerned.
+pub fn touchscreens(conn: &Connection) -> Result<HashSet<u16>> {
+    let reply = conn.wait_for_reply(conn.send_request(&xinput::XiQueryDevice {
+        device: xinput::Device::All,
+    }));
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(xcb::Error::Protocol(_)) => return Ok(HashSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(reply
+        .infos()
+        .filter(|info| {
+            info.classes().any(|class| {
+                matches!(
+                    class.data(),
+                    xinput::DeviceClassData::Touch {
+                        mode: xinput::TouchMode::Direct,
+                        ..
+                    }
+                )
+            })
+        })
+        .map(|info| info.device().id())
+        .collect())
+}
+
+/// Works out which master pointer each input device belongs to, by ID. Master
+/// keyboards are paired with a master pointer, and slave devices are attached
+/// to one master or the other. Floating slaves don't belong to anything, so
+/// they're left out. Without XInput 2 there's no telling, so there are none.
+pub fn master_pointers(conn: &Connection) -> Result<HashMap<u16, u16>> {
+    let reply = conn.wait_for_reply(conn.send_request(&xinput::XiQueryDevice {
+        device: xinput::Device::All,
+    }));
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(xcb::Error::Protocol(_)) => return Ok(HashMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    // What each device is, and what it's attached (or paired) to.
+    let devices: HashMap<u16, (xinput::DeviceType, u16)> = reply
+        .infos()
+        .map(|info| {
+            (info.device().id(), (info.r#type(), info.attachment().id()))
+        })
+        .collect();
+    let pointer_of = |id: u16| match devices.get(&id)? {
+        (xinput::DeviceType::MasterPointer, _) => Some(id),
+        (xinput::DeviceType::MasterKeyboard, paired) => Some(*paired),
+        _ => None,
+    };
+    Ok(devices
+        .iter()
+        .filter_map(|(&id, &(kind, attachment))| {
+            let master = match kind {
+                xinput::DeviceType::MasterPointer
+                | xinput::DeviceType::MasterKeyboard => pointer_of(id),
+                xinput::DeviceType::SlavePointer
+                | xinput::DeviceType::SlaveKeyboard => pointer_of(attachment),
+                xinput::DeviceType::FloatingSlave => None,
+            };
+            Some((id, master?))
+        })
+        .collect())
+}
+
+/// Asks which master pointer is ours, i.e. the one that core requests like
+/// QueryPointer and WarpPointer act on. Without XInput 2, it's the only one.
+pub fn client_pointer(conn: &Connection) -> Result<u16> {
+    // The X server always gives the first master pointer this ID.
+    const VIRTUAL_CORE_POINTER: u16 = 2;
+
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xinput::XiGetClientPointer {
+            window: x::Window::none(),
+        }));
+    match reply {
+        Ok(reply) if reply.set() => Ok(reply.device().id()),
+        Ok(_) | Err(xcb::Error::Protocol(_)) => Ok(VIRTUAL_CORE_POINTER),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// A key going down or coming back up.
+#[derive(Copy, Clone, Debug)]
+pub struct KeyChange {
+    pub pressed: bool,
+    pub keycode: u32,
+    pub time: x::Timestamp,
+    /// Whether the server has said outright that this is the keyboard
+    /// repeating a held key. Only XInput 2 can.
+    pub repeat: bool,
+    /// The modifiers held at the time, if the event says. Only XInput 1
+    /// events do.
+    pub mods: Option<x::KeyButMask>,
+}
+
+/// Works out which key `event` is about, if it's about one.
+pub fn key_change(event: &xinput::Event) -> Option<KeyChange> {
+    let raw = |e: &xinput::RawKeyPressEvent, pressed| KeyChange {
+        pressed,
+        keycode: e.detail(),
+        time: e.time(),
+        repeat: e.flags().contains(xinput::KeyEventFlags::KEY_REPEAT),
+        mods: None,
+    };
+    let xi1 = |e: &xinput::DeviceKeyPressEvent, pressed| KeyChange {
+        pressed,
+        keycode: e.detail().into(),
+        time: e.time(),
+        repeat: false,
+        mods: Some(e.state()),
+    };
+    match event {
+        xinput::Event::RawKeyPress(e) => Some(raw(e, true)),
+        xinput::Event::RawKeyRelease(e) => Some(raw(e, false)),
+        xinput::Event::DeviceKeyPress(e) => Some(xi1(e, true)),
+        xinput::Event::DeviceKeyRelease(e) => Some(xi1(e, false)),
+        _ => None,
+    }
+}
+
+/// Checks whether `event` is only scrolling. That's a press of one of the
+/// buttons that wheels send (4 to 7), or, with XInput 2, motion along none
+/// of a device's axes but its scroll axes, which is how touchpads and some
+/// mice scroll smoothly. Pointer devices have X and Y as their first two
+/// axes, so motion along anything else is taken to be scrolling.
+pub fn is_scroll(event: &xinput::Event) -> bool {
+    const WHEEL: std::ops::RangeInclusive<u32> = 4..=7;
+    match event {
+        xinput::Event::RawButtonPress(e) => WHEEL.contains(&e.detail()),
+        xinput::Event::DeviceButtonPress(e)
+        | xinput::Event::DeviceButtonRelease(e) => {
+            WHEEL.contains(&e.detail().into())
+        }
+        xinput::Event::RawMotion(e) => {
+            let axes = e.valuator_mask().first().copied().unwrap_or(0);
+            let others = e.valuator_mask().iter().skip(1).any(|&m| m != 0);
+            axes & 0b11 == 0 && (axes != 0 || others)
+        }
+        _ => false,
+    }
+}
+
+/// Works out which input device sent `event`, if it came from one.
+pub fn source_device(event: &xinput::Event) -> Option<u16> {
+    // XInput 1 events use the top bit of the device ID to say that more
+    // events are coming.
+    let xi1 = |id: u8| Some(u16::from(id & 0x7f));
+    match event {
+        xinput::Event::RawMotion(e) | xinput::Event::RawButtonPress(e) => {
+            Some(e.source().id())
+        }
+        xinput::Event::DeviceValuator(e) => xi1(e.device_id()),
+        xinput::Event::DeviceMotionNotify(e)
+        | xinput::Event::DeviceButtonPress(e)
+        | xinput::Event::DeviceButtonRelease(e)
+        | xinput::Event::DeviceKeyPress(e)
+        | xinput::Event::DeviceKeyRelease(e)
+        | xinput::Event::ProximityIn(e)
+        | xinput::Event::ProximityOut(e) => xi1(e.device_id()),
+        xinput::Event::RawKeyPress(e) | xinput::Event::RawKeyRelease(e) => {
+            Some(e.source().id())
+        }
+        _ => None,
+    }
+}
+
+/// The server always gives the first master pointer and keyboard these IDs.
+pub const CORE_POINTER: u8 = 2;
+pub const CORE_KEYBOARD: u8 = 3;
+
+/// The core input events, by type.
+pub const KEY_PRESS: u8 = 2;
+pub const KEY_RELEASE: u8 = 3;
+pub const BUTTON_PRESS: u8 = 4;
+pub const BUTTON_RELEASE: u8 = 5;
+pub const MOTION_NOTIFY: u8 = 6;
+
+/// How big a core event is on the wire.
+pub const EVENT_SIZE: usize = 32;
+
+/// Finds out where XInput's events start, for `device_event`.
+pub fn first_event(conn: &Connection) -> Result<u8> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::QueryExtension {
+        name: b"XInputExtension",
+    }))?;
+    if !reply.present() {
+        bail!("the X server doesn't have the XInput extension");
+    }
+    Ok(reply.first_event())
+}
+
+/// Passes off a core input event, laid out as it comes over the wire, as an
+/// XInput 1 device event from `device`, which the banisher already knows what
+/// to do with. The two are laid out the same but for the event type, and the
+/// last byte, which is padding in one and the device ID in the other.
+/// `first_event` is where XInput's events start.
+pub fn device_event(
+    conn: &Connection,
+    first_event: u8,
+    wire: &[u8; EVENT_SIZE],
+    device: u8,
+) -> Option<Event> {
+    // The top bit says whether the event was sent with SendEvent.
+    let kind = wire[0] & 0x7f;
+    if !(KEY_PRESS..=MOTION_NOTIFY).contains(&kind) {
+        return None;
+    }
+    // XInput 1's device events come in the same order as the core ones,
+    // starting one after its first event.
+    let mut wire = *wire;
+    wire[0] = first_event + (kind - KEY_PRESS) + 1;
+    wire[EVENT_SIZE - 1] = device;
+    // Safety: xcb takes ownership of the event, and frees it with free, so it
+    // has to come from malloc (or calloc). Events in memory have a little more
+    // to them than on the wire, which calloc leaves zeroed.
+    unsafe {
+        let size = std::mem::size_of::<xcb::ffi::xcb_generic_event_t>();
+        let raw = libc::calloc(1, size) as *mut u8;
+        if raw.is_null() {
+            return None;
+        }
+        std::ptr::copy_nonoverlapping(wire.as_ptr(), raw, EVENT_SIZE);
+        let raw = raw as *mut xcb::ffi::xcb_generic_event_t;
+        Some(conn.resolve_event(&mut *raw))
+    }
+}
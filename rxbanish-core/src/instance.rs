@@ -0,0 +1,140 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Making sure there's only one of us per screen.
+//!
+//! Two copies of rxbanish on the same screen will fight over the pointer, and
+//! nobody wants that. We avoid it the way window managers and compositors do:
+//! by owning a selection named after each screen (ICCCM calls this a "manager
+//! selection"). Whoever owns it is in charge. A new instance can take the
+//! selections away, at which point the old one gets a SelectionClear event and
+//! bows out.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use xcb::{x, Connection, Xid};
+
+/// How long we'll wait for a previous instance to exit after taking over from
+/// it.
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Proof that we're the rxbanish in charge of the screens.
+pub struct Instance {
+    selections: Vec<x::Atom>,
+    window: x::Window,
+}
+
+impl Instance {
+    /// Claims the screens with the given `roots` for ourselves.
+    ///
+    /// If another rxbanish already has any of them, this fails, unless
+    /// `replace` is set, in which case we take over and wait for the other one
+    /// to exit.
+    pub fn claim(
+        conn: &Connection,
+        roots: &[x::Window],
+        replace: bool,
+    ) -> Result<Self> {
+        let mut selections = vec![];
+        let mut previous = vec![];
+        for screen_num in 0..roots.len() {
+            let name = format!("_RXBANISH_S{screen_num}");
+            let selection =
+                conn.wait_for_reply(conn.send_request(&x::InternAtom {
+                    only_if_exists: false,
+                    name: name.as_bytes(),
+                }))?.atom();
+            selections.push(selection);
+
+            let owner =
+                conn.wait_for_reply(conn.send_request(&x::GetSelectionOwner {
+                    selection,
+                }))?.owner();
+            if !owner.is_none() && !previous.contains(&owner) {
+                previous.push(owner);
+            }
+        }
+        if !previous.is_empty() {
+            if !replace {
+                bail!(
+                    "rxbanish is already running on this display \
+                     (use --replace to take over from it)"
+                );
+            }
+            log::info!("replacing the rxbanish that's already running");
+        }
+
+        // We need a window to own the selections. It never gets mapped, so it
+        // doesn't much matter what it looks like, or which screen it's on.
+        let window = conn.generate_id();
+        conn.send_and_check_request(&x::CreateWindow {
+            depth: 0,
+            wid: window,
+            parent: roots[0],
+            x: -1,
+            y: -1,
+            width: 1,
+            height: 1,
+            border_width: 0,
+            class: x::WindowClass::InputOnly,
+            visual: x::COPY_FROM_PARENT,
+            value_list: &[],
+        })?;
+
+        for &selection in &selections {
+            // ICCCM would rather we used a real timestamp here, but since we
+            // check the result below, CurrentTime does no harm.
+            conn.send_and_check_request(&x::SetSelectionOwner {
+                owner: window,
+                selection,
+                time: x::CURRENT_TIME,
+            })?;
+            let owner =
+                conn.wait_for_reply(conn.send_request(&x::GetSelectionOwner {
+                    selection,
+                }))?.owner();
+            if owner != window {
+                bail!("another rxbanish started at the same time as this one");
+            }
+        }
+
+        for window in previous {
+            wait_for_exit(conn, window)?;
+        }
+
+        Ok(Self { selections, window })
+    }
+
+    /// Checks whether `event` means another instance has taken over from us.
+    pub fn lost(&self, event: &x::SelectionClearEvent) -> bool {
+        self.selections.contains(&event.selection())
+            && event.owner() == self.window
+    }
+}
+
+/// Waits for the instance that owned `window` to go away, which it'll do once
+/// it notices we've taken its selection. Its window goes away when it
+/// disconnects, so we just keep checking on the window.
+///
+/// Waiting matters, because until the old instance is gone it's still hiding
+/// the pointer and holding on to the control socket.
+fn wait_for_exit(conn: &Connection, window: x::Window) -> Result<()> {
+    let give_up = Instant::now() + REPLACE_TIMEOUT;
+    while Instant::now() < give_up {
+        let reply = conn.wait_for_reply(conn.send_request(
+            &x::GetWindowAttributes { window },
+        ));
+        match reply {
+            Ok(_) => std::thread::sleep(Duration::from_millis(50)),
+            Err(xcb::Error::Protocol(xcb::ProtocolError::X(
+                x::Error::Window(_),
+                _,
+            ))) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    log::warn!("the previous rxbanish is taking its time exiting; carrying on");
+    Ok(())
+}
@@ -0,0 +1,303 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Keysym handling: turning names like "Print" into keysyms, and keycodes from
+//! input events into keysyms using the server's keyboard mapping.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use xcb::{x, Connection};
+use xkeysym::{key, Keysym};
+
+/// Ranges of keysym values that have names we might want to look up. Outside
+/// of these, keysyms are either unnamed or Unicode code points, which we handle
+/// separately.
+const NAMED_RANGES: &[RangeInclusive<u32>] = &[
+    // Latin-1 through the function and modifier keys.
+    0x0000..=0xffff,
+    // Unicode keysyms that also have traditional names.
+    0x0100_0000..=0x0100_2fff,
+    // Vendor keysyms: DEC and HP, then Sun, then XF86.
+    0x1000_0000..=0x1000_00ff,
+    0x1000_fe00..=0x1000_ffff,
+    0x1004_ff00..=0x1004_ffff,
+    0x1005_ff00..=0x1005_ffff,
+    0x1008_0000..=0x1008_ffff,
+];
+
+/// Lazily builds a table of keysyms by name.
+///
+/// `xkeysym` can only go from keysyms to names, and not back, so we go and
+/// ask it about every keysym that might have a name. This takes a moment, so we
+/// only do it once, and only if someone actually asks for a keysym by name.
+fn names() -> &'static HashMap<String, Keysym> {
+    static NAMES: OnceLock<HashMap<String, Keysym>> = OnceLock::new();
+    NAMES.get_or_init(|| {
+        let mut names = HashMap::new();
+        for range in NAMED_RANGES {
+            for raw in range.clone() {
+                let sym = Keysym::new(raw);
+                let Some(name) = sym.name() else { continue };
+                // xkeysym gives us names as they're spelled in the C headers,
+                // e.g. XK_Print or XF86XK_AudioRaiseVolume, while humans (and
+                // xev) spell them Print and XF86AudioRaiseVolume.
+                let name = name.replacen("XK_", "", 1);
+                // Several names can map to the same keysym, and occasionally a
+                // keysym has a deprecated alias; the first one wins.
+                names.entry(name).or_insert(sym);
+            }
+        }
+        names
+    })
+}
+
+/// Parses a keysym given by name, like "Print" or "XF86AudioRaiseVolume", as
+/// printed by xev. For keysyms without names, we also accept a hex value
+/// ("0xff61") or a Unicode code point ("U+20AC").
+pub fn parse(name: &str) -> Result<Keysym> {
+    if let Some(hex) = name.strip_prefix("0x") {
+        if let Ok(raw) = u32::from_str_radix(hex, 16) {
+            return Ok(Keysym::new(raw));
+        }
+    }
+    if let Some(hex) = name.strip_prefix("U+") {
+        let c = u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+        if let Some(c) = c {
+            return Ok(Keysym::from_char(c));
+        }
+    }
+    names()
+        .get(name)
+        .copied()
+        .ok_or_else(|| anyhow!("unknown keysym name: {name}"))
+}
+
+/// Named groups of related keys, so that people can ignore all the media keys
+/// (say) without having to look up and list every one of them.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Group {
+    /// F1 and friends.
+    Function,
+    /// Volume, playback, and eject keys.
+    Media,
+    /// Arrows, Home/End, and Page Up/Down, including their keypad versions.
+    Navigation,
+    /// Backspace, Delete, Insert, Undo/Redo, and Cut/Copy/Paste.
+    Editing,
+    /// Everything on the numeric keypad, with Num Lock on or off.
+    Numpad,
+}
+
+impl Group {
+    /// Checks whether `sym` belongs to this group.
+    pub fn contains(self, sym: Keysym) -> bool {
+        #[allow(non_upper_case_globals)]
+        match self {
+            Group::Function => sym.is_function_key(),
+            Group::Media => matches!(
+                sym.raw(),
+                key::XF86_AudioLowerVolume
+                    | key::XF86_AudioMute
+                    | key::XF86_AudioRaiseVolume
+                    | key::XF86_AudioMicMute
+                    | key::XF86_AudioPlay
+                    | key::XF86_AudioPause
+                    | key::XF86_AudioStop
+                    | key::XF86_AudioPrev
+                    | key::XF86_AudioNext
+                    | key::XF86_AudioRewind
+                    | key::XF86_AudioForward
+                    | key::XF86_AudioRecord
+                    | key::XF86_AudioRepeat
+                    | key::XF86_AudioRandomPlay
+                    | key::XF86_AudioMedia
+                    | key::XF86_Eject
+            ),
+            // The ranges here are Home, Left, Up, Right, Down, Page_Up,
+            // Page_Down, End, Begin, and then the same again for the keypad.
+            Group::Navigation => matches!(
+                sym.raw(),
+                key::Home..=key::Begin | key::KP_Home..=key::KP_Begin
+            ),
+            Group::Editing => matches!(
+                sym.raw(),
+                key::BackSpace
+                    | key::Delete
+                    | key::Insert
+                    | key::Undo
+                    | key::Redo
+                    | key::XF86_Cut
+                    | key::XF86_Copy
+                    | key::XF86_Paste
+            ),
+            Group::Numpad => sym.is_keypad_key(),
+        }
+    }
+}
+
+/// A copy of the server's keyboard mapping, for turning keycodes from key
+/// events into keysyms, along with which keys are modifiers.
+pub struct Keymap {
+    min_keycode: u8,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<u32>,
+    /// Keycodes for each of the eight modifiers, in order, as the server
+    /// gives them to us. Zero means no key.
+    modifiers: Vec<u8>,
+}
+
+/// The eight modifiers, in the order the server lists their keys.
+const MODIFIERS: [x::KeyButMask; 8] = [
+    x::KeyButMask::SHIFT,
+    x::KeyButMask::LOCK,
+    x::KeyButMask::CONTROL,
+    x::KeyButMask::MOD1,
+    x::KeyButMask::MOD2,
+    x::KeyButMask::MOD3,
+    x::KeyButMask::MOD4,
+    x::KeyButMask::MOD5,
+];
+
+impl Keymap {
+    /// Fetches the current keyboard mapping from the server. This needs to be
+    /// redone whenever we get a MappingNotify for the keyboard or modifiers.
+    pub fn fetch(conn: &Connection) -> Result<Self> {
+        let setup = conn.get_setup();
+        let min_keycode = setup.min_keycode();
+        let max_keycode = setup.max_keycode();
+
+        let reply =
+            conn.wait_for_reply(conn.send_request(&x::GetKeyboardMapping {
+                first_keycode: min_keycode,
+                count: max_keycode - min_keycode + 1,
+            }))?;
+        let modifiers = conn
+            .wait_for_reply(conn.send_request(&x::GetModifierMapping {}))?;
+
+        Ok(Self {
+            min_keycode,
+            keysyms_per_keycode: reply.keysyms_per_keycode(),
+            keysyms: reply.keysyms().to_vec(),
+            modifiers: modifiers.keycodes().to_vec(),
+        })
+    }
+
+    /// Returns all the keysyms bound to `keycode`, in any shift level. Keys
+    /// that aren't bound to anything produce nothing.
+    pub fn keysyms(&self, keycode: u8) -> impl Iterator<Item = Keysym> + '_ {
+        let per = usize::from(self.keysyms_per_keycode);
+        let start = usize::from(keycode.wrapping_sub(self.min_keycode)) * per;
+        self.keysyms
+            .get(start..start + per)
+            .unwrap_or(&[])
+            .iter()
+            .map(|&raw| Keysym::new(raw))
+            .filter(|&sym| sym != Keysym::NoSymbol)
+    }
+
+    /// Returns all the keycodes that produce `sym`, in any shift level.
+    pub fn keycodes(&self, sym: Keysym) -> impl Iterator<Item = u8> + '_ {
+        let per = usize::from(self.keysyms_per_keycode).max(1);
+        self.keysyms
+            .chunks(per)
+            .zip(self.min_keycode..=u8::MAX)
+            .filter(move |(syms, _)| syms.contains(&sym.raw()))
+            .map(|(_, keycode)| keycode)
+    }
+
+    /// Returns the modifiers that `keycode` is bound to, which is usually
+    /// none.
+    pub fn modifier_mask(&self, keycode: u8) -> x::KeyButMask {
+        let per = (self.modifiers.len() / MODIFIERS.len()).max(1);
+        self.modifiers
+            .chunks(per)
+            .zip(MODIFIERS)
+            .filter(|(keys, _)| keycode != 0 && keys.contains(&keycode))
+            .fold(x::KeyButMask::empty(), |mask, (_, m)| mask | m)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Group};
+    use serde::de::value::{Error, StrDeserializer};
+    use serde::Deserialize;
+    use xkeysym::{key, Keysym};
+
+    fn group(name: &str) -> Result<Group, Error> {
+        Group::deserialize(StrDeserializer::<Error>::new(name))
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(parse("Print").unwrap(), Keysym::new(key::Print));
+        assert_eq!(parse("a").unwrap(), Keysym::new(key::a));
+        assert_eq!(parse("A").unwrap(), Keysym::new(key::A));
+        assert_eq!(parse("BackSpace").unwrap(), Keysym::new(key::BackSpace));
+        assert_eq!(
+            parse("XF86AudioRaiseVolume").unwrap(),
+            Keysym::new(key::XF86_AudioRaiseVolume)
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("0xff61").unwrap(), Keysym::new(key::Print));
+        assert_eq!(parse("0x1008ff13").unwrap().raw(), 0x1008ff13);
+        assert_eq!(parse("U+20AC").unwrap(), Keysym::from_char('€'));
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("").is_err());
+        assert!(parse("NoSuchKey").is_err());
+        // Names are case sensitive, as they are in xev.
+        assert!(parse("print").is_err());
+        assert!(parse("XK_Print").is_err());
+        assert!(parse("0x").is_err());
+        assert!(parse("0xgg").is_err());
+        // Surrogates aren't characters.
+        assert!(parse("U+D800").is_err());
+        assert!(parse("U+").is_err());
+    }
+
+    #[test]
+    fn group_names() {
+        assert!(matches!(group("function"), Ok(Group::Function)));
+        assert!(matches!(group("media"), Ok(Group::Media)));
+        assert!(matches!(group("navigation"), Ok(Group::Navigation)));
+        assert!(matches!(group("editing"), Ok(Group::Editing)));
+        assert!(matches!(group("numpad"), Ok(Group::Numpad)));
+        assert!(group("Media").is_err());
+        assert!(group("arrows").is_err());
+        assert!(group("").is_err());
+    }
+
+    #[test]
+    fn group_members() {
+        let sym = |s| parse(s).unwrap();
+        assert!(Group::Function.contains(sym("F1")));
+        assert!(Group::Function.contains(sym("F12")));
+        assert!(!Group::Function.contains(sym("Escape")));
+        assert!(Group::Media.contains(sym("XF86AudioPlay")));
+        assert!(Group::Media.contains(sym("XF86Eject")));
+        assert!(!Group::Media.contains(sym("XF86MonBrightnessUp")));
+        assert!(Group::Navigation.contains(sym("Home")));
+        assert!(Group::Navigation.contains(sym("Next")));
+        assert!(Group::Navigation.contains(sym("KP_Left")));
+        assert!(!Group::Navigation.contains(sym("Return")));
+        assert!(Group::Editing.contains(sym("BackSpace")));
+        assert!(Group::Editing.contains(sym("XF86Paste")));
+        assert!(!Group::Editing.contains(sym("x")));
+        assert!(Group::Numpad.contains(sym("KP_5")));
+        assert!(Group::Numpad.contains(sym("KP_Enter")));
+        assert!(!Group::Numpad.contains(sym("5")));
+    }
+}
@@ -0,0 +1,69 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The workings of rxbanish, for programs that want to hide the X11 mouse
+//! pointer while you're typing without running rxbanish itself.
+//!
+//! There are two halves to this. A `Session` is a connection to the X server,
+//! with everything registered that we need to hear about. A `Banisher` takes
+//! the events that arrive on it and decides when to hide and show the pointer.
+//! Neither does any waiting, so that you can fit them into your own event
+//! loop; the session's file descriptors are there for polling, and the
+//! banisher will tell you when it next needs a nudge. (Or, with
+//! `Settings::sync_alarms`, `Banisher::schedule` can have the server do the
+//! nudging, with an event that goes to `handle_event` like any other.)
+//!
+//! ```no_run
+//! use rxbanish_core::{Banisher, Session, Settings};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let settings = Settings::default();
+//! let mut x = Session::connect(None, false, &settings)?;
+//! let mut banisher = Banisher::new(settings);
+//! banisher.start(&x)?;
+//! loop {
+//!     // A real program would wait on `x.fds()` along with whatever else it
+//!     // does, until no later than `banisher.deadline(&x)`.
+//!     while let Some(event) = x.poll_for_event()? {
+//!         if banisher.handle_event(&mut x, event)? {
+//!             return Ok(());
+//!         }
+//!     }
+//!     banisher.handle_timeout(&mut x)?;
+//! }
+//! # }
+//! ```
+//!
+//! If the server goes away, `lost_connection` will say so about the error, and
+//! you can carry on with a new `Session` and another call to
+//! `Banisher::start`.
+
+mod alarm;
+mod banisher;
+mod dpms;
+pub mod evdev;
+mod focus;
+pub mod hotkey;
+mod inhibit;
+mod input;
+mod instance;
+pub mod keysym;
+mod outputs;
+mod park;
+mod pointer;
+mod policy;
+mod record;
+mod screensaver;
+pub mod screenshot;
+mod session;
+mod settings;
+
+pub use banisher::Banisher;
+pub use input::{describe_devices, DeviceReport, Snoop};
+pub use policy::{Action, Change, Command, Policy, Reason, State, Stats};
+pub use session::{lost_connection, Session};
+pub use settings::{
+    Banish, Corner, DevicePolicy, HideOn, HideScope, Input, Method, Settings,
+    Suspension, WindowType,
+};
@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Keeping track of the monitors, by way of RandR.
+//!
+//! A screen is often spread across several monitors, and "the corner of the
+//! screen" can be off the edge of all of them, or on a different monitor from
+//! the one the user is looking at. So we ask RandR where each monitor's output
+//! sits on the screen, and ask again whenever it tells us that's changed, such
+//! as when a laptop is docked or undocked.
+//!
+//! Servers without RandR (or with it, but without any outputs lit up, like
+//! some virtual ones) get one pretend output per screen, covering all of it.
+
+use anyhow::Result;
+use log::debug;
+use xcb::{randr, x, Connection, Extension, Xid};
+
+use crate::pointer::Position;
+
+/// A monitor's place on its screen.
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// RandR's name for it, e.g. "DP-1".
+    pub name: String,
+    /// The root window of its screen.
+    pub root: x::Window,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Output {
+    /// Checks whether `pos` is on this output.
+    pub fn contains(&self, pos: Position) -> bool {
+        let (x, y) = (i32::from(pos.x), i32::from(pos.y));
+        let (left, top) = (i32::from(self.x), i32::from(self.y));
+        pos.root == self.root
+            && (left..left + i32::from(self.width)).contains(&x)
+            && (top..top + i32::from(self.height)).contains(&y)
+    }
+}
+
+/// Keeps track of the outputs on each screen.
+pub struct Outputs {
+    /// Whether we can ask RandR about outputs, which needs version 1.2.
+    randr: bool,
+    roots: Vec<x::Window>,
+    outputs: Vec<Output>,
+}
+
+impl Outputs {
+    /// Asks to hear about changes to the outputs on each of `roots`, and
+    /// finds out where they are now.
+    pub fn new(conn: &Connection, roots: &[x::Window]) -> Result<Self> {
+        let mut outputs = Self {
+            randr: false,
+            roots: roots.to_vec(),
+            outputs: vec![],
+        };
+        if conn.active_extensions().any(|e| e == Extension::RandR) {
+            let reply =
+                conn.wait_for_reply(conn.send_request(&randr::QueryVersion {
+                    major_version: 1,
+                    minor_version: 2,
+                }))?;
+            outputs.randr = (reply.major_version(), reply.minor_version())
+                >= (1, 2);
+        }
+        if outputs.randr {
+            for &root in roots {
+                conn.send_and_check_request(&randr::SelectInput {
+                    window: root,
+                    enable: randr::NotifyMask::SCREEN_CHANGE
+                        | randr::NotifyMask::CRTC_CHANGE
+                        | randr::NotifyMask::OUTPUT_CHANGE,
+                })?;
+            }
+        }
+        outputs.refresh(conn)?;
+        Ok(outputs)
+    }
+
+    /// Finds the output `pos` is on, if it's on one at all. It can fall
+    /// between them, when they're different sizes.
+    pub fn at(&self, pos: Position) -> Option<&Output> {
+        self.outputs.iter().find(|o| o.contains(pos))
+    }
+
+    /// Checks whether there's a lit-up output called `name`.
+    pub fn exists(&self, name: &str) -> bool {
+        self.outputs.iter().any(|o| o.name == name)
+    }
+
+    /// Finds out where the outputs are, from scratch.
+    pub fn refresh(&mut self, conn: &Connection) -> Result<()> {
+        self.outputs.clear();
+        for (i, (&root, screen)) in
+            self.roots.iter().zip(conn.get_setup().roots()).enumerate()
+        {
+            let found = if self.randr {
+                lit_outputs(conn, root)?
+            } else {
+                vec![]
+            };
+            if found.is_empty() {
+                // The setup's idea of the screen size never changes, which
+                // is only right without RandR, but it's the best we have.
+                self.outputs.push(Output {
+                    name: format!("screen-{i}"),
+                    root,
+                    x: 0,
+                    y: 0,
+                    width: screen.width_in_pixels(),
+                    height: screen.height_in_pixels(),
+                });
+            }
+            self.outputs.extend(found);
+        }
+        for o in &self.outputs {
+            debug!(
+                "output {} is {}x{}+{}+{}",
+                o.name, o.width, o.height, o.x, o.y
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Asks RandR for the outputs on `root` that are connected and showing
+/// something.
+fn lit_outputs(conn: &Connection, root: x::Window) -> Result<Vec<Output>> {
+    let resources = conn.wait_for_reply(conn.send_request(
+        &randr::GetScreenResourcesCurrent { window: root },
+    ))?;
+    let timestamp = resources.config_timestamp();
+    let mut outputs = vec![];
+    for &output in resources.outputs() {
+        let info = conn.wait_for_reply(conn.send_request(
+            &randr::GetOutputInfo {
+                output,
+                config_timestamp: timestamp,
+            },
+        ))?;
+        if info.connection() != randr::Connection::Connected
+            || info.crtc().is_none()
+        {
+            continue;
+        }
+        let crtc = conn.wait_for_reply(conn.send_request(
+            &randr::GetCrtcInfo {
+                crtc: info.crtc(),
+                config_timestamp: timestamp,
+            },
+        ))?;
+        outputs.push(Output {
+            name: String::from_utf8_lossy(info.name()).into_owned(),
+            root,
+            x: crtc.x(),
+            y: crtc.y(),
+            width: crtc.width(),
+            height: crtc.height(),
+        });
+    }
+    Ok(outputs)
+}
@@ -0,0 +1,95 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Keeping a banished pointer in its corner.
+//!
+//! Once we've moved the pointer into a corner, an accidental brush of the
+//! touchpad can still drag it back out across other windows, which is
+//! surprising when you can't see it (and downright annoying with
+//! focus-follows-mouse). So we fence it in with a pair of XFixes pointer
+//! barriers, which stop the pointer from crossing them in one direction. They
+//! only stop real movement, not warps, so we can still put the pointer back
+//! ourselves.
+
+use anyhow::Result;
+use xcb::{xfixes, Connection};
+use xfixes::BarrierDirections as Allow;
+
+use crate::outputs::Output;
+use crate::Corner;
+
+/// How big a box, in pixels, the pointer is allowed to wander around in.
+const PARKING_SPACE: u16 = 16;
+
+/// Barriers we've put up around a parked pointer.
+pub struct Parking {
+    barriers: [xfixes::Barrier; 2],
+}
+
+impl Parking {
+    /// Fences in `corner` of `output`.
+    pub fn new(
+        conn: &Connection,
+        output: &Output,
+        corner: Corner,
+    ) -> Result<Self> {
+        let left = matches!(corner, Corner::TopLeft | Corner::BottomLeft);
+        let top = matches!(corner, Corner::TopLeft | Corner::TopRight);
+
+        // Barriers are in screen coordinates, which are unsigned here, since
+        // no output starts off the top or left of the screen.
+        let (x0, y0) = (output.x as u16, output.y as u16);
+        let (x1, y1) = (x0 + output.width, y0 + output.height);
+
+        // Each barrier lets the pointer through towards the corner, but not
+        // away from it.
+        let (x, x_span, x_way) = if left {
+            let x = x0 + PARKING_SPACE;
+            (x, (x0, x), Allow::NEGATIVE_X)
+        } else {
+            let x = x1.saturating_sub(PARKING_SPACE);
+            (x, (x, x1), Allow::POSITIVE_X)
+        };
+        let (y, y_span, y_way) = if top {
+            let y = y0 + PARKING_SPACE;
+            (y, (y0, y), Allow::NEGATIVE_Y)
+        } else {
+            let y = y1.saturating_sub(PARKING_SPACE);
+            (y, (y, y1), Allow::POSITIVE_Y)
+        };
+        let lines = [
+            // The vertical fence, across the corner's row...
+            (x, y_span.0, x, y_span.1, x_way),
+            // ...and the horizontal one, across its column.
+            (x_span.0, y, x_span.1, y, y_way),
+        ];
+
+        let barriers = lines.map(|_| conn.generate_id());
+        for (&barrier, (x1, y1, x2, y2, directions)) in
+            barriers.iter().zip(lines)
+        {
+            conn.send_and_check_request(&xfixes::CreatePointerBarrier {
+                barrier,
+                window: output.root,
+                x1,
+                y1,
+                x2,
+                y2,
+                directions,
+                devices: &[],
+            })?;
+        }
+        Ok(Self { barriers })
+    }
+
+    /// Takes the barriers down again.
+    pub fn release(self, conn: &Connection) -> Result<()> {
+        for barrier in self.barriers {
+            conn.send_and_check_request(&xfixes::DeletePointerBarrier {
+                barrier,
+            })?;
+        }
+        Ok(())
+    }
+}
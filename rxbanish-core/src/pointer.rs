@@ -0,0 +1,408 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Doing things to the pointer: hiding it, showing it, and moving it around.
+
+use anyhow::Result;
+use xcb::{x, xfixes, xinput, Connection, Xid};
+
+use crate::focus::ignore_bad_window;
+use crate::outputs::Outputs;
+use crate::{Banish, Corner};
+
+/// Where the pointer is.
+#[derive(Copy, Clone, Debug)]
+pub struct Position {
+    pub root: x::Window,
+    pub x: i16,
+    pub y: i16,
+}
+
+impl Position {
+    /// Asks the server where the pointer is. `window` can be any window.
+    pub fn query(conn: &Connection, window: x::Window) -> Result<Self> {
+        let reply = conn.wait_for_reply(conn.send_request(&x::QueryPointer {
+            window,
+        }))?;
+        Ok(Self {
+            root: reply.root(),
+            x: reply.root_x(),
+            y: reply.root_y(),
+        })
+    }
+
+    /// Like `query`, for the master pointer `master`, which needn't be ours.
+    pub fn query_master(
+        conn: &Connection,
+        window: x::Window,
+        master: u16,
+    ) -> Result<Self> {
+        let reply =
+            conn.wait_for_reply(conn.send_request(&xinput::XiQueryPointer {
+                window,
+                device: xinput::Device::from_id(master),
+            }))?;
+        // These come in 16.16 fixed point, and we only care about pixels.
+        Ok(Self {
+            root: reply.root(),
+            x: (reply.root_x() >> 16) as i16,
+            y: (reply.root_y() >> 16) as i16,
+        })
+    }
+
+    /// Moves the pointer here.
+    pub fn warp(self, conn: &Connection) -> Result<()> {
+        conn.send_and_check_request(&x::WarpPointer {
+            src_window: x::Window::none(),
+            dst_window: self.root,
+            src_x: 0,
+            src_y: 0,
+            src_width: 0,
+            src_height: 0,
+            dst_x: self.x,
+            dst_y: self.y,
+        })?;
+        Ok(())
+    }
+
+    /// Checks whether the pointer has moved more than `limit` pixels from
+    /// here to `other`. Moving to another screen always counts.
+    pub fn moved_beyond(self, other: Position, limit: u16) -> bool {
+        let dx = i32::from(other.x) - i32::from(self.x);
+        let dy = i32::from(other.y) - i32::from(self.y);
+        let limit = i32::from(limit);
+        self.root != other.root || dx * dx + dy * dy > limit * limit
+    }
+}
+
+/// Undoes `hide`, given the `windows` and `banish` it was called with. A
+/// pointer that was only moved stays where it is.
+pub fn show(
+    conn: &Connection,
+    windows: &[x::Window],
+    banish: Banish,
+) -> Result<()> {
+    if banish != Banish::Corner {
+        for &window in windows {
+            // Destroying a window undoes hiding the pointer on it, so there's
+            // nothing to do for one that's gone.
+            ignore_bad_window(conn.send_and_check_request(
+                &xfixes::ShowCursor { window },
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Gets the pointer out of the way, as chosen by `banish`. XFixes hides it
+/// on `windows`, which are usually the root windows, but needn't be. If we
+/// moved it, returns where it was before.
+pub fn hide(
+    conn: &Connection,
+    windows: &[x::Window],
+    outputs: &Outputs,
+    banish: Banish,
+    corner: Corner,
+) -> Result<Option<Position>> {
+    let from = if banish != Banish::Hide {
+        warp_to_corner(conn, windows[0], outputs, corner)?
+    } else {
+        None
+    };
+    if banish != Banish::Corner {
+        for &window in windows {
+            // A window other than a root may have gone away by now, in which
+            // case there's nothing to hide the pointer on.
+            ignore_bad_window(conn.send_and_check_request(
+                &xfixes::HideCursor { window },
+            ))?;
+        }
+    }
+    Ok(from)
+}
+
+/// Checks whether the cursor the server is showing for the pointer has
+/// anything to see in it, as opposed to being a blank one like ours. This
+/// doesn't know about XFixes hiding the pointer, which leaves the cursor
+/// itself alone.
+pub fn cursor_visible(conn: &Connection) -> Result<bool> {
+    let reply =
+        conn.wait_for_reply(conn.send_request(&xfixes::GetCursorImage {}))?;
+    // The image is ARGB, one pixel to a word.
+    Ok(reply.cursor_image().iter().any(|pixel| pixel >> 24 != 0))
+}
+
+/// Makes an invisible cursor, for hiding one master pointer at a time when
+/// there are several (MPX), since XFixes can only hide all of them together,
+/// and for servers where XFixes can't hide the pointer at all. `root` can be
+/// any root window.
+pub fn blank_cursor(conn: &Connection, root: x::Window) -> Result<x::Cursor> {
+    // A new pixmap's contents are undefined, so clear it before using it as
+    // the cursor's mask.
+    let pixmap: x::Pixmap = conn.generate_id();
+    conn.send_and_check_request(&x::CreatePixmap {
+        depth: 1,
+        pid: pixmap,
+        drawable: x::Drawable::Window(root),
+        width: 1,
+        height: 1,
+    })?;
+    let gc: x::Gcontext = conn.generate_id();
+    conn.send_and_check_request(&x::CreateGc {
+        cid: gc,
+        drawable: x::Drawable::Pixmap(pixmap),
+        value_list: &[x::Gc::Foreground(0)],
+    })?;
+    conn.send_and_check_request(&x::PolyFillRectangle {
+        drawable: x::Drawable::Pixmap(pixmap),
+        gc,
+        rectangles: &[x::Rectangle {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        }],
+    })?;
+    let cursor: x::Cursor = conn.generate_id();
+    conn.send_and_check_request(&x::CreateCursor {
+        cid: cursor,
+        source: pixmap,
+        mask: pixmap,
+        fore_red: 0,
+        fore_green: 0,
+        fore_blue: 0,
+        back_red: 0,
+        back_green: 0,
+        back_blue: 0,
+        x: 0,
+        y: 0,
+    })?;
+    conn.send_and_check_request(&x::FreeGc { gc })?;
+    conn.send_and_check_request(&x::FreePixmap { pixmap })?;
+    Ok(cursor)
+}
+
+/// Hides a pointer by giving the window under it `blank` as its cursor.
+/// Returns the window, for `uncloak`. `root` can be any root window.
+///
+/// With XInput 2, `master` picks out a master pointer, and only that one is
+/// hidden; the window's own cursor stays as it was, underneath. Without it,
+/// `master` is `None`, and we have to replace the window's cursor, which
+/// `uncloak` can't put back; the window gets its parent's cursor until its
+/// owner next sets one.
+///
+/// Windows further up don't get the cursor, so moving the pointer onto
+/// another window would show it again, but moving it is what brings it back
+/// anyway.
+pub fn cloak(
+    conn: &Connection,
+    root: x::Window,
+    blank: x::Cursor,
+    master: Option<u16>,
+) -> Result<x::Window> {
+    // Finds the root window the pointer is on, and the child of `window`
+    // it's in, if any.
+    let query = |window| -> Result<(x::Window, x::Window)> {
+        Ok(match master {
+            Some(master) => {
+                let reply = conn.wait_for_reply(conn.send_request(
+                    &xinput::XiQueryPointer {
+                        window,
+                        device: xinput::Device::from_id(master),
+                    },
+                ))?;
+                (reply.root(), reply.child())
+            }
+            None => {
+                let reply = conn.wait_for_reply(
+                    conn.send_request(&x::QueryPointer { window }),
+                )?;
+                (reply.root(), reply.child())
+            }
+        })
+    };
+    // The cursor comes from the innermost window the pointer is in, so dig
+    // down to it.
+    let mut window = query(root)?.0;
+    loop {
+        let (_, child) = query(window)?;
+        if child.is_none() {
+            break;
+        }
+        window = child;
+    }
+    set_cursor(conn, window, blank, master)?;
+    Ok(window)
+}
+
+/// Like `cloak`, but gives `blank` to every top-level window on each of
+/// `roots`, and the roots themselves, as well as the window under the
+/// pointer, for `Method::WindowCursor`. Returns all the windows, for
+/// `uncloak`.
+pub fn cloak_all(
+    conn: &Connection,
+    roots: &[x::Window],
+    blank: x::Cursor,
+    master: Option<u16>,
+) -> Result<Vec<x::Window>> {
+    let mut windows = vec![cloak(conn, roots[0], blank, master)?];
+    for &root in roots {
+        let tree = conn
+            .wait_for_reply(conn.send_request(&x::QueryTree { window: root }))?;
+        for &window in std::iter::once(&root).chain(tree.children()) {
+            if !windows.contains(&window) {
+                set_cursor(conn, window, blank, master)?;
+                windows.push(window);
+            }
+        }
+    }
+    Ok(windows)
+}
+
+/// Hides the pointer the way unclutter does, by putting a tiny window with
+/// `blank` for its cursor right under it, for `Method::CoverWindow`. Returns
+/// the window, for `uncover`. `root` can be any root window.
+///
+/// The window only takes input, so it can't be seen, but it does catch
+/// clicks, and the window it covers sees the pointer leave.
+pub fn cover(
+    conn: &Connection,
+    root: x::Window,
+    blank: x::Cursor,
+) -> Result<x::Window> {
+    let reply = conn
+        .wait_for_reply(conn.send_request(&x::QueryPointer { window: root }))?;
+    let window: x::Window = conn.generate_id();
+    conn.send_and_check_request(&x::CreateWindow {
+        depth: x::COPY_FROM_PARENT as u8,
+        wid: window,
+        parent: reply.root(),
+        x: reply.root_x() - 1,
+        y: reply.root_y() - 1,
+        width: 3,
+        height: 3,
+        border_width: 0,
+        class: x::WindowClass::InputOnly,
+        visual: x::COPY_FROM_PARENT,
+        // Override-redirect keeps the window manager's hands off it.
+        value_list: &[
+            x::Cw::OverrideRedirect(true),
+            x::Cw::Cursor(blank),
+        ],
+    })?;
+    conn.send_and_check_request(&x::MapWindow { window })?;
+    Ok(window)
+}
+
+/// Undoes `cover`.
+pub fn uncover(conn: &Connection, window: x::Window) -> Result<()> {
+    conn.send_and_check_request(&x::DestroyWindow { window })?;
+    Ok(())
+}
+
+/// Undoes `cloak` for `master` on `window`.
+pub fn uncloak(
+    conn: &Connection,
+    window: x::Window,
+    master: Option<u16>,
+) -> Result<()> {
+    set_cursor(conn, window, x::Cursor::none(), master)
+}
+
+/// Gives `window` `cursor`, for `master` if we have XInput 2, or for everyone
+/// if not. Windows come and go, so it's not an error if `window` already has.
+fn set_cursor(
+    conn: &Connection,
+    window: x::Window,
+    cursor: x::Cursor,
+    master: Option<u16>,
+) -> Result<()> {
+    let cookie = match master {
+        Some(master) => conn.send_request_checked(&xinput::XiChangeCursor {
+            window,
+            cursor,
+            device: xinput::Device::from_id(master),
+        }),
+        None => conn.send_request_checked(&x::ChangeWindowAttributes {
+            window,
+            value_list: &[x::Cw::Cursor(cursor)],
+        }),
+    };
+    match conn.check_request(cookie) {
+        Ok(()) => Ok(()),
+        Err(xcb::ProtocolError::X(x::Error::Window(_), _)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Checks whether some other client has grabbed the pointer, by trying to
+/// grab it ourselves. X doesn't have a way to just ask. `root` can be any root
+/// window.
+///
+/// This isn't invisible: while we hold the grab, however briefly, other
+/// clients get `LeaveNotify` and `EnterNotify` events, and any that try to
+/// grab the pointer themselves find it already grabbed.
+pub fn grabbed(conn: &Connection, root: x::Window) -> Result<bool> {
+    let reply = conn.wait_for_reply(conn.send_request(&x::GrabPointer {
+        owner_events: false,
+        grab_window: root,
+        event_mask: x::EventMask::empty(),
+        pointer_mode: x::GrabMode::Async,
+        keyboard_mode: x::GrabMode::Async,
+        confine_to: x::Window::none(),
+        cursor: x::Cursor::none(),
+        time: x::CURRENT_TIME,
+    }))?;
+    match reply.status() {
+        x::GrabStatus::Success => {
+            conn.send_and_check_request(&x::UngrabPointer {
+                time: x::CURRENT_TIME,
+            })?;
+            Ok(false)
+        }
+        x::GrabStatus::AlreadyGrabbed | x::GrabStatus::Frozen => Ok(true),
+        // These would be odd, but don't tell us anything about other clients.
+        x::GrabStatus::InvalidTime | x::GrabStatus::NotViewable => Ok(false),
+    }
+}
+
+/// Checks whether any of the pointer's main buttons are held down, as they
+/// are partway through a drag. `window` can be any window. The wheel's
+/// "buttons" only go down for an instant, so they don't count.
+pub fn buttons_held(conn: &Connection, window: x::Window) -> Result<bool> {
+    let reply =
+        conn.wait_for_reply(conn.send_request(&x::QueryPointer { window }))?;
+    let buttons = x::KeyButMask::BUTTON1
+        | x::KeyButMask::BUTTON2
+        | x::KeyButMask::BUTTON3;
+    Ok(reply.mask().intersects(buttons))
+}
+
+/// Moves the pointer into `corner` of whichever output it's on, returning
+/// where it was. `window` can be any window.
+fn warp_to_corner(
+    conn: &Connection,
+    window: x::Window,
+    outputs: &Outputs,
+    corner: Corner,
+) -> Result<Option<Position>> {
+    let from = Position::query(conn, window)?;
+    let root = from.root;
+    let Some(output) = outputs.at(from) else {
+        return Ok(None);
+    };
+    // Coordinates are signed, but no screen is big enough for that to matter.
+    let left = output.x;
+    let top = output.y;
+    let right = left + output.width.saturating_sub(1) as i16;
+    let bottom = top + output.height.saturating_sub(1) as i16;
+    let (x, y) = match corner {
+        Corner::TopLeft => (left, top),
+        Corner::TopRight => (right, top),
+        Corner::BottomLeft => (left, bottom),
+        Corner::BottomRight => (right, bottom),
+    };
+    Position { root, x, y }.warp(conn)?;
+    Ok(Some(from))
+}
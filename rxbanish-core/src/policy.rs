@@ -0,0 +1,609 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The part of deciding when to hide the pointer that doesn't depend on X.
+//!
+//! A `Policy` keeps track of what the user's been doing, and says what should
+//! become of the pointer: keystrokes hide it once there have been enough of
+//! them in a row, pointer activity brings it back, the timeouts run out, and
+//! pauses and suspensions keep it showing. It also keeps the counts and the
+//! record of changes that everyone else gets to see.
+//!
+//! It doesn't know how to hide anything itself. The `Banisher` drives one
+//! from X's events, and does the hiding with X; without X, the Wayland side
+//! of rxbanish drives one from the input devices, and asks the compositor.
+//! Either way, the same settings mean the same thing.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use serde::Serialize;
+use xcb::x;
+
+use crate::{DevicePolicy, HideOn, Settings};
+
+/// The longest pause between keystrokes that still counts as typing them in a
+/// row, for `Settings::min_keystrokes`.
+const KEYSTROKE_GAP: Duration = Duration::from_secs(1);
+
+/// How many `Change`s we keep for `take_changes`, in case nobody's taking
+/// them.
+const MAX_CHANGES: usize = 64;
+
+/// Things we can be asked to do.
+#[derive(Clone, Debug)]
+pub enum Command {
+    /// Stop hiding the pointer (and show it if it's hidden) until resumed.
+    Pause,
+    /// Undo a previous pause.
+    Resume,
+    /// Pause if running, resume if paused.
+    Toggle,
+    /// Hide the pointer right now, as if the user had typed something.
+    Hide,
+    /// Show the pointer right now, as if the user had moved the mouse.
+    Show,
+    /// Get back in step with the X server after something may have happened
+    /// behind our back, like the machine going to sleep. This shows the
+    /// pointer, and registers for input events all over again.
+    Resync,
+    /// Switch to another profile of settings, by name, or back to the usual
+    /// ones. Settings are the caller's business, so all the policy does with
+    /// this is hand it back through `take_profile_switch`.
+    Profile(Option<String>),
+}
+
+/// Running totals of what we've been up to since we started, mostly so that
+/// people can check whether their settings are doing anything.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Stats {
+    /// How many times we've hidden the pointer.
+    pub hides: u64,
+    /// How many times we've shown it again.
+    pub shows: u64,
+    /// How many times the focused window has made us stay out of the way.
+    pub suspensions: u64,
+    /// Keystrokes that didn't hide the pointer, because of ignored keys or
+    /// groups.
+    pub ignored_keys: u64,
+    /// Keystrokes that didn't hide the pointer, because of ignored modifiers.
+    pub ignored_mods: u64,
+    /// Input events we've seen, by device ID.
+    pub events: BTreeMap<u16, u64>,
+}
+
+/// A time we hid or showed the pointer, for anyone who wants to react to it.
+#[derive(Clone, Debug)]
+pub struct Change {
+    /// Whether we hid the pointer, as opposed to showing it.
+    pub hidden: bool,
+    /// Why, in the same words as the logs: "key," "motion," "idle-timeout,"
+    /// and so on.
+    pub reason: &'static str,
+    /// The name of the input device responsible, if there was one.
+    pub device: Option<String>,
+    /// The class of the focused window at the time, if we know it.
+    pub class: Option<String>,
+}
+
+/// Where the pointer is, or should be.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum State {
+    Hidden,
+    Shown,
+}
+
+impl State {
+    /// Names the state, for `Settings::trace_events`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            State::Hidden => "hidden",
+            State::Shown => "shown",
+        }
+    }
+}
+
+/// Why the pointer might be changing state, for the logs.
+#[derive(Copy, Clone, Debug)]
+pub enum Reason {
+    Key,
+    Motion,
+    IdleTimeout,
+    MaxHidden,
+    Command,
+    Focus,
+    Suspended,
+    Start,
+    Wake,
+    Cursor,
+    Exit,
+    Other,
+}
+
+impl Reason {
+    /// The code for this reason that we put in the logs.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Key => "key",
+            Reason::Motion => "motion",
+            Reason::IdleTimeout => "idle-timeout",
+            Reason::MaxHidden => "max-hidden",
+            Reason::Command => "command",
+            Reason::Focus => "focus",
+            Reason::Suspended => "suspended",
+            Reason::Start => "start",
+            Reason::Wake => "wake",
+            Reason::Cursor => "cursor",
+            Reason::Exit => "exit",
+            Reason::Other => "other",
+        }
+    }
+}
+
+/// Something the user did, as far as the policy cares.
+#[derive(Copy, Clone, Debug)]
+pub enum Action {
+    /// A key going down, or coming back up, and whether it's only the
+    /// keyboard repeating it.
+    Key { pressed: bool, repeat: bool },
+    /// The pointer moving, or a pen coming into range.
+    Motion,
+    /// A button going down or coming back up.
+    Button,
+    /// A turn of a scroll wheel.
+    Scroll,
+}
+
+/// Decides what should become of the pointer; see the module docs.
+pub struct Policy {
+    /// Whether someone has asked us to stop.
+    paused: bool,
+    /// How many keystrokes the user has typed in a row, for
+    /// `Settings::min_keystrokes`, when the last of them was, and on which
+    /// seat (the master pointer's keyboard, under X).
+    keystrokes: u32,
+    last_keystroke: Instant,
+    typist: Option<u16>,
+    stats: Stats,
+    /// Recent changes, oldest first, until someone takes them.
+    changes: VecDeque<Change>,
+    /// A profile someone has asked for, until someone takes it.
+    profile_switch: Option<Option<String>>,
+    /// Where the pointer is.
+    state: State,
+    /// Time of the last pointer movement or button, for the idle timeout.
+    last_motion: Instant,
+    /// Time the pointer was last hidden, for the maximum hidden duration.
+    hidden_since: Instant,
+    /// Whether we were keeping out of the way last time round, so we can
+    /// count suspensions.
+    was_suspended: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            paused: false,
+            keystrokes: 0,
+            last_keystroke: now,
+            typist: None,
+            stats: Stats::default(),
+            changes: VecDeque::new(),
+            profile_switch: None,
+            state: State::Shown,
+            last_motion: now,
+            hidden_since: now,
+            was_suspended: false,
+        }
+    }
+}
+
+impl Policy {
+    /// Starts over with the pointer showing, as it is on a freshly connected
+    /// server. Pauses, counts, and anything waiting to be taken carry on.
+    pub fn restart(&mut self) {
+        let now = Instant::now();
+        self.state = State::Shown;
+        self.last_motion = now;
+        self.hidden_since = now;
+        self.was_suspended = false;
+    }
+
+    /// Whether someone has paused us.
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Where the pointer is.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether the pointer is currently hidden.
+    pub fn hidden(&self) -> bool {
+        self.state == State::Hidden
+    }
+
+    /// Whether we were keeping out of the way, as of the last `settle`.
+    pub fn was_suspended(&self) -> bool {
+        self.was_suspended
+    }
+
+    /// Our running totals.
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    pub(crate) fn stats_mut(&mut self) -> &mut Stats {
+        &mut self.stats
+    }
+
+    /// Collects the most recent request to switch profiles, if there's been
+    /// one since last time. `None` inside means back to the usual settings.
+    pub fn take_profile_switch(&mut self) -> Option<Option<String>> {
+        self.profile_switch.take()
+    }
+
+    /// Collects the times we've hidden or shown the pointer since last time,
+    /// oldest first. Only the most recent few are kept if nobody asks.
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        self.changes.drain(..).collect()
+    }
+
+    /// Checks whether `action`, on a device with policy `device`, is anything
+    /// to us at all. `touch` says whether the device is a touchscreen.
+    pub fn admits(
+        settings: &Settings,
+        device: DevicePolicy,
+        action: Action,
+        touch: bool,
+    ) -> bool {
+        if device == DevicePolicy::Ignore || (touch && !settings.touch_reveals)
+        {
+            return false;
+        }
+        match action {
+            // Only one end of a keystroke counts. By default, that's the
+            // release, because otherwise we can't tell e.g. tapping shift
+            // from holding it for a click.
+            Action::Key { pressed, repeat } => {
+                device.hides()
+                    && pressed == (settings.hide_on == HideOn::Press)
+                    && !(repeat && settings.ignore_repeat)
+            }
+            Action::Scroll if !settings.scroll_reveals => false,
+            Action::Motion | Action::Button | Action::Scroll => {
+                device.reveals()
+            }
+        }
+    }
+
+    /// Takes in a keystroke on `seat`, with `mods` held, including `own`,
+    /// the modifiers that the key itself is. Returns `Some` if it means a
+    /// change, which it will once there have been enough of them, unless the
+    /// modifiers say it's a shortcut.
+    pub fn keystroke(
+        &mut self,
+        settings: &Settings,
+        seat: u16,
+        mods: x::KeyButMask,
+        own: x::KeyButMask,
+    ) -> Option<State> {
+        if mods.intersects(settings.ignored_mods)
+            || (mods - own).intersects(settings.chord_mods)
+        {
+            // A shortcut, if it's a modifier held with some other key.
+            self.stats.ignored_mods += 1;
+            return None;
+        }
+        let now = Instant::now();
+        // Keystrokes only count as a run if they're all on the same keyboard,
+        // or seat.
+        if now - self.last_keystroke > KEYSTROKE_GAP
+            || self.typist != Some(seat)
+        {
+            self.keystrokes = 0;
+        }
+        self.typist = Some(seat);
+        self.keystrokes = self.keystrokes.saturating_add(1);
+        self.last_keystroke = now;
+        (self.keystrokes >= settings.min_keystrokes).then_some(State::Hidden)
+    }
+
+    /// Takes in pointer activity, `action`, on `seat`, whose pointer is in
+    /// `state`; the pointer is `ours` if it's the one this policy looks after.
+    /// Returns the state it should be in now.
+    pub fn pointer(
+        &mut self,
+        settings: &Settings,
+        seat: u16,
+        ours: bool,
+        action: Action,
+        state: State,
+    ) -> State {
+        // Movement doesn't bring the pointer back if the user has asked us to
+        // wait for a button.
+        if let Action::Motion = action {
+            if !settings.motion_reveals && state == State::Hidden {
+                return state;
+            }
+        }
+        // That breaks any run of keystrokes on its keyboard, and restarts
+        // the idle clock.
+        if ours {
+            self.last_motion = Instant::now();
+        }
+        if self.typist.is_none_or(|t| t == seat) {
+            self.keystrokes = 0;
+        }
+        State::Shown
+    }
+
+    /// Forgets about `seat`'s keystrokes, since it's gone away.
+    pub fn forget_seat(&mut self, seat: u16) {
+        if self.typist == Some(seat) {
+            self.typist = None;
+            self.keystrokes = 0;
+        }
+    }
+
+    /// Carries out the part of `cmd` that doesn't involve the pointer,
+    /// returning the state the pointer should be in afterwards. `Resync` is
+    /// the caller's business.
+    pub fn command(&mut self, cmd: Command) -> State {
+        match cmd {
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::Toggle => self.paused = !self.paused,
+            Command::Hide => return State::Hidden,
+            Command::Show => return State::Shown,
+            Command::Resync => (),
+            Command::Profile(name) => self.profile_switch = Some(name),
+        }
+        self.state
+    }
+
+    /// Works out when the timeout for the state we're in runs out, if it
+    /// has one. Each timeout only applies in one of the two states, so
+    /// there's never more than one to worry about.
+    pub fn timeout(&self, settings: &Settings) -> Option<Instant> {
+        match self.state {
+            State::Shown => settings.idle_timeout.map(|t| self.last_motion + t),
+            State::Hidden => {
+                settings.max_hidden.map(|t| self.hidden_since + t)
+            }
+        }
+    }
+
+    /// Works out when the timeout next needs checking on, if ever. While
+    /// we're paused or `suspended`, the pointer stays put, so there's no need.
+    pub fn deadline(
+        &self,
+        settings: &Settings,
+        suspended: bool,
+    ) -> Option<Instant> {
+        if self.paused || suspended {
+            return None;
+        }
+        self.timeout(settings)
+    }
+
+    /// Says what the pointer should do if its timeout has run out by `now`.
+    pub fn expired(
+        &self,
+        settings: &Settings,
+        now: Instant,
+    ) -> Option<(State, Reason)> {
+        if self.timeout(settings).is_none_or(|d| d > now) {
+            return None;
+        }
+        Some(match self.state {
+            // The pointer has been sitting still for long enough.
+            State::Shown => (State::Hidden, Reason::IdleTimeout),
+            // The pointer has been hidden for long enough.
+            State::Hidden => (State::Shown, Reason::MaxHidden),
+        })
+    }
+
+    /// Notes that the user was last active at `since`, going by someone who
+    /// sees more input than we do, if that's later than we thought.
+    pub fn active_since(&mut self, since: Instant) {
+        self.last_motion = self.last_motion.max(since);
+    }
+
+    /// Works out what the pointer should really do when something says it
+    /// should be in `target`, for `reason`: if we've been paused, or are
+    /// `suspended` for whatever reason, it shows, so that we don't leave it
+    /// hidden behind us.
+    pub fn settle(
+        &mut self,
+        target: State,
+        reason: Reason,
+        suspended: bool,
+    ) -> (State, Reason) {
+        if suspended && !self.was_suspended {
+            self.stats.suspensions += 1;
+        }
+        self.was_suspended = suspended;
+        if !(self.paused || suspended) {
+            return (target, reason);
+        }
+        match target {
+            State::Hidden => (State::Shown, Reason::Suspended),
+            State::Shown => (State::Shown, reason),
+        }
+    }
+
+    /// Moves the pointer to `target`, for `reason`, as far as the books are
+    /// concerned, on account of the input device called `device`, while an
+    /// application of `class` has the focus. Returns whether the pointer
+    /// needs hiding (`Some(true)`) or showing (`Some(false)`) to match, if
+    /// either; that's the caller's job, and on a dry run, it's best left.
+    pub fn commit(
+        &mut self,
+        settings: &Settings,
+        target: State,
+        reason: Reason,
+        device: Option<&str>,
+        class: Option<&str>,
+    ) -> Option<bool> {
+        if target == self.state {
+            return None;
+        }
+        let hidden = target == State::Hidden;
+        let reason = reason.as_str();
+        match (settings.dry_run, hidden) {
+            (true, true) => info!(reason, device; "would hide pointer"),
+            (true, false) => info!(reason, device; "would show pointer"),
+            (false, true) => debug!(reason, device; "hiding pointer"),
+            (false, false) => debug!(reason, device; "showing pointer"),
+        }
+        if self.changes.len() == MAX_CHANGES {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(Change {
+            hidden,
+            reason,
+            device: device.map(str::to_string),
+            class: class.map(str::to_string),
+        });
+        let now = Instant::now();
+        if hidden {
+            self.stats.hides += 1;
+            self.hidden_since = now;
+        } else {
+            self.stats.shows += 1;
+            // Restart the idle clock whenever the pointer comes back, or the
+            // idle timeout would hide it again immediately if it came back
+            // for some reason other than moving.
+            self.last_motion = now;
+        }
+        self.state = target;
+        Some(hidden)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Command, Policy, Reason, State};
+    use crate::{DevicePolicy, Settings};
+    use xcb::x::KeyButMask;
+
+    const NONE: KeyButMask = KeyButMask::empty();
+
+    /// Types a keystroke on `seat`, with `mods` held, and commits whatever
+    /// comes of it.
+    fn type_key(p: &mut Policy, s: &Settings, seat: u16, mods: KeyButMask) {
+        if let Some(target) = p.keystroke(s, seat, mods, NONE) {
+            let (target, reason) = p.settle(target, Reason::Key, false);
+            p.commit(s, target, reason, None, None);
+        }
+    }
+
+    #[test]
+    fn admits_one_end_of_keystrokes() {
+        let s = Settings::default();
+        let press = Action::Key { pressed: true, repeat: false };
+        let release = Action::Key { pressed: false, repeat: false };
+        assert!(!Policy::admits(&s, DevicePolicy::Both, press, false));
+        assert!(Policy::admits(&s, DevicePolicy::Both, release, false));
+        assert!(!Policy::admits(&s, DevicePolicy::Reveal, release, false));
+        assert!(!Policy::admits(&s, DevicePolicy::Ignore, release, false));
+    }
+
+    #[test]
+    fn admits_pointer_activity() {
+        let mut s = Settings::default();
+        assert!(Policy::admits(&s, DevicePolicy::Both, Action::Motion, false));
+        assert!(!Policy::admits(&s, DevicePolicy::Hide, Action::Button, false));
+        s.scroll_reveals = false;
+        assert!(!Policy::admits(&s, DevicePolicy::Both, Action::Scroll, false));
+        s.touch_reveals = false;
+        assert!(!Policy::admits(&s, DevicePolicy::Both, Action::Motion, true));
+    }
+
+    #[test]
+    fn keystrokes_hide_after_a_run() {
+        let s = Settings {
+            min_keystrokes: 3,
+            ..Settings::default()
+        };
+        let mut p = Policy::default();
+        type_key(&mut p, &s, 0, NONE);
+        type_key(&mut p, &s, 0, NONE);
+        assert!(!p.hidden());
+        // Another seat starts a run of its own.
+        type_key(&mut p, &s, 1, NONE);
+        assert!(!p.hidden());
+        type_key(&mut p, &s, 1, NONE);
+        type_key(&mut p, &s, 1, NONE);
+        assert!(p.hidden());
+        assert_eq!(p.stats().hides, 1);
+        assert_eq!(p.take_changes().len(), 1);
+    }
+
+    #[test]
+    fn pointer_breaks_a_run() {
+        let s = Settings {
+            min_keystrokes: 2,
+            ..Settings::default()
+        };
+        let mut p = Policy::default();
+        type_key(&mut p, &s, 0, NONE);
+        let target = p.pointer(&s, 0, true, Action::Button, State::Shown);
+        assert_eq!(target, State::Shown);
+        type_key(&mut p, &s, 0, NONE);
+        assert!(!p.hidden());
+    }
+
+    #[test]
+    fn shortcuts_dont_hide() {
+        let s = Settings {
+            chord_mods: KeyButMask::CONTROL,
+            ..Settings::default()
+        };
+        let mut p = Policy::default();
+        type_key(&mut p, &s, 0, KeyButMask::CONTROL);
+        assert!(!p.hidden());
+        assert_eq!(p.stats().ignored_mods, 1);
+        // Control on its own is only a modifier being let go of.
+        let own = KeyButMask::CONTROL;
+        assert!(p.keystroke(&s, 0, own, own).is_some());
+    }
+
+    #[test]
+    fn pausing_shows_the_pointer() {
+        let s = Settings::default();
+        let mut p = Policy::default();
+        type_key(&mut p, &s, 0, NONE);
+        assert!(p.hidden());
+        let target = p.command(Command::Pause);
+        let (target, reason) = p.settle(target, Reason::Command, false);
+        assert_eq!(p.commit(&s, target, reason, None, None), Some(false));
+        type_key(&mut p, &s, 0, NONE);
+        assert!(!p.hidden());
+        assert_eq!(p.deadline(&s, false), None);
+    }
+
+    #[test]
+    fn suspensions_are_counted_once() {
+        let mut p = Policy::default();
+        let settled = p.settle(State::Hidden, Reason::Key, true);
+        assert!(matches!(settled, (State::Shown, Reason::Suspended)));
+        p.settle(State::Hidden, Reason::Key, true);
+        p.settle(State::Shown, Reason::Focus, false);
+        assert_eq!(p.stats().suspensions, 1);
+    }
+
+    #[test]
+    fn profile_switches_are_handed_back() {
+        let mut p = Policy::default();
+        let name = Some("work".to_string());
+        assert_eq!(p.command(Command::Profile(name.clone())), State::Shown);
+        assert_eq!(p.take_profile_switch(), Some(name));
+        assert_eq!(p.take_profile_switch(), None);
+    }
+}
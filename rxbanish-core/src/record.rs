@@ -0,0 +1,129 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Listening to input by way of the RECORD extension, for servers that won't
+//! let us snoop with XInput.
+//!
+//! RECORD hands over copies of the core input events the server sees, on a
+//! connection of their own, since the request that starts them flowing never
+//! finishes. Core events don't say which device they came from, so they're
+//! put down to the core pointer and keyboard.
+//!
+//! Rather than teach the banisher a third kind of event, we pass these off as
+//! XInput 1 device events, which it already knows what to do with.
+
+use std::os::fd::{AsRawFd, RawFd};
+
+use anyhow::{bail, Result};
+use log::debug;
+use xcb::{record, Connection, Event, Extension};
+
+use crate::input::{
+    self, CORE_KEYBOARD, CORE_POINTER, EVENT_SIZE, KEY_PRESS, KEY_RELEASE,
+    MOTION_NOTIFY,
+};
+
+/// What RECORD calls data it's intercepted on its way out of the server.
+const FROM_SERVER: u8 = 0;
+
+/// A RECORD context that's recording core input events.
+pub struct Recorder {
+    /// The connection the recorded events arrive on, which can't be used for
+    /// anything else.
+    data: Connection,
+    cookie: record::EnableContextCookie,
+    /// Where XInput's events start, for passing events off as its own.
+    xinput_base: u8,
+}
+
+impl Recorder {
+    /// Starts recording input on the server on `display`, which `conn` is
+    /// already connected to.
+    pub fn new(conn: &Connection, display: Option<&str>) -> Result<Self> {
+        if !conn.active_extensions().any(|e| e == Extension::Record) {
+            bail!("the X server doesn't have the RECORD extension");
+        }
+        conn.wait_for_reply(conn.send_request(&record::QueryVersion {
+            major_version: 1,
+            minor_version: 13,
+        }))?;
+        let xinput_base = input::first_event(conn)?;
+
+        // Every key, button, and bit of motion, from anyone.
+        let none = record::Range8 { first: 0, last: 0 };
+        let none_ext = record::ExtRange {
+            major: none,
+            minor: record::Range16 { first: 0, last: 0 },
+        };
+        let range = record::Range::new(
+            none,
+            none,
+            none_ext,
+            none_ext,
+            none,
+            record::Range8 {
+                first: KEY_PRESS,
+                last: MOTION_NOTIFY,
+            },
+            none,
+            false,
+            false,
+        );
+        let context = conn.generate_id();
+        conn.send_and_check_request(&record::CreateContext {
+            context,
+            element_header: 0,
+            client_specs: &[record::Cs::AllClients as u32],
+            ranges: &[range],
+        })?;
+
+        let (data, _) = Connection::connect_with_extensions(
+            display,
+            &[Extension::Record],
+            &[],
+        )?;
+        let cookie = data.send_request(&record::EnableContext { context });
+        data.flush()?;
+        debug!("recording input with RECORD");
+        Ok(Self {
+            data,
+            cookie,
+            xinput_base,
+        })
+    }
+
+    /// Collects whatever's been recorded since last time, without blocking,
+    /// as XInput 1 events that `conn` can make sense of.
+    pub fn poll(&self, conn: &Connection) -> Result<Vec<Event>> {
+        let mut events = vec![];
+        while let Some(reply) = self.data.poll_for_reply(&self.cookie) {
+            let reply = reply?;
+            if reply.category() != FROM_SERVER {
+                continue;
+            }
+            for wire in reply.data().chunks_exact(EVENT_SIZE) {
+                let wire: &[u8; EVENT_SIZE] = wire.try_into().unwrap();
+                // Core events don't say which device they came from.
+                let device = match wire[0] & 0x7f {
+                    KEY_PRESS | KEY_RELEASE => CORE_KEYBOARD,
+                    _ => CORE_POINTER,
+                };
+                events.extend(input::device_event(
+                    conn,
+                    self.xinput_base,
+                    wire,
+                    device,
+                ));
+            }
+        }
+        self.data.has_error()?;
+        Ok(events)
+    }
+}
+
+impl AsRawFd for Recorder {
+    fn as_raw_fd(&self) -> RawFd {
+        self.data.as_raw_fd()
+    }
+}
@@ -0,0 +1,101 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Staying out of the way while the screen saver is on.
+//!
+//! The MIT-SCREEN-SAVER extension tells us when the server's screen saver
+//! comes on and goes off. While it's on, the screen isn't showing anything the
+//! user is working on, so we show the pointer and leave it alone, and once
+//! it's off again we carry on where we left off. Screen lockers are often
+//! started by the screen saver coming on (that's what xss-lock does), so this
+//! covers those as well.
+//!
+//! The same extension also knows how long it's been since the user last
+//! touched any input device, which is what the screen saver's own timeout goes
+//! by. We only hear about the input we've asked for, and can miss some of
+//! that, so the idle timeout asks the server too before hiding the pointer.
+//!
+//! Servers without the extension never have the screen saver on, as far as
+//! we're concerned, and leave us to keep track of idle time ourselves.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Result;
+use xcb::{screensaver, x, Connection, Extension};
+
+/// Keeps track of which screens have the screen saver on.
+pub struct ScreenSaver {
+    on: HashSet<x::Window>,
+    /// A screen to ask about idle time on, if the server has the extension.
+    /// Any will do, since input isn't tied to a screen.
+    root: Option<x::Window>,
+}
+
+impl ScreenSaver {
+    /// Asks to hear about the screen saver on each of `roots`, and checks
+    /// whether it's on already.
+    pub fn new(conn: &Connection, roots: &[x::Window]) -> Result<Self> {
+        let mut saver = Self {
+            on: HashSet::new(),
+            root: None,
+        };
+        if !conn.active_extensions().any(|e| e == Extension::ScreenSaver) {
+            return Ok(saver);
+        }
+        conn.wait_for_reply(conn.send_request(&screensaver::QueryVersion {
+            client_major_version: 1,
+            client_minor_version: 1,
+        }))?;
+        for &root in roots {
+            conn.send_and_check_request(&screensaver::SelectInput {
+                drawable: x::Drawable::Window(root),
+                event_mask: screensaver::EventFlags::NOTIFY_MASK,
+            })?;
+            let reply =
+                conn.wait_for_reply(conn.send_request(&screensaver::QueryInfo {
+                    drawable: x::Drawable::Window(root),
+                }))?;
+            if is_on(reply.state()) {
+                saver.on.insert(root);
+            }
+        }
+        saver.root = roots.first().copied();
+        Ok(saver)
+    }
+
+    /// Asks the server how long it's been since the user last touched any
+    /// input device, if it can tell us.
+    pub fn idle(&self, conn: &Connection) -> Result<Option<Duration>> {
+        let Some(root) = self.root else {
+            return Ok(None);
+        };
+        let reply =
+            conn.wait_for_reply(conn.send_request(&screensaver::QueryInfo {
+                drawable: x::Drawable::Window(root),
+            }))?;
+        Ok(Some(Duration::from_millis(reply.ms_since_user_input().into())))
+    }
+
+    /// Whether the screen saver is on, on any screen.
+    pub fn active(&self) -> bool {
+        !self.on.is_empty()
+    }
+
+    /// Keeps track of the screen saver coming on or going off.
+    pub fn handle_notify(&mut self, event: &screensaver::NotifyEvent) {
+        if is_on(event.state() as u8) {
+            self.on.insert(event.root());
+        } else {
+            self.on.remove(&event.root());
+        }
+    }
+}
+
+/// Checks whether a screen saver state means it's on. It's still on while
+/// it's cycling from one pattern to the next.
+fn is_on(state: u8) -> bool {
+    state == screensaver::State::On as u8
+        || state == screensaver::State::Cycle as u8
+}
@@ -0,0 +1,136 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Noticing screenshot tools, so we can stay out of their way.
+//!
+//! Region selectors like slop (behind maim) and flameshot put up a window over
+//! the whole screen, and need you to see the pointer to use them. Their windows
+//! often don't take the focus, so we can't rely on the focus tracking for
+//! this; instead we watch for windows being mapped on the root windows, and
+//! check their classes as they appear.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use xcb::{x, Connection};
+
+use crate::focus::{self, WmClass};
+
+/// Classes of screenshot tools we know about without being told.
+pub const BUILTIN: &[&str] = &[
+    "flameshot",
+    "maim",
+    "slop",
+    "scrot",
+    "spectacle",
+    "gnome-screenshot",
+    "ksnip",
+    "shutter",
+    "xfce4-screenshooter",
+    "deepin-screenshot",
+];
+
+/// Keeps track of which screenshot tool windows are on screen.
+pub struct ToolWatcher {
+    windows: HashSet<x::Window>,
+}
+
+impl ToolWatcher {
+    /// Looks for screenshot tools, named by `classes`, that are already on
+    /// screen. The caller is responsible for selecting SubstructureNotify on
+    /// `roots` so that we hear about new ones.
+    pub fn new(
+        conn: &Connection,
+        roots: &[x::Window],
+        classes: &[String],
+    ) -> Result<Self> {
+        let mut watcher = Self { windows: HashSet::new() };
+        for &root in roots {
+            let tree =
+                conn.wait_for_reply(conn.send_request(&x::QueryTree {
+                    window: root,
+                }))?;
+            for &window in tree.children() {
+                let attrs = conn.wait_for_reply(conn.send_request(
+                    &x::GetWindowAttributes { window },
+                ));
+                let viewable = match attrs {
+                    Ok(attrs) => attrs.map_state() == x::MapState::Viewable,
+                    // Windows come and go; if it's gone, it's not a problem.
+                    Err(xcb::Error::Protocol(xcb::ProtocolError::X(
+                        x::Error::Window(_),
+                        _,
+                    ))) => false,
+                    Err(e) => return Err(e.into()),
+                };
+                if viewable {
+                    watcher.handle_map(conn, window, classes)?;
+                }
+            }
+        }
+        Ok(watcher)
+    }
+
+    /// Whether any screenshot tool has a window up.
+    pub fn active(&self) -> bool {
+        !self.windows.is_empty()
+    }
+
+    /// Checks whether `window`, which has just been mapped, belongs to a
+    /// screenshot tool named in `classes`.
+    pub fn handle_map(
+        &mut self,
+        conn: &Connection,
+        window: x::Window,
+        classes: &[String],
+    ) -> Result<()> {
+        if tool_window(conn, window, classes)? {
+            self.windows.insert(window);
+        }
+        Ok(())
+    }
+
+    /// Forgets about `window`, which has been unmapped or destroyed.
+    pub fn handle_unmap(&mut self, window: x::Window) {
+        self.windows.remove(&window);
+    }
+}
+
+/// Checks whether `class` belongs to a screenshot tool named in `classes`.
+pub fn is_tool(class: &WmClass, classes: &[String]) -> bool {
+    classes.iter().any(|name| class.matches(name))
+}
+
+/// Checks whether top-level `window` belongs to a screenshot tool. With a
+/// reparenting window manager, the top-level window is often a frame with the
+/// application's window inside, so we look one level down too.
+fn tool_window(
+    conn: &Connection,
+    window: x::Window,
+    classes: &[String],
+) -> Result<bool> {
+    if let Some(class) = focus::get_wm_class(conn, window)? {
+        return Ok(is_tool(&class, classes));
+    }
+    let tree = conn.wait_for_reply(conn.send_request(&x::QueryTree {
+        window,
+    }));
+    let tree = match tree {
+        Ok(tree) => tree,
+        // It's gone already.
+        Err(xcb::Error::Protocol(xcb::ProtocolError::X(
+            x::Error::Window(_),
+            _,
+        ))) => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    for &child in tree.children() {
+        if let Some(class) = focus::get_wm_class(conn, child)? {
+            if is_tool(&class, classes) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
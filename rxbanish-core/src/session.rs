@@ -0,0 +1,355 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Our connection to the X server.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::os::fd::{AsRawFd, RawFd};
+
+use anyhow::Result;
+use log::debug;
+use xcb::{x, xfixes, Connection, Event, Extension};
+
+use crate::alarm::SyncAlarm;
+use crate::dpms::Dpms;
+use crate::evdev::Evdev;
+use crate::focus::FocusTracker;
+use crate::inhibit::Inhibitor;
+use crate::input::{self, snoop_xinput, Registrations};
+use crate::instance::Instance;
+use crate::keysym::Keymap;
+use crate::outputs::Outputs;
+use crate::pointer;
+use crate::record::Recorder;
+use crate::screensaver::ScreenSaver;
+use crate::screenshot::ToolWatcher;
+use crate::{hotkey, Input, Settings};
+
+/// Our connection to the X server, and everything we've set up on it. If the
+/// server goes away, throw this out and make a new one.
+pub struct Session {
+    pub(crate) conn: Connection,
+    pub(crate) roots: Vec<x::Window>,
+    pub(crate) instance: Instance,
+    /// Whether the server is Xwayland, running under a Wayland compositor.
+    pub(crate) xwayland: bool,
+    /// Where we're hearing about input from.
+    pub(crate) input: Input,
+    /// Whether we're getting XInput 2 raw events, rather than XInput 1 ones.
+    pub(crate) raw: bool,
+    /// Where we're hearing about input from RECORD, if we are, instead of
+    /// snooping with XInput.
+    pub(crate) recorder: Option<Recorder>,
+    /// Likewise, where we're reading input devices directly, if we are.
+    pub(crate) evdev: Option<Evdev>,
+    /// Events RECORD or evdev have handed over that we haven't passed on
+    /// yet.
+    pub(crate) pending: VecDeque<Event>,
+    /// Devices we're still trying to snoop on with XInput 1.
+    pub(crate) registrations: Registrations,
+    /// Names of the input devices, by ID.
+    pub(crate) devices: HashMap<u16, String>,
+    /// IDs of the input devices that are touchscreens.
+    pub(crate) touchscreens: HashSet<u16>,
+    /// Which master pointer each input device belongs to, by ID. There's
+    /// usually only one, but MPX allows for more.
+    pub(crate) masters: HashMap<u16, u16>,
+    /// The master pointer that core requests act on, which gets the full
+    /// treatment, as opposed to any others.
+    pub(crate) client_pointer: u16,
+    /// An invisible cursor, for hiding master pointers one at a time, or
+    /// hiding ours without XFixes.
+    pub(crate) blank: x::Cursor,
+    /// Whether XFixes can hide the pointer. Without it, we make do with
+    /// `blank`.
+    pub(crate) xfixes: bool,
+    /// Whether the server can put up pointer barriers, for `Settings::park`.
+    pub(crate) barriers: bool,
+    /// The versions of XFixes and XInput we're using, for the record. XFixes
+    /// is 0.0 if the server doesn't have it.
+    pub(crate) xfixes_version: (u32, u32),
+    pub(crate) xinput_version: (u16, u16),
+    pub(crate) keymap: Keymap,
+    pub(crate) focus: FocusTracker,
+    pub(crate) tools: ToolWatcher,
+    pub(crate) inhibit: Inhibitor,
+    pub(crate) screensaver: ScreenSaver,
+    pub(crate) dpms: Dpms,
+    pub(crate) outputs: Outputs,
+    pub(crate) alarm: SyncAlarm,
+}
+
+impl Session {
+    /// Connects to the X server on `display`, or the one in `$DISPLAY`, and
+    /// registers for everything we need to hear about.
+    ///
+    /// If another rxbanish is already running on the display, this fails,
+    /// unless `replace` is set, in which case we take over from it.
+    pub fn connect(
+        display: Option<&str>,
+        replace: bool,
+        settings: &Settings,
+    ) -> Result<Self> {
+        let (conn, screen_num) = Connection::connect_with_extensions(
+            // Display choice
+            display,
+            // Mandatory extensions
+            &[Extension::Input],
+            // Optional extensions
+            &[
+                Extension::XFixes,
+                Extension::ScreenSaver,
+                Extension::Dpms,
+                Extension::RandR,
+                Extension::Sync,
+                Extension::Record,
+            ],
+        )?;
+
+        // Identify the root windows. We'll use these for event registration
+        // and cursor manipulation. Basically everything.
+        //
+        // Most displays only have one screen, and so one root window, but
+        // there are still a few people out there with "Zaphod" multihead
+        // setups, which have several. There's still only one pointer, so we
+        // hide and show it on all of them together.
+        let setup = conn.get_setup();
+        let roots: Vec<x::Window> = setup.roots().map(|s| s.root()).collect();
+        // The root of the default screen, for things there's only one of.
+        let root = roots[screen_num as usize];
+
+        // Make sure we're not about to fight with another rxbanish.
+        let instance = Instance::claim(&conn, &roots, replace)?;
+
+        // Xwayland only sees input to X clients, and can only hide the
+        // pointer over them. It says who it is with an extension of its own,
+        // at least since version 21.1.
+        let xwayland = conn
+            .wait_for_reply(conn.send_request(&x::QueryExtension {
+                name: b"XWAYLAND",
+            }))?
+            .present();
+
+        // Check the version of XFixes at the server. For reasons I don't
+        // understand this appears to be load-bearing; without it, the XFixes
+        // calls will return an error. That's particularly strange since the C
+        // programs I'm reading don't bother with this.
+        //
+        // HideCursor arrived in version 4. Servers without it are rare, but
+        // we can still manage without.
+        let xfixes_version =
+            if conn.active_extensions().any(|e| e == Extension::XFixes) {
+                let reply = conn.wait_for_reply(conn.send_request(
+                    &xfixes::QueryVersion {
+                        client_major_version: 5,
+                        client_minor_version: 0,
+                    },
+                ))?;
+                (reply.major_version(), reply.minor_version())
+            } else {
+                (0, 0)
+            };
+        let xfixes = xfixes_version.0 >= 4;
+        let barriers = xfixes_version.0 >= 5;
+
+        // Ask to hear about the cursor changing, so that we notice when some
+        // other client undoes our blank cursor. That arrived in version 2.
+        if xfixes_version.0 >= 2 {
+            for &root in &roots {
+                conn.send_and_check_request(&xfixes::SelectCursorInput {
+                    window: root,
+                    event_mask: xfixes::CursorNotifyMask::DISPLAY_CURSOR,
+                })?;
+            }
+        }
+
+        // Alright, snoop on all input devices. It's kind of terrifying that
+        // you can do this in X tbh. Or, if we've been asked to, have RECORD
+        // tell us about input instead, or read it from the devices ourselves.
+        //
+        // Under Xwayland, XInput misses all the typing into Wayland clients,
+        // so we read the devices ourselves if we can, unless told otherwise.
+        let xinput_version = input::version(&conn)?;
+        let mut registrations = Registrations::default();
+        let (mut recorder, mut evdev) = (None, None);
+        let mut input = settings.input;
+        match input {
+            Input::Auto if xwayland => match Evdev::new(&conn, root) {
+                Ok(e) => {
+                    evdev = Some(e);
+                    input = Input::Evdev;
+                }
+                Err(e) => debug!("can't read input devices: {e}"),
+            },
+            Input::Auto | Input::Xi2 | Input::Legacy => (),
+            Input::Xrecord => recorder = Some(Recorder::new(&conn, display)?),
+            Input::Evdev => evdev = Some(Evdev::new(&conn, root)?),
+        }
+        let raw = recorder.is_none()
+            && evdev.is_none()
+            && snoop_xinput(&conn, &roots, &mut registrations, input)?;
+        let devices = input::device_names(&conn)?;
+        let touchscreens = input::touchscreens(&conn)?;
+        let masters = input::master_pointers(&conn)?;
+        let client_pointer = input::client_pointer(&conn)?;
+        let blank = pointer::blank_cursor(&conn, root)?;
+
+        // Key events only give us keycodes, so we need the keyboard mapping to
+        // tell which keys are being pressed.
+        let keymap = Keymap::fetch(&conn)?;
+
+        // Watch the root windows' properties, to keep track of what's
+        // focused and whether anyone has inhibited us, and their children, to
+        // spot screenshot tools appearing.
+        for &root in &roots {
+            conn.send_and_check_request(&x::ChangeWindowAttributes {
+                window: root,
+                value_list: &[x::Cw::EventMask(
+                    x::EventMask::PROPERTY_CHANGE
+                        | x::EventMask::SUBSTRUCTURE_NOTIFY,
+                )],
+            })?;
+        }
+
+        // Watch what's focused, so we can stay out of the way of particular
+        // applications.
+        let focus = FocusTracker::new(&conn, &roots, root)?;
+
+        // Likewise screenshot tools.
+        let tools =
+            ToolWatcher::new(&conn, &roots, &settings.screenshot_tools)?;
+
+        // And other programs asking us to back off.
+        let inhibit = Inhibitor::new(&conn, &roots)?;
+
+        // And the screen saver, and the monitors powering down.
+        let screensaver = ScreenSaver::new(&conn, &roots)?;
+        let dpms = Dpms::new(&conn)?;
+
+        // Find out where the monitors are, for putting the pointer in the
+        // corner of one.
+        let outputs = Outputs::new(&conn, &roots)?;
+
+        // And get ready to have the server keep time, if we're asked to.
+        let alarm = SyncAlarm::new(&conn)?;
+
+        Ok(Self {
+            conn,
+            roots,
+            instance,
+            xwayland,
+            input,
+            raw,
+            recorder,
+            evdev,
+            pending: VecDeque::new(),
+            registrations,
+            devices,
+            touchscreens,
+            masters,
+            client_pointer,
+            blank,
+            xfixes,
+            barriers,
+            xfixes_version,
+            xinput_version,
+            keymap,
+            focus,
+            tools,
+            inhibit,
+            screensaver,
+            dpms,
+            outputs,
+            alarm,
+        })
+    }
+
+    /// Registers for input events again, and catches up on the input devices
+    /// and keyboard mapping, in case anything has changed behind our back.
+    pub(crate) fn refresh(&mut self) -> Result<()> {
+        self.registrations = Registrations::default();
+        if self.recorder.is_none() && self.evdev.is_none() {
+            self.raw = snoop_xinput(
+                &self.conn,
+                &self.roots,
+                &mut self.registrations,
+                self.input,
+            )?;
+        }
+        self.devices = input::device_names(&self.conn)?;
+        self.touchscreens = input::touchscreens(&self.conn)?;
+        self.masters = input::master_pointers(&self.conn)?;
+        self.client_pointer = input::client_pointer(&self.conn)?;
+        self.keymap = Keymap::fetch(&self.conn)?;
+        self.outputs.refresh(&self.conn)?;
+        Ok(())
+    }
+
+    /// Whether the server is Xwayland, as far as it lets on.
+    pub fn xwayland(&self) -> bool {
+        self.xwayland
+    }
+
+    /// Where we're hearing about input from. With `Input::Auto`, this can
+    /// turn out to be evdev, under Xwayland.
+    pub fn input(&self) -> Input {
+        self.input
+    }
+
+    /// The connection itself, so that the caller can wait on it alongside
+    /// whatever else it's doing.
+    pub fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// The file descriptors to wait on for events: the connection's, and
+    /// RECORD's or evdev's if we're using either.
+    pub fn fds(&self) -> Vec<RawFd> {
+        let mut fds = vec![self.conn.as_raw_fd()];
+        fds.extend(self.recorder.as_ref().map(Recorder::as_raw_fd));
+        fds.extend(self.evdev.as_ref().map(Evdev::as_raw_fd));
+        fds
+    }
+
+    /// Takes the next event off the connection, or failing that, the next
+    /// input RECORD or evdev has seen, without blocking. Use this rather than
+    /// polling the connection, so as not to miss out on the latter.
+    pub fn poll_for_event(&mut self) -> Result<Option<Event>> {
+        if let Some(event) = self.conn.poll_for_event()? {
+            return Ok(Some(event));
+        }
+        if self.pending.is_empty() {
+            if let Some(recorder) = &self.recorder {
+                self.pending.extend(recorder.poll(&self.conn)?);
+            }
+            if let Some(evdev) = &mut self.evdev {
+                self.pending.extend(evdev.poll(&self.conn, &self.devices)?);
+            }
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Checks whether there's more than one master pointer, in which case we
+    /// can't hide ours with XFixes without hiding everyone else's too.
+    pub(crate) fn mpx(&self) -> bool {
+        self.masters.values().any(|&m| m != self.client_pointer)
+    }
+
+    /// Grabs the hotkeys in `settings`, replacing any we grabbed before. If
+    /// the settings say not to grab them, this only lets go of the old ones.
+    pub fn grab_hotkeys(&self, settings: &Settings) -> Result<()> {
+        let hotkeys = settings.hotkeys().filter(|_| settings.grab_hotkeys);
+        hotkey::grab(&self.conn, &self.roots, &self.keymap, hotkeys)
+    }
+}
+
+/// Checks whether `err` came from losing our connection to the X server, as
+/// opposed to the server objecting to something we did. Errors like this can
+/// be recovered from by connecting again.
+pub fn lost_connection(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.is::<xcb::ConnError>()
+            || matches!(e.downcast_ref(), Some(xcb::Error::Connection(_)))
+    })
+}
@@ -0,0 +1,625 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! What the user has asked us to do.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use xcb::x::KeyButMask;
+use xkeysym::Keysym;
+
+use crate::hotkey::Hotkey;
+use crate::keysym::{Group, Keymap};
+use crate::{screenshot, Command, Session};
+
+/// Everything that affects when and how we hide the pointer. The defaults are
+/// rxbanish's defaults.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    /// Modifiers that stop a keystroke from hiding the pointer.
+    pub ignored_mods: KeyButMask,
+    /// Modifiers that stop other keys pressed while they're held from hiding
+    /// the pointer, leaving the modifiers themselves alone.
+    pub chord_mods: KeyButMask,
+    /// Keys that don't hide the pointer.
+    pub ignored_keys: HashSet<Keysym>,
+    /// Groups of keys that don't hide the pointer either.
+    pub ignored_groups: Vec<Group>,
+    /// Keys that always bring the pointer back, whatever the rest of the
+    /// settings say.
+    pub reveal_keys: HashSet<Keysym>,
+    /// Applications, by either half of `WM_CLASS`, to leave the pointer alone
+    /// in.
+    pub excluded_classes: Vec<String>,
+    /// Patterns for the titles of windows to leave the pointer alone in,
+    /// while they have the focus. They only need to match part of the title.
+    pub excluded_titles: Vec<Regex>,
+    /// If not empty, the only applications to hide the pointer in.
+    pub only_classes: Vec<String>,
+    /// If not empty, the only monitors, by RandR output name, to hide the
+    /// pointer on.
+    pub only_outputs: Vec<String>,
+    /// Whether to only hide the pointer while it's over the focused window.
+    pub only_over_focus: bool,
+    /// Applications, by either half of `WM_CLASS`, not to hide the pointer
+    /// over, whatever's focused.
+    pub excluded_hover_classes: Vec<String>,
+    /// Where we hear about input from. This only takes effect on connecting.
+    pub input: Input,
+    /// If not empty, the only input devices, by name, that hide or reveal the
+    /// pointer.
+    pub devices: Vec<String>,
+    /// Input devices, by name, that don't hide or reveal the pointer.
+    pub ignored_devices: Vec<String>,
+    /// Whether to ignore the virtual devices that XTEST uses for synthetic
+    /// input, from the likes of xdotool.
+    pub virtual_detect: bool,
+    /// Patterns for the names of other virtual devices to ignore, where `*`
+    /// matches any run of characters and `?` any one.
+    pub virtual_devices: Vec<String>,
+    /// What particular input devices, by name, are allowed to do. Devices
+    /// that aren't listed can do everything.
+    pub device_policies: HashMap<String, DevicePolicy>,
+    /// Screenshot tools, by class, to stay out of the way of whenever they
+    /// have a window up.
+    pub screenshot_tools: Vec<String>,
+    /// How long the pointer can sit still before we hide it anyway.
+    pub idle_timeout: Option<Duration>,
+    /// How long the pointer can stay hidden before we bring it back.
+    pub max_hidden: Option<Duration>,
+    /// Whether to have the X server keep time for the timeouts, with SYNC
+    /// alarms, when it can.
+    pub sync_alarms: bool,
+    /// How far, in pixels, the pointer has to move to come back.
+    pub jitter: Option<u16>,
+    /// Which end of a keystroke hides the pointer.
+    pub hide_on: HideOn,
+    /// How many keystrokes in a row it takes to hide the pointer.
+    pub min_keystrokes: u32,
+    /// Whether to pay no attention to keys repeating while they're held.
+    pub ignore_repeat: bool,
+    /// How to get the pointer out of the way.
+    pub banish: Banish,
+    /// How to make the pointer invisible, when `banish` does.
+    pub method: Method,
+    /// The shell commands to run in place of hiding and showing the pointer,
+    /// when `method` is `Command`.
+    pub hide_cmd: Option<String>,
+    pub show_cmd: Option<String>,
+    /// Which windows to hide the pointer on, when `method` is XFixes.
+    pub hide_scope: HideScope,
+    /// Where to send the pointer, if `banish` moves it.
+    pub corner: Corner,
+    /// Whether to put a moved pointer back where it was when it returns.
+    pub restore: bool,
+    /// Whether to fence a moved pointer into its corner.
+    pub park: bool,
+    /// Whether to hide the pointer as soon as we connect.
+    pub start_hidden: bool,
+    /// Whether to leave the pointer alone while a fullscreen window has focus.
+    pub fullscreen_detect: bool,
+    /// Kinds of window to leave the pointer alone in while they have focus,
+    /// since they tend to want clicking on.
+    pub skipped_window_types: Vec<WindowType>,
+    /// Whether to leave the pointer alone while another client has grabbed it.
+    /// Finding out means grabbing the pointer ourselves for a moment, which
+    /// other clients can see, so this is off unless asked for.
+    pub grab_detect: bool,
+    /// Whether moving the pointer brings it back, as well as buttons.
+    pub motion_reveals: bool,
+    /// Whether touching a touchscreen brings the pointer back.
+    pub touch_reveals: bool,
+    /// Whether scrolling brings the pointer back.
+    pub scroll_reveals: bool,
+    /// A key that pauses and resumes us.
+    pub toggle_key: Option<Hotkey>,
+    /// A key that hides the pointer right away.
+    pub hide_key: Option<Hotkey>,
+    /// Keys that switch to profiles of settings, by profile name.
+    pub profile_keys: Vec<(Hotkey, String)>,
+    /// Whether to grab the hotkeys, rather than spotting them among ordinary
+    /// key presses.
+    pub grab_hotkeys: bool,
+    /// Whether to only say what we'd do to the pointer, without doing it.
+    pub dry_run: bool,
+    /// Whether to log every event we get, and what we made of it.
+    pub trace_events: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ignored_mods: KeyButMask::empty(),
+            chord_mods: KeyButMask::empty(),
+            ignored_keys: HashSet::new(),
+            ignored_groups: vec![],
+            reveal_keys: HashSet::new(),
+            excluded_classes: vec![],
+            excluded_titles: vec![],
+            only_classes: vec![],
+            only_outputs: vec![],
+            only_over_focus: false,
+            excluded_hover_classes: vec![],
+            input: Input::default(),
+            devices: vec![],
+            ignored_devices: vec![],
+            virtual_detect: true,
+            virtual_devices: vec![],
+            device_policies: HashMap::new(),
+            screenshot_tools: screenshot::BUILTIN
+                .iter()
+                .map(|&name| name.to_string())
+                .collect(),
+            idle_timeout: None,
+            max_hidden: None,
+            sync_alarms: false,
+            jitter: None,
+            hide_on: HideOn::default(),
+            ignore_repeat: false,
+            min_keystrokes: 1,
+            banish: Banish::default(),
+            method: Method::default(),
+            hide_cmd: None,
+            show_cmd: None,
+            hide_scope: HideScope::default(),
+            corner: Corner::default(),
+            restore: true,
+            park: false,
+            start_hidden: false,
+            fullscreen_detect: true,
+            skipped_window_types: WindowType::ALL
+                .into_iter()
+                .filter(|&t| t != WindowType::Normal)
+                .collect(),
+            grab_detect: false,
+            motion_reveals: true,
+            touch_reveals: true,
+            scroll_reveals: true,
+            toggle_key: None,
+            hide_key: None,
+            grab_hotkeys: true,
+            profile_keys: vec![],
+            dry_run: false,
+            trace_events: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Checks whether `sym` is a key the user has asked us to ignore, either
+    /// by name or as part of a group.
+    pub(crate) fn ignores_key(&self, sym: Keysym) -> bool {
+        self.ignored_keys.contains(&sym)
+            || self.ignored_groups.iter().any(|g| g.contains(sym))
+    }
+
+    /// Works out what the input device called `name` is allowed to do.
+    pub fn device_policy(&self, name: &str) -> DevicePolicy {
+        if self.ignores_device(name) {
+            DevicePolicy::Ignore
+        } else {
+            self.device_policies.get(name).copied().unwrap_or_default()
+        }
+    }
+
+    /// Checks whether the user has asked us not to listen to the input device
+    /// called `name`, either by name or because it's virtual.
+    fn ignores_device(&self, name: &str) -> bool {
+        let wanted = self.devices.is_empty()
+            || self.devices.iter().any(|d| d == name);
+        // The X server names the XTEST devices after the master devices they
+        // belong to, e.g. "Virtual core XTEST keyboard".
+        let xtest = self.virtual_detect && name.contains(" XTEST ");
+        !wanted
+            || xtest
+            || self.ignored_devices.iter().any(|d| d == name)
+            || self.virtual_devices.iter().any(|p| glob_match(p, name))
+    }
+
+    /// Lists the hotkeys we need to grab.
+    pub(crate) fn hotkeys(&self) -> impl Iterator<Item = &Hotkey> {
+        self.toggle_key
+            .iter()
+            .chain(&self.hide_key)
+            .chain(self.profile_keys.iter().map(|(key, _)| key))
+    }
+
+    /// Works out which command a key event is asking for, if it's for one of
+    /// our hotkeys. Hotkeys don't count as typing.
+    pub(crate) fn hotkey_command(
+        &self,
+        keymap: &Keymap,
+        keycode: u8,
+        state: KeyButMask,
+    ) -> Option<Command> {
+        let pressed = |key: &Hotkey| key.matches(keymap, keycode, state);
+        if self.toggle_key.as_ref().is_some_and(pressed) {
+            Some(Command::Toggle)
+        } else if self.hide_key.as_ref().is_some_and(pressed) {
+            Some(Command::Hide)
+        } else {
+            self.profile_keys
+                .iter()
+                .find(|(key, _)| pressed(key))
+                .map(|(_, name)| Command::Profile(Some(name.clone())))
+        }
+    }
+
+    /// Checks whether we should leave the pointer alone because of what's
+    /// focused, or what's on screen, or because someone asked.
+    pub(crate) fn suspended_for(&self, x: &Session) -> bool {
+        self.suspension_for(x).is_some()
+    }
+
+    /// Works out why we should leave the pointer alone, if we should.
+    pub(crate) fn suspension_for(&self, x: &Session) -> Option<Suspension> {
+        if x.inhibit.active() {
+            return Some(Suspension::Inhibited);
+        }
+        if x.screensaver.active() {
+            return Some(Suspension::ScreenSaver);
+        }
+        if x.dpms.blanked() {
+            return Some(Suspension::Dpms);
+        }
+        if self.fullscreen_detect && x.focus.fullscreen() {
+            return Some(Suspension::Fullscreen);
+        }
+        if x.focus
+            .window_type()
+            .is_some_and(|t| self.skipped_window_types.contains(&t))
+        {
+            return Some(Suspension::WindowType);
+        }
+        if x.tools.active() {
+            return Some(Suspension::ScreenshotTool);
+        }
+        if x.focus.title().is_some_and(|title| {
+            self.excluded_titles.iter().any(|re| re.is_match(title))
+        }) {
+            return Some(Suspension::Title);
+        }
+        let class = x.focus.class();
+        if class.is_some_and(|c| screenshot::is_tool(c, &self.screenshot_tools))
+        {
+            return Some(Suspension::ScreenshotTool);
+        }
+        let matches_any = |names: &[String]| {
+            class.is_some_and(|class| names.iter().any(|n| class.matches(n)))
+        };
+        if (!self.only_classes.is_empty() && !matches_any(&self.only_classes))
+            || matches_any(&self.excluded_classes)
+        {
+            return Some(Suspension::Class);
+        }
+        None
+    }
+}
+
+/// Reasons for leaving the pointer alone by ourselves.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Suspension {
+    /// Some program has set `_RXBANISH_INHIBIT`.
+    Inhibited,
+    /// The screen saver is on.
+    ScreenSaver,
+    /// DPMS has powered down the monitors.
+    Dpms,
+    /// A fullscreen window has the focus.
+    Fullscreen,
+    /// A window of a type we skip, like a dialog, has the focus.
+    WindowType,
+    /// A screenshot tool is running.
+    ScreenshotTool,
+    /// The focused window's title is one to leave alone.
+    Title,
+    /// The focused window belongs to an application to leave alone.
+    Class,
+}
+
+impl Suspension {
+    /// Explains the reason in a few words, for people.
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::Inhibited => "another program has asked",
+            Self::ScreenSaver => "the screen saver is on",
+            Self::Dpms => "the monitors are powered down",
+            Self::Fullscreen => "a fullscreen window has the focus",
+            Self::WindowType => "a dialog or similar window has the focus",
+            Self::ScreenshotTool => "a screenshot tool is running",
+            Self::Title => "the focused window's title is excluded",
+            Self::Class => "the focused application is excluded",
+        }
+    }
+}
+
+/// Checks whether `name` matches `pattern`, in which `*` matches any run of
+/// characters (including none), `?` matches any one character, and
+/// everything else matches itself.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where we were just after the last star, and where in `name` it's
+    // currently taken up to, so that we can come back and have it take one
+    // more character if what follows doesn't work out.
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((after, taken)) => {
+                    p = after;
+                    n = taken + 1;
+                    star = Some((after, n));
+                }
+                None => return false,
+            },
+        }
+    }
+    // Any stars left over can match nothing.
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Where we hear about input from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Input {
+    /// XInput, which tells us which device everything came from: XInput 2
+    /// if the server has it, or XInput 1 if not.
+    #[default]
+    Auto,
+    /// XInput 2's raw events, or nothing, if the server doesn't have them.
+    Xi2,
+    /// XInput 1, even if the server has XInput 2, for drivers whose raw
+    /// events go astray.
+    Legacy,
+    /// The RECORD extension, for servers that won't let us snoop with
+    /// XInput. All input seems to come from the core pointer and keyboard.
+    Xrecord,
+    /// The input devices in /dev/input, read directly, which usually takes
+    /// being in the `input` group.
+    Evdev,
+}
+
+/// Which end of a keystroke hides the pointer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum HideOn {
+    /// Hide as soon as a key is pressed.
+    Press,
+    /// Hide when a key is released.
+    #[default]
+    Release,
+}
+
+/// What an input device is allowed to do to the pointer.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DevicePolicy {
+    /// Keys hide the pointer, and moving or clicking reveals it.
+    #[default]
+    Both,
+    /// Keys hide the pointer, but moving or clicking doesn't reveal it.
+    Hide,
+    /// Moving or clicking reveals the pointer, but keys don't hide it.
+    Reveal,
+    /// Nothing the device does counts.
+    Ignore,
+}
+
+impl DevicePolicy {
+    /// Checks whether keystrokes from the device can hide the pointer.
+    pub fn hides(self) -> bool {
+        matches!(self, Self::Both | Self::Hide)
+    }
+
+    /// Checks whether the device moving or clicking can reveal the pointer.
+    pub fn reveals(self) -> bool {
+        matches!(self, Self::Both | Self::Reveal)
+    }
+
+    /// Names the policy, the same way as the config file.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Both => "both",
+            Self::Hide => "hide",
+            Self::Reveal => "reveal",
+            Self::Ignore => "ignore",
+        }
+    }
+}
+
+/// Ways of getting the pointer out of the way.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Banish {
+    /// Make the pointer invisible where it is.
+    #[default]
+    Hide,
+    /// Move the pointer into a corner of its monitor, but leave it visible.
+    Corner,
+    /// Move the pointer into a corner and make it invisible there.
+    Both,
+}
+
+/// Ways of making the pointer invisible, for drivers and compositors that
+/// don't take to the usual one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Method {
+    /// Ask XFixes to hide it.
+    #[default]
+    Xfixes,
+    /// Give every top-level window a blank cursor, and put their own cursors
+    /// back afterwards.
+    WindowCursor,
+    /// Put a tiny invisible window with a blank cursor under the pointer,
+    /// like unclutter does.
+    CoverWindow,
+    /// Leave the pointer be, and run `Settings::hide_cmd` and
+    /// `Settings::show_cmd` instead. The banisher doesn't run them itself;
+    /// its caller does, going by `Banisher::take_changes`.
+    Command,
+}
+
+/// Which windows XFixes hides the pointer on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum HideScope {
+    /// The root windows, which covers everything.
+    #[default]
+    Root,
+    /// The focused top-level window, following the focus as it moves, for
+    /// servers that only take notice of it there.
+    Focused,
+}
+
+/// The kinds of window in EWMH's `_NET_WM_WINDOW_TYPE`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum WindowType {
+    Normal,
+    Dialog,
+    Utility,
+    Toolbar,
+    Menu,
+    PopupMenu,
+    DropdownMenu,
+    Combo,
+    Tooltip,
+    Notification,
+    Splash,
+    Dock,
+    Desktop,
+    Dnd,
+}
+
+impl WindowType {
+    /// All of them, in order.
+    pub const ALL: [Self; 14] = [
+        Self::Normal,
+        Self::Dialog,
+        Self::Utility,
+        Self::Toolbar,
+        Self::Menu,
+        Self::PopupMenu,
+        Self::DropdownMenu,
+        Self::Combo,
+        Self::Tooltip,
+        Self::Notification,
+        Self::Splash,
+        Self::Dock,
+        Self::Desktop,
+        Self::Dnd,
+    ];
+
+    /// The name of the atom that stands for this type.
+    pub(crate) fn atom_name(self) -> &'static [u8] {
+        match self {
+            Self::Normal => b"_NET_WM_WINDOW_TYPE_NORMAL",
+            Self::Dialog => b"_NET_WM_WINDOW_TYPE_DIALOG",
+            Self::Utility => b"_NET_WM_WINDOW_TYPE_UTILITY",
+            Self::Toolbar => b"_NET_WM_WINDOW_TYPE_TOOLBAR",
+            Self::Menu => b"_NET_WM_WINDOW_TYPE_MENU",
+            Self::PopupMenu => b"_NET_WM_WINDOW_TYPE_POPUP_MENU",
+            Self::DropdownMenu => b"_NET_WM_WINDOW_TYPE_DROPDOWN_MENU",
+            Self::Combo => b"_NET_WM_WINDOW_TYPE_COMBO",
+            Self::Tooltip => b"_NET_WM_WINDOW_TYPE_TOOLTIP",
+            Self::Notification => b"_NET_WM_WINDOW_TYPE_NOTIFICATION",
+            Self::Splash => b"_NET_WM_WINDOW_TYPE_SPLASH",
+            Self::Dock => b"_NET_WM_WINDOW_TYPE_DOCK",
+            Self::Desktop => b"_NET_WM_WINDOW_TYPE_DESKTOP",
+            Self::Dnd => b"_NET_WM_WINDOW_TYPE_DND",
+        }
+    }
+}
+
+/// Corners of the screen, for `Banish::Corner`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn glob_literal() {
+        assert!(glob_match("Firefox", "Firefox"));
+        assert!(!glob_match("Firefox", "firefox"));
+        assert!(!glob_match("Firefox", "Firefox2"));
+        assert!(!glob_match("Firefox", "Fire"));
+    }
+
+    #[test]
+    fn glob_empty() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "x"));
+        assert!(!glob_match("x", ""));
+    }
+
+    #[test]
+    fn glob_star() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("*Touchpad", "SynPS/2 Synaptics Touchpad"));
+        assert!(glob_match("Logitech*", "Logitech USB Receiver"));
+        assert!(glob_match("*USB*", "Logitech USB Receiver"));
+        assert!(glob_match("a*b*c", "abc"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("a*b*c", "aXbYc_"));
+        assert!(!glob_match("*Touchpad", "Touchpad Buttons"));
+    }
+
+    #[test]
+    fn glob_trailing_star() {
+        assert!(glob_match("abc*", "abc"));
+        assert!(glob_match("abc**", "abcdef"));
+        assert!(!glob_match("abc*", "ab"));
+    }
+
+    #[test]
+    fn glob_question() {
+        assert!(glob_match("?", "x"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("?", "xy"));
+        assert!(glob_match("event?", "event3"));
+        assert!(!glob_match("event?", "event12"));
+        assert!(glob_match("*?", "x"));
+        assert!(!glob_match("*?", ""));
+        assert!(glob_match("é?", "éa"));
+    }
+
+    #[test]
+    fn glob_backtracks() {
+        assert!(glob_match("*aab", "aaab"));
+        assert!(glob_match("*a?b", "aaxaab"));
+        assert!(!glob_match("*a?b", "aaxab_"));
+    }
+}
@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Figuring out what kind of graphical session we're running in.
+
+use std::ffi::OsString;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use rxbanish_core::{Input, Session};
+use serde::{Deserialize, Serialize};
+
+/// The kinds of session we know about, which decide how we go about hiding the
+/// pointer.
+#[derive(
+    Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// A real X server.
+    X11,
+    /// X clients running on Xwayland under a Wayland compositor. This works,
+    /// but we can only see input to, and hide the pointer over, X clients.
+    Xwayland,
+    /// A Wayland compositor, without going through X. This only works under
+    /// compositors we know how to ask to hide the pointer.
+    Wayland,
+}
+
+impl Backend {
+    /// Guesses what sort of session we're in from the environment, the same
+    /// way toolkits generally do. `have_display` says whether the user has
+    /// told us which X display to use, making `$DISPLAY` moot.
+    pub fn detect(have_display: bool) -> Result<Self> {
+        let set = |name| {
+            std::env::var_os(name).is_some_and(|v: OsString| !v.is_empty())
+        };
+        let wayland = set("WAYLAND_DISPLAY")
+            || std::env::var_os("XDG_SESSION_TYPE")
+                .is_some_and(|v| v == "wayland");
+
+        match (wayland, have_display || set("DISPLAY")) {
+            (false, true) => Ok(Backend::X11),
+            (true, true) => Ok(Backend::Xwayland),
+            (true, false) => Ok(Backend::Wayland),
+            (false, false) => {
+                bail!("can't find an X display (is DISPLAY set?)")
+            }
+        }
+    }
+
+    /// Checks that we can actually do something useful in this kind of
+    /// session, complaining loudly if not.
+    pub fn check(self) -> Result<()> {
+        match self {
+            // We find out how well Xwayland will work once we've connected;
+            // see `adapt`.
+            Backend::X11 | Backend::Xwayland => (),
+            Backend::Wayland => {
+                // Plain Wayland doesn't give clients any way to watch other
+                // clients' input or hide the compositor's pointer, and nor
+                // does wlroots, so unless we know how to ask the particular
+                // compositor (see `wayland`), or the user tells us how,
+                // there's nothing we can do here.
+                bail!(
+                    "this looks like a Wayland session with no X server, \
+                     under a compositor rxbanish doesn't know how to ask to \
+                     hide the pointer (it knows sway, Hyprland, river, and \
+                     GNOME with its extension); use --hide-cmd and \
+                     --show-cmd to tell it how, or run Xwayland"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Settles what kind of session we're in, now that we've connected to
+    /// `x`, and warns about what won't work. The server knows better than the
+    /// environment whether it's Xwayland, unless the user has told us.
+    pub fn adapt(self, x: &Session, chosen: bool) -> Self {
+        let backend = match self {
+            Backend::X11 if x.xwayland() && !chosen => {
+                log::info!("the X server is Xwayland");
+                Backend::Xwayland
+            }
+            backend => backend,
+        };
+        if backend != Backend::Xwayland {
+            return backend;
+        }
+        let input = if x.input() == Input::Evdev {
+            // We see all the input there is, but still can't do anything
+            // about the pointer over Wayland clients.
+            "reading input devices directly"
+        } else {
+            "only input to X clients will hide the pointer"
+        };
+        log::warn!(
+            "running under Xwayland: {input}, and the pointer can only be \
+             hidden over X clients"
+        );
+        if let Some(hint) = compositor_setting() {
+            log::warn!("your compositor can hide the pointer itself: {hint}");
+        }
+        if crate::wayland::Compositor::detect().is_some() {
+            log::info!(
+                "rxbanish can also ask this compositor to hide the pointer \
+                 over Wayland clients, with --backend wayland, but without \
+                 the features that need X"
+            );
+        }
+        backend
+    }
+}
+
+/// Suggests the Wayland compositor's own setting for hiding the pointer
+/// while typing, if we recognize the compositor and it has one.
+fn compositor_setting() -> Option<&'static str> {
+    let set = |name| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+    if set("SWAYSOCK") {
+        Some("see `seat * hide_cursor when-typing enable` in sway(5)")
+    } else if set("HYPRLAND_INSTANCE_SIGNATURE") {
+        Some("see `cursor:hide_on_key_press` in Hyprland's settings")
+    } else {
+        None
+    }
+}
@@ -0,0 +1,138 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Spotting other programs that hide the pointer.
+//!
+//! unclutter, xbanish and the like all hide and show the pointer on their own
+//! schedules, and running one of them alongside us makes the pointer blink in
+//! and out of existence. Unlike us, they don't claim a selection to say
+//! they're running, so we go looking for them among the processes instead.
+//! That only finds ones running on this machine, which is where they nearly
+//! always are.
+
+use std::fs;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+
+/// Programs we know of that hide the pointer, by executable name.
+const KNOWN: &[&str] = &["unclutter", "unclutter-xfixes", "xbanish", "hhpc"];
+
+/// The kernel cuts process names off after this many bytes.
+const COMM_LEN: usize = 15;
+
+/// What to do about other pointer hiders.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnCompetitor {
+    /// Say so, and carry on regardless.
+    #[default]
+    Warn,
+    /// Refuse to start.
+    Exit,
+    /// Ask them to exit (with SIGTERM), and carry on.
+    Replace,
+}
+
+/// Another pointer hider that's running.
+struct Competitor {
+    pid: i32,
+    name: String,
+}
+
+/// Looks for other pointer hiders on `display`, or the one in `$DISPLAY`, and
+/// deals with them as `on` says.
+pub fn check(on: OnCompetitor, display: Option<&str>) -> Result<()> {
+    let display = display
+        .map(str::to_string)
+        .or_else(|| std::env::var("DISPLAY").ok());
+    for Competitor { pid, name } in find(display.as_deref())? {
+        match on {
+            OnCompetitor::Warn => warn!(
+                "{name} (pid {pid}) is also hiding the pointer, and the two \
+                 of us will get in each other's way \
+                 (use --on-competitor=replace to stop it)"
+            ),
+            OnCompetitor::Exit => bail!(
+                "{name} (pid {pid}) is already hiding the pointer \
+                 (use --on-competitor=replace to take over from it)"
+            ),
+            OnCompetitor::Replace => {
+                info!("asking {name} (pid {pid}) to exit");
+                // Safety: kill has no memory safety requirements.
+                if unsafe { libc::kill(pid, libc::SIGTERM) } < 0 {
+                    let e = std::io::Error::last_os_error();
+                    warn!("can't stop {name} (pid {pid}): {e}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Lists the other pointer hiders running on `display`. Processes whose
+/// display we can't see (generally because they belong to someone else) are
+/// given the benefit of the doubt and counted.
+fn find(display: Option<&str>) -> Result<Vec<Competitor>> {
+    let us = std::process::id();
+    let mut found = vec![];
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let Some(pid) = entry.file_name().to_str().and_then(|p| p.parse().ok())
+        else {
+            continue;
+        };
+        if pid as u32 == us {
+            continue;
+        }
+        // Processes can exit while we're looking, so failing to read about
+        // one just means it's not there anymore.
+        let Ok(comm) = fs::read_to_string(entry.path().join("comm")) else {
+            continue;
+        };
+        let comm = comm.trim_end();
+        let Some(&name) = KNOWN
+            .iter()
+            .find(|&&name| &name[..name.len().min(COMM_LEN)] == comm)
+        else {
+            continue;
+        };
+        let theirs = fs::read(entry.path().join("environ"))
+            .ok()
+            .and_then(|environ| display_in(&environ));
+        if let (Some(ours), Some(theirs)) = (display, theirs.as_deref()) {
+            if !same_display(ours, theirs) {
+                continue;
+            }
+        }
+        found.push(Competitor {
+            pid,
+            name: name.to_string(),
+        });
+    }
+    Ok(found)
+}
+
+/// Finds `DISPLAY` in the contents of a process's `environ` file.
+fn display_in(environ: &[u8]) -> Option<String> {
+    environ.split(|&b| b == 0).find_map(|var| {
+        let value = var.strip_prefix(b"DISPLAY=")?;
+        Some(String::from_utf8_lossy(value).into_owned())
+    })
+}
+
+/// Checks whether two display names refer to the same display, ignoring any
+/// screen number, since the pointer is shared between screens.
+fn same_display(a: &str, b: &str) -> bool {
+    let strip = |name: &str| -> String {
+        match name.rsplit_once(':') {
+            Some((host, rest)) => {
+                let number = rest.split('.').next().unwrap_or(rest);
+                format!("{host}:{number}")
+            }
+            None => name.to_string(),
+        }
+    };
+    strip(a) == strip(b)
+}
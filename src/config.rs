@@ -0,0 +1,293 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Config file support.
+//!
+//! The config file is TOML, and its keys are spelled the same way as the
+//! corresponding command line flags, e.g.
+//!
+//! ```toml
+//! ignore-mod = ["shift", "ctrl"]
+//! timeout = 5
+//! ```
+//!
+//! The exceptions are `device-policy`, a table from device names to what those
+//! devices may do, and `profile-key`, a table from profile names to hotkeys,
+//! neither of which has a flag.
+//!
+//! The file can also have profiles: tables under `profile`, each with any of
+//! the same keys, which replace the ones at the top level while that profile
+//! is in use, e.g.
+//!
+//! ```toml
+//! timeout = 5
+//!
+//! [profile.gaming]
+//! exclude-class = ["steam"]
+//! timeout = 1
+//! ```
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use rxbanish_core::{
+    keysym, Banish, Corner, DevicePolicy, HideOn, HideScope, Input, Method,
+    WindowType,
+};
+
+use xkeysym::Keysym;
+
+use crate::Mod;
+
+/// Contents of the config file. Everything is optional, and anything left out
+/// falls back to the command line (or the command line's defaults).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(default)]
+    pub ignore_mod: Vec<Mod>,
+    #[serde(default)]
+    pub ignore_when_modified: Vec<Mod>,
+    #[serde(default)]
+    pub ignore_key: Vec<String>,
+    pub ignore_key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub reveal_key: Vec<String>,
+    #[serde(default)]
+    pub ignore_group: Vec<keysym::Group>,
+    #[serde(default)]
+    pub ignore_numpad: bool,
+    #[serde(default)]
+    pub exclude_class: Vec<String>,
+    #[serde(default)]
+    pub exclude_title: Vec<String>,
+    #[serde(default)]
+    pub only_class: Vec<String>,
+    #[serde(default)]
+    pub only_output: Vec<String>,
+    #[serde(default)]
+    pub only_over_focus: bool,
+    #[serde(default)]
+    pub exclude_hover_class: Vec<String>,
+    pub input: Option<Input>,
+    #[serde(default)]
+    pub device: Vec<String>,
+    #[serde(default)]
+    pub ignore_device: Vec<String>,
+    #[serde(default)]
+    pub virtual_device: Vec<String>,
+    #[serde(default)]
+    pub device_policy: HashMap<String, DevicePolicy>,
+    #[serde(default)]
+    pub screenshot_tool: Vec<String>,
+    pub timeout: Option<u64>,
+    pub max_hidden: Option<u64>,
+    #[serde(default)]
+    pub sync_alarms: bool,
+    pub jitter: Option<u16>,
+    pub hide_on: Option<HideOn>,
+    pub min_keystrokes: Option<u32>,
+    #[serde(default)]
+    pub ignore_repeat: bool,
+    pub banish: Option<Banish>,
+    pub method: Option<Method>,
+    pub hide_cmd: Option<String>,
+    pub show_cmd: Option<String>,
+    pub hide_scope: Option<HideScope>,
+    pub corner: Option<Corner>,
+    #[serde(default)]
+    pub no_restore: bool,
+    #[serde(default)]
+    pub park: bool,
+    #[serde(default)]
+    pub start_hidden: bool,
+    #[serde(default)]
+    pub no_fullscreen_detect: bool,
+    #[serde(default)]
+    pub skip_window_type: Vec<WindowType>,
+    #[serde(default)]
+    pub no_window_type_detect: bool,
+    #[serde(default)]
+    pub grab_detect: bool,
+    #[serde(default)]
+    pub no_virtual_detect: bool,
+    #[serde(default)]
+    pub no_motion_reveal: bool,
+    #[serde(default)]
+    pub no_touch_reveal: bool,
+    #[serde(default)]
+    pub ignore_scrolling: bool,
+    pub toggle_key: Option<String>,
+    pub hide_key: Option<String>,
+    #[serde(default)]
+    pub no_grab_hotkeys: bool,
+    #[serde(default)]
+    pub profile_key: HashMap<String, String>,
+    pub on_hide: Option<String>,
+    pub on_show: Option<String>,
+    #[serde(default)]
+    pub notify: bool,
+}
+
+/// Works out where the config file lives if the user didn't tell us, following
+/// the XDG conventions. Returns `None` if we can't figure out a home directory.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rxbanish").join("config.toml"))
+}
+
+/// Loads the config file at `path`, with `profile`'s settings in place of the
+/// top-level ones, if it's given.
+///
+/// If `required` is false, a missing file is treated like an empty one, since
+/// most people won't have one. Any other problem, including keys we don't
+/// recognize, or a profile that isn't there, is an error.
+pub fn load(
+    path: &Path,
+    required: bool,
+    profile: Option<&str>,
+) -> Result<Config> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+            return with_profile(toml::Table::new(), profile);
+        }
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("can't read config file {}", path.display())
+            });
+        }
+    };
+    let mut config = toml::from_str(&text)
+        .map_err(anyhow::Error::from)
+        .and_then(|table| with_profile(table, profile))
+        .with_context(|| format!("bad config file {}", path.display()))?;
+    // Other files the config file mentions are found relative to it.
+    if let Some(dir) = path.parent() {
+        config.ignore_key_file = config.ignore_key_file.map(|f| dir.join(f));
+    }
+    Ok(config)
+}
+
+/// Reads a list of keysym names from the file at `path`, one per line. Blank
+/// lines are skipped, and so is anything after a `#`.
+pub fn load_key_list(path: &Path) -> Result<Vec<Keysym>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("can't read key list {}", path.display()))?;
+    let mut keys = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let name = line.split('#').next().unwrap_or_default().trim();
+        if name.is_empty() {
+            continue;
+        }
+        let key = keysym::parse(name).with_context(|| {
+            format!("in key list {}, line {}", path.display(), i + 1)
+        })?;
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Turns the contents of the config file into a `Config`, with `profile`'s
+/// settings laid over the top-level ones. Every profile gets checked, not only
+/// the one we're using, so that mistakes turn up before anyone switches to
+/// them.
+fn with_profile(
+    mut table: toml::Table,
+    profile: Option<&str>,
+) -> Result<Config> {
+    let profiles = match table.remove("profile") {
+        Some(toml::Value::Table(profiles)) => profiles,
+        Some(_) => bail!("profile should be a table of profiles"),
+        None => toml::Table::new(),
+    };
+    let mut chosen = None;
+    for (name, overrides) in profiles {
+        let toml::Value::Table(overrides) = overrides else {
+            bail!("profile {name:?} should be a table");
+        };
+        let mut merged = table.clone();
+        merged.extend(overrides);
+        let config: Config = merged
+            .try_into()
+            .with_context(|| format!("in profile {name:?}"))?;
+        if profile == Some(name.as_str()) {
+            chosen = Some(config);
+        }
+    }
+    match (profile, chosen) {
+        (None, _) => Ok(table.try_into()?),
+        (Some(_), Some(config)) => Ok(config),
+        (Some(name), None) => bail!("there's no profile called {name:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::with_profile;
+
+    const TEXT: &str = r#"
+        timeout = 5
+        exclude-class = ["mpv"]
+        notify = true
+
+        [profile.gaming]
+        timeout = 1
+        exclude-class = ["steam"]
+
+        [profile.quiet]
+        notify = false
+    "#;
+
+    fn table(text: &str) -> toml::Table {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn no_profile() {
+        let config = with_profile(table(TEXT), None).unwrap();
+        assert_eq!(config.timeout, Some(5));
+        assert_eq!(config.exclude_class, ["mpv"]);
+        assert!(config.notify);
+    }
+
+    #[test]
+    fn profile_overrides() {
+        let config = with_profile(table(TEXT), Some("gaming")).unwrap();
+        assert_eq!(config.timeout, Some(1));
+        // Lists are replaced, not added to.
+        assert_eq!(config.exclude_class, ["steam"]);
+        // Anything the profile doesn't mention stays as it was.
+        assert!(config.notify);
+
+        let config = with_profile(table(TEXT), Some("quiet")).unwrap();
+        assert_eq!(config.timeout, Some(5));
+        assert!(!config.notify);
+    }
+
+    #[test]
+    fn unknown_profile() {
+        assert!(with_profile(table(TEXT), Some("work")).is_err());
+        assert!(with_profile(toml::Table::new(), Some("work")).is_err());
+    }
+
+    #[test]
+    fn bad_profiles() {
+        // A profile that's wrong is an error even if we aren't using it, or
+        // are using another.
+        let text = format!("{TEXT}\n[profile.broken]\ntimeout = \"soon\"\n");
+        assert!(with_profile(table(&text), None).is_err());
+        assert!(with_profile(table(&text), Some("gaming")).is_err());
+        let text = format!("{TEXT}\n[profile.broken]\nno-such-key = 1\n");
+        assert!(with_profile(table(&text), None).is_err());
+        assert!(with_profile(table("profile = 3"), None).is_err());
+        assert!(with_profile(table("[profile]\ngaming = 3"), None).is_err());
+    }
+}
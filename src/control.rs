@@ -0,0 +1,144 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Remote control of a running rxbanish.
+//!
+//! Control requests can come from other threads (D-Bus, for instance), but the
+//! X connection and all our state belong to the main loop. So requests are
+//! queued up here, and an eventfd wakes the main loop to come and collect them.
+//! In the other direction, the main loop publishes a snapshot of its status
+//! that anyone can read without bothering it, along with its statistics.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use rxbanish_core::{Command, Stats};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::Backend;
+
+/// What the main loop is up to, as of the last time it told us.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Status {
+    /// Whether someone has paused us.
+    pub paused: bool,
+    /// Whether we're leaving the pointer alone because of what's on screen.
+    pub suspended: bool,
+    /// Whether the pointer is currently hidden.
+    pub hidden: bool,
+    /// What kind of session we're running in.
+    pub backend: Option<Backend>,
+    /// The input devices we're listening to.
+    pub devices: Vec<String>,
+    /// The profile from the config file we're using, if any.
+    pub profile: Option<String>,
+}
+
+/// Creates a connected pair of `Remote` and `Inbox`.
+pub fn channel() -> Result<(Remote, Inbox)> {
+    // Safety: eventfd has no memory safety preconditions, and if it succeeds
+    // we're the sole owner of the descriptor it returns.
+    let wake = unsafe {
+        let fd = libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Arc::new(OwnedFd::from_raw_fd(fd))
+    };
+    let (tx, rx) = mpsc::channel();
+    let status = Arc::new(Mutex::new(Status::default()));
+    let stats = Arc::new(Mutex::new(Stats::default()));
+    Ok((
+        Remote {
+            tx,
+            wake: wake.clone(),
+            status: status.clone(),
+            stats: stats.clone(),
+        },
+        Inbox { rx, wake, status, stats },
+    ))
+}
+
+/// The end of the channel that sends commands, and can be handed out to as
+/// many threads as needed.
+#[derive(Clone)]
+pub struct Remote {
+    tx: Sender<Command>,
+    wake: Arc<OwnedFd>,
+    status: Arc<Mutex<Status>>,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Remote {
+    /// Queues `cmd` for the main loop, and pokes it.
+    pub fn send(&self, cmd: Command) {
+        // If the main loop has gone away we're shutting down, and it doesn't
+        // much matter what happens to the command.
+        if self.tx.send(cmd).is_ok() {
+            let one = 1u64;
+            // Safety: we're writing exactly the 8 bytes that eventfd expects.
+            // The only way this can fail is if the counter is about to
+            // overflow, in which case the main loop is awake anyway.
+            unsafe {
+                libc::write(
+                    self.wake.as_raw_fd(),
+                    &one as *const u64 as *const libc::c_void,
+                    std::mem::size_of_val(&one),
+                );
+            }
+        }
+    }
+
+    /// Reads the most recently published status.
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Takes a copy of the statistics as they stand.
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// The end of the channel that the main loop reads.
+pub struct Inbox {
+    rx: Receiver<Command>,
+    wake: Arc<OwnedFd>,
+    status: Arc<Mutex<Status>>,
+    stats: Arc<Mutex<Stats>>,
+}
+
+impl Inbox {
+    /// Collects all commands that have arrived so far, without blocking.
+    pub fn recv(&self) -> impl Iterator<Item = Command> + '_ {
+        // Reset the eventfd _before_ draining the queue, so that anything that
+        // arrives while we're draining will wake us up again, rather than
+        // getting stuck until the next unrelated wakeup.
+        let mut count = 0u64;
+        // Safety: we're reading exactly the 8 bytes that eventfd produces. If
+        // it wasn't signalled it'll fail with EAGAIN, which is fine.
+        unsafe {
+            libc::read(
+                self.wake.as_raw_fd(),
+                &mut count as *mut u64 as *mut libc::c_void,
+                std::mem::size_of_val(&count),
+            );
+        }
+        self.rx.try_iter()
+    }
+
+    /// Makes `status` and `stats` visible to anyone holding a `Remote`.
+    pub fn publish(&self, status: Status, stats: &Stats) {
+        *self.status.lock().unwrap() = status;
+        self.stats.lock().unwrap().clone_from(stats);
+    }
+}
+
+impl AsRawFd for Inbox {
+    fn as_raw_fd(&self) -> RawFd {
+        self.wake.as_raw_fd()
+    }
+}
@@ -0,0 +1,101 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Turning into a daemon, the traditional Unix way.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+/// Forks into the background and detaches from the terminal. The parent
+/// process exits, and the child returns from this function.
+///
+/// Our output goes to `log_file` if given, and is thrown away otherwise. If
+/// `pid_file` is given, the child's PID is written there before the parent
+/// exits, so that anyone waiting on the parent (like systemd with
+/// `Type=forking`) finds it right away. The returned `PidFile` removes it
+/// again when dropped.
+///
+/// This must be called before any threads are started, since only the calling
+/// thread survives the fork.
+pub fn daemonize(
+    log_file: Option<&Path>,
+    pid_file: Option<&Path>,
+) -> Result<Option<PidFile>> {
+    // Open everything before forking, so that problems get reported to the
+    // terminal rather than vanishing.
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    let out = match log_file {
+        Some(path) => File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("can't open {}", path.display()))?,
+        None => null.try_clone()?,
+    };
+    let pid_file = pid_file.map(std::path::absolute).transpose()?;
+
+    // Safety: we don't have any other threads (see above), so fork is safe.
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error().into()),
+        0 => (),
+        child => {
+            let status = match &pid_file {
+                Some(path) => match write_pid(path, child) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        eprintln!("{e:#}");
+                        1
+                    }
+                },
+                None => 0,
+            };
+            // Skip destructors and atexit handlers, which belong to the child
+            // now.
+            // Safety: _exit is always safe to call.
+            unsafe { libc::_exit(status) }
+        }
+    }
+
+    // We're the child. Leave the parent's session, so that we're no longer
+    // attached to its terminal, and stop holding on to its working directory.
+    // Safety: neither of these has memory safety preconditions.
+    unsafe {
+        if libc::setsid() < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        libc::chdir(c"/".as_ptr());
+    }
+
+    // Rust flushes stdout by line, so there shouldn't be anything stuck in
+    // there, but better safe than sorry.
+    std::io::stdout().flush().ok();
+    for (from, to) in [(&null, 0), (&out, 1), (&out, 2)] {
+        // Safety: these are all valid file descriptors.
+        if unsafe { libc::dup2(from.as_raw_fd(), to) } < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(pid_file.map(|path| PidFile { path }))
+}
+
+fn write_pid(path: &Path, pid: libc::pid_t) -> Result<()> {
+    std::fs::write(path, format!("{pid}\n"))
+        .with_context(|| format!("can't write PID file {}", path.display()))
+}
+
+/// A PID file that we wrote, and will remove when we're done.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
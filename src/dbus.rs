@@ -0,0 +1,157 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A D-Bus interface for controlling rxbanish, for desktop environments that
+//! would rather do that than send signals.
+//!
+//! We publish `org.rxbanish.Control` on the session bus, with an object at
+//! `/org/rxbanish/Control` that has `Pause`, `Resume`, and `Toggle` methods, a
+//! `SetProfile` method that switches to a profile from the config file (or
+//! back to the usual settings, given an empty name), a `Status` property
+//! ("active", "paused", or "suspended", for when we're leaving the pointer
+//! alone because of what's on screen), and a `Hidden` property that says
+//! whether the pointer is currently hidden. Both properties announce their
+//! changes with `PropertiesChanged`. There's also a `Stats` method, which
+//! returns our counters as a dictionary. For example:
+//!
+//! ```text
+//! busctl --user call org.rxbanish.Control /org/rxbanish/Control \
+//!     org.rxbanish.Control Toggle
+//! ```
+//!
+//! zbus handles the bus on its own thread, so this just forwards everything to
+//! the main loop through a `Remote`. The properties change in the main loop,
+//! though, so it's up to that to tell us, through `Bus::update`.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::debug;
+use rxbanish_core::Command;
+use zbus::names::BusName;
+use zbus::zvariant::Value;
+
+use crate::control::{Remote, Status};
+
+pub const NAME: &str = "org.rxbanish.Control";
+pub const PATH: &str = "/org/rxbanish/Control";
+
+struct Control {
+    remote: Remote,
+}
+
+#[zbus::interface(name = "org.rxbanish.Control")]
+impl Control {
+    /// Stop hiding the pointer until told to resume.
+    fn pause(&self) {
+        self.remote.send(Command::Pause);
+    }
+
+    /// Start hiding the pointer again after a pause.
+    fn resume(&self) {
+        self.remote.send(Command::Resume);
+    }
+
+    /// Pause if active, resume if paused.
+    fn toggle(&self) {
+        self.remote.send(Command::Toggle);
+    }
+
+    /// Switch to the named profile from the config file, or back to the usual
+    /// settings if the name is empty.
+    fn set_profile(&self, name: &str) {
+        let name = (!name.is_empty()).then(|| name.to_string());
+        self.remote.send(Command::Profile(name));
+    }
+
+    /// Counters of what we've been up to since we started.
+    fn stats(&self) -> HashMap<&'static str, Value<'static>> {
+        let stats = self.remote.stats();
+        let events: HashMap<u16, u64> = stats.events.into_iter().collect();
+        HashMap::from([
+            ("hides", stats.hides.into()),
+            ("shows", stats.shows.into()),
+            ("suspensions", stats.suspensions.into()),
+            ("ignored_keys", stats.ignored_keys.into()),
+            ("ignored_mods", stats.ignored_mods.into()),
+            ("events", events.into()),
+        ])
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &'static str {
+        describe(&self.remote.status())
+    }
+
+    #[zbus(property)]
+    fn hidden(&self) -> bool {
+        self.remote.status().hidden
+    }
+}
+
+/// Sums up `status` for the `Status` property.
+fn describe(status: &Status) -> &'static str {
+    if status.paused {
+        "paused"
+    } else if status.suspended {
+        "suspended"
+    } else {
+        "active"
+    }
+}
+
+/// Our connection to the session bus, and what we last said about ourselves
+/// on it.
+pub struct Bus {
+    conn: zbus::blocking::Connection,
+    status: &'static str,
+    hidden: bool,
+}
+
+impl Bus {
+    /// Lets anyone watching the properties know about `status`, if it's
+    /// changed them.
+    pub fn update(&mut self, status: &Status) {
+        let mut changed = HashMap::new();
+        let described = describe(status);
+        if described != self.status {
+            self.status = described;
+            changed.insert("Status", Value::from(described));
+        }
+        if status.hidden != self.hidden {
+            self.hidden = status.hidden;
+            changed.insert("Hidden", Value::from(status.hidden));
+        }
+        if changed.is_empty() {
+            return;
+        }
+        let invalidated: &[&str] = &[];
+        // Anyone who cares can always ask, so this is no great loss if it
+        // doesn't get through.
+        if let Err(e) = self.conn.emit_signal(
+            None::<BusName>,
+            PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            &(NAME, changed, invalidated),
+        ) {
+            debug!("can't announce D-Bus property changes: {e}");
+        }
+    }
+}
+
+/// Connects to the session bus and starts serving the control interface. The
+/// interface goes away when the returned `Bus` is dropped.
+pub fn serve(remote: Remote) -> Result<Bus> {
+    let status = remote.status();
+    let bus = Bus {
+        status: describe(&status),
+        hidden: status.hidden,
+        conn: zbus::blocking::connection::Builder::session()?
+            .name(NAME)?
+            .serve_at(PATH, Control { remote })?
+            .build()?,
+    };
+    Ok(bus)
+}
@@ -0,0 +1,173 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hiding the pointer by running the user's own commands.
+//!
+//! For compositors we don't know how to ask, or for doing something else
+//! entirely while the user types, the user can give us a command to run in
+//! place of hiding the pointer, and another in place of showing it; see
+//! `Method::Command`.
+//!
+//! Unlike hooks, these have to happen in order, or a quick hide and show
+//! could finish the wrong way round. So they run one at a time, in the
+//! background, and the next waits for the last to finish. Only the latest
+//! thing asked for is worth waiting to do: if the pointer's been hidden and
+//! shown again while a command was running, there's no need to do either.
+//! Nothing gets to hold us up for long, though; a command that hasn't
+//! finished in `TIMEOUT` gets killed.
+
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::signal;
+use crate::wayland::Cursor;
+
+/// How long a command gets before we give up on it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often to check on a running command. Nothing wakes us when one
+/// finishes, so we have to go and look.
+const CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs hide and show commands one at a time.
+#[derive(Default)]
+pub struct Runner {
+    running: Option<Running>,
+    /// The command to run once the current one's done, and whether it hides
+    /// the pointer.
+    next: Option<(String, bool)>,
+}
+
+/// A command we've started.
+struct Running {
+    child: Child,
+    command: String,
+    hidden: bool,
+    started: Instant,
+}
+
+impl Runner {
+    /// Runs `command`, which hides the pointer if `hidden` and shows it if
+    /// not, as soon as whatever's running now is done.
+    pub fn run(&mut self, command: &str, hidden: bool) {
+        match &self.running {
+            // Once the running command's done, things will be the way we
+            // want them.
+            Some(running) if running.hidden == hidden => self.next = None,
+            Some(_) => self.next = Some((command.into(), hidden)),
+            None => self.start(command.into(), hidden),
+        }
+    }
+
+    /// Checks on the running command, and starts the next if it's done.
+    /// Returns when it needs checking on again, if it does.
+    pub fn poll(&mut self) -> Option<Instant> {
+        if let Some(running) = &mut self.running {
+            let done = match running.child.try_wait() {
+                Ok(Some(status)) => {
+                    if !status.success() {
+                        warn!("command {:?} failed: {status}", running.command);
+                    }
+                    true
+                }
+                Ok(None) if running.started.elapsed() >= TIMEOUT => {
+                    warn!(
+                        "command {:?} took too long; killing it",
+                        running.command
+                    );
+                    // It may have finished in the meantime, which is fine.
+                    let _ = running.child.kill();
+                    let _ = running.child.wait();
+                    true
+                }
+                Ok(None) => false,
+                Err(e) => {
+                    warn!("can't check on command {:?}: {e}", running.command);
+                    true
+                }
+            };
+            if done {
+                self.running = None;
+                if let Some((command, hidden)) = self.next.take() {
+                    self.start(command, hidden);
+                }
+            }
+        }
+        self.running.as_ref().map(|running| {
+            (Instant::now() + CHECK_INTERVAL).min(running.started + TIMEOUT)
+        })
+    }
+
+    /// Waits for everything we've been asked to do to be done, for when
+    /// we're on our way out.
+    pub fn finish(&mut self) {
+        while let Some(when) = self.poll() {
+            std::thread::sleep(when.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    fn start(&mut self, command: String, hidden: bool) {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(&command).stdin(Stdio::null());
+        let state = if hidden { "hidden" } else { "shown" };
+        cmd.env("RXBANISH_STATE", state);
+        signal::unblock_in_child(&mut cmd);
+        // As with hooks, a broken command is the user's problem to fix, and
+        // the next one may well work.
+        match cmd.spawn() {
+            Ok(child) => {
+                debug!(command; "running command");
+                self.running = Some(Running {
+                    child,
+                    command,
+                    hidden,
+                    started: Instant::now(),
+                });
+            }
+            Err(e) => warn!("can't run command {command:?}: {e}"),
+        }
+    }
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// The user's commands, standing in for a compositor.
+pub struct External {
+    hide: String,
+    show: String,
+    runner: Runner,
+}
+
+impl External {
+    pub fn new(hide: &str, show: &str) -> Self {
+        Self {
+            hide: hide.into(),
+            show: show.into(),
+            runner: Runner::default(),
+        }
+    }
+}
+
+impl Cursor for External {
+    fn hide(&mut self) -> Result<()> {
+        self.runner.run(&self.hide, true);
+        Ok(())
+    }
+
+    fn show(&mut self) -> Result<()> {
+        self.runner.run(&self.show, false);
+        Ok(())
+    }
+
+    fn poll(&mut self) -> Option<Instant> {
+        self.runner.poll()
+    }
+}
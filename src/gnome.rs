@@ -0,0 +1,61 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hiding the pointer under GNOME Shell, by way of our extension.
+//!
+//! Mutter will hide the pointer when asked, but only from inside the Shell, so
+//! contrib/gnome-shell-extension has a tiny extension that offers that over
+//! the session bus. This is the other end of it.
+//!
+//! If we go away with the pointer hidden, the extension notices us leaving
+//! the bus and shows it again, so even a SIGKILL can't leave it stuck.
+
+use anyhow::{Context, Result};
+
+use crate::wayland::Cursor;
+
+const SHELL: &str = "org.gnome.Shell";
+const PATH: &str = "/io/github/cbiffle/Rxbanish";
+const INTERFACE: &str = "io.github.cbiffle.Rxbanish1";
+
+/// GNOME Shell, with our extension running in it.
+pub struct Shell {
+    conn: zbus::blocking::Connection,
+}
+
+impl Shell {
+    /// Connects to the session bus and makes sure the extension's there to
+    /// answer.
+    pub fn connect() -> Result<Self> {
+        let shell = Self {
+            conn: zbus::blocking::Connection::session()?,
+        };
+        shell.call("ShowPointer").context(
+            "can't reach rxbanish's GNOME Shell extension (is it installed \
+             and enabled? see contrib/gnome-shell-extension)",
+        )?;
+        Ok(shell)
+    }
+
+    fn call(&self, method: &str) -> Result<()> {
+        self.conn.call_method(
+            Some(SHELL),
+            PATH,
+            Some(INTERFACE),
+            method,
+            &(),
+        )?;
+        Ok(())
+    }
+}
+
+impl Cursor for Shell {
+    fn hide(&mut self) -> Result<()> {
+        self.call("HidePointer")
+    }
+
+    fn show(&mut self) -> Result<()> {
+        self.call("ShowPointer")
+    }
+}
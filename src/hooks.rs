@@ -0,0 +1,166 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Running the user's commands when the pointer is hidden and shown.
+//!
+//! Hooks run through the shell, in the background, so that a slow one can't
+//! hold up the pointer. We keep hold of them until they finish, so that they
+//! don't hang around as zombies, and so that we can say if they fail.
+//!
+//! Each hook gets some context in its environment, so that it doesn't need
+//! to go asking X:
+//!
+//! - `RXBANISH_STATE`: "hidden" or "shown"
+//! - `RXBANISH_REASON`: why, e.g. "key," "motion," or "idle-timeout"
+//! - `RXBANISH_DEVICE`: the name of the input device responsible, if any
+//! - `RXBANISH_CLASS`: the class of the focused window, if we know it
+//!
+//! Variables that don't apply are left unset.
+//!
+//! The runner also puts up desktop notifications, if the user wants them, when
+//! we start or stop leaving the pointer alone by ourselves.
+
+use std::process::{Child, Command, Stdio};
+
+use anyhow::Result;
+use log::{debug, warn};
+use rxbanish_core::{Change, Suspension};
+
+use crate::notify::Notifier;
+use crate::signal;
+
+/// The user's commands for when the pointer is hidden and shown.
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+    pub on_hide: Option<String>,
+    pub on_show: Option<String>,
+    /// Whether to put up a notification when we start or stop leaving the
+    /// pointer alone by ourselves.
+    pub notify: bool,
+}
+
+/// Runs hooks, and keeps track of them until they finish.
+pub struct Runner {
+    hooks: Hooks,
+    children: Vec<Child>,
+    /// Why we were leaving the pointer alone, last we heard.
+    suspension: Option<Suspension>,
+    /// Our line to the notification server, once we've needed it.
+    notifier: Option<Notifier>,
+}
+
+impl Runner {
+    /// Makes a runner for `hooks`.
+    pub fn new(hooks: Hooks) -> Self {
+        Self {
+            hooks,
+            children: vec![],
+            suspension: None,
+            notifier: None,
+        }
+    }
+
+    /// Switches to a new set of hooks, e.g. after reloading the config. Hooks
+    /// that are already running carry on.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    /// Runs the hook for `change`, if there is one.
+    pub fn run(&mut self, change: &Change) {
+        let hook = if change.hidden {
+            &self.hooks.on_hide
+        } else {
+            &self.hooks.on_show
+        };
+        let Some(command) = hook else {
+            return;
+        };
+        // A broken hook is the user's problem to fix, not a reason to stop
+        // hiding the pointer.
+        match spawn(command, change) {
+            Ok(child) => {
+                debug!(command; "running hook");
+                self.children.push(child);
+            }
+            Err(e) => warn!("can't run hook {command:?}: {e}"),
+        }
+    }
+
+    /// Notes why we're leaving the pointer alone now, if we are, and tells
+    /// the user if that's changed and they want to know.
+    pub fn suspend(&mut self, why: Option<Suspension>) {
+        // There's nobody looking while the screen is blanked, and by the time
+        // there is, it won't be anymore.
+        if matches!(why, Some(Suspension::ScreenSaver | Suspension::Dpms)) {
+            return;
+        }
+        if why == self.suspension {
+            return;
+        }
+        self.suspension = why;
+        if !self.hooks.notify {
+            return;
+        }
+        if self.notifier.is_none() {
+            match Notifier::start() {
+                Ok(notifier) => self.notifier = Some(notifier),
+                Err(e) => {
+                    warn!("can't send notifications: {e}");
+                    // Don't keep trying, and filling up the log.
+                    self.hooks.notify = false;
+                    return;
+                }
+            }
+        }
+        let Some(notifier) = &self.notifier else {
+            return;
+        };
+        match why {
+            Some(why) => {
+                notifier.send("Leaving the pointer alone", why.describe())
+            }
+            None => notifier.send("Hiding the pointer again", ""),
+        }
+    }
+
+    /// Collects hooks that have finished.
+    pub fn reap(&mut self) {
+        self.children.retain_mut(|child| match child.try_wait() {
+            Ok(None) => true,
+            Ok(Some(status)) => {
+                if !status.success() {
+                    warn!("hook {} failed: {status}", child.id());
+                }
+                false
+            }
+            Err(e) => {
+                warn!("can't check on hook {}: {e}", child.id());
+                false
+            }
+        });
+    }
+}
+
+/// Starts `command` with the shell, without waiting for it to finish, and
+/// tells it about `change`.
+fn spawn(command: &str, change: &Change) -> Result<Child> {
+    let mut cmd = Command::new("/bin/sh");
+    cmd.arg("-c").arg(command).stdin(Stdio::null());
+    let state = if change.hidden { "hidden" } else { "shown" };
+    cmd.env("RXBANISH_STATE", state);
+    cmd.env("RXBANISH_REASON", change.reason);
+    // Don't let anything we inherited pass for context.
+    for (var, value) in [
+        ("RXBANISH_DEVICE", &change.device),
+        ("RXBANISH_CLASS", &change.class),
+    ] {
+        match value {
+            Some(value) => cmd.env(var, value),
+            None => cmd.env_remove(var),
+        };
+    }
+    signal::unblock_in_child(&mut cmd);
+    Ok(cmd.spawn()?)
+}
@@ -0,0 +1,78 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hiding the pointer under Hyprland, by way of its IPC socket.
+//!
+//! Hyprland's `cursor:invisible` option hides the pointer until it's turned
+//! off again, which is just what we need. Options can be set at run time with
+//! the `keyword` command, as hyprctl does. Hyprland hangs up after answering
+//! each request, so every one gets a connection of its own.
+//!
+//! The option outlives us, so it's up to `wayland` to turn it off again
+//! however we exit.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+
+use crate::wayland::Cursor;
+
+/// A Hyprland instance.
+pub struct Hyprland {
+    socket: PathBuf,
+}
+
+impl Hyprland {
+    /// Finds the Hyprland that `HYPRLAND_INSTANCE_SIGNATURE` refers to, and
+    /// makes sure it's there to answer.
+    pub fn connect() -> Result<Self> {
+        let signature = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE")
+            .context("HYPRLAND_INSTANCE_SIGNATURE isn't set")?;
+        // Hyprland has kept its sockets in the runtime directory since 0.40,
+        // and in /tmp before that.
+        let socket = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(|dir| PathBuf::from(dir).join("hypr"))
+            .filter(|dir| dir.join(&signature).exists())
+            .unwrap_or_else(|| PathBuf::from("/tmp/hypr"))
+            .join(&signature)
+            .join(".socket.sock");
+        let hyprland = Self { socket };
+        hyprland.show_pointer(true)?;
+        Ok(hyprland)
+    }
+
+    fn show_pointer(&self, show: bool) -> Result<()> {
+        self.request(&format!("keyword cursor:invisible {}", !show))
+    }
+
+    /// Sends `request`, as though from hyprctl.
+    fn request(&self, request: &str) -> Result<()> {
+        let mut socket =
+            UnixStream::connect(&self.socket).with_context(|| {
+                format!(
+                    "can't connect to Hyprland at {}",
+                    self.socket.display()
+                )
+            })?;
+        socket.write_all(request.as_bytes())?;
+        let mut reply = String::new();
+        socket.read_to_string(&mut reply)?;
+        if reply.trim() != "ok" {
+            bail!("Hyprland wouldn't do `{request}`: {}", reply.trim());
+        }
+        Ok(())
+    }
+}
+
+impl Cursor for Hyprland {
+    fn hide(&mut self) -> Result<()> {
+        self.show_pointer(false)
+    }
+
+    fn show(&mut self) -> Result<()> {
+        self.show_pointer(true)
+    }
+}
@@ -0,0 +1,124 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Noticing edits to the config file, by way of inotify.
+//!
+//! Editors save files in all sorts of ways: some write them in place, and
+//! others write a new file and rename it over the old one, which leaves a
+//! watch on the old file watching nothing. So we watch the directory the file
+//! is in instead, and pick out the events about it by name. We only look at
+//! files that have been written and closed, or renamed into place, so that we
+//! never read one halfway through being written.
+
+use std::ffi::{CString, OsString};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+/// A file descriptor that becomes readable when something happens to the
+/// file we're watching.
+pub struct FileWatcher {
+    fd: OwnedFd,
+    name: OsString,
+}
+
+impl FileWatcher {
+    /// Starts watching for `path` being written or replaced. The directory
+    /// it's in has to exist, but the file itself needn't, yet.
+    pub fn new(path: &Path) -> Result<Self> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            bail!("can't watch {}", path.display());
+        };
+        // An empty parent means the current directory.
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())?;
+        // Safety: inotify_init1 has no memory safety requirements, and we
+        // take ownership of the descriptor it returns, if it does.
+        let fd = unsafe {
+            let fd = libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC);
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            OwnedFd::from_raw_fd(fd)
+        };
+        // Safety: `dir` is a valid C string for the duration of the call.
+        let wd = unsafe {
+            libc::inotify_add_watch(
+                fd.as_raw_fd(),
+                dir.as_ptr(),
+                libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO,
+            )
+        };
+        if wd < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            fd,
+            name: name.to_os_string(),
+        })
+    }
+
+    /// Collects everything that's happened since last time, without
+    /// blocking, and says whether any of it was our file changing.
+    pub fn changed(&self) -> Result<bool> {
+        // Enough room for plenty of events with names as long as they get.
+        let mut buf = [0u8; 16 * 1024];
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut changed = false;
+        loop {
+            // Safety: we're asking read for no more bytes than buf holds.
+            let n = unsafe {
+                libc::read(
+                    self.fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::WouldBlock {
+                    return Ok(changed);
+                }
+                return Err(err.into());
+            }
+            let n = n as usize;
+            let mut offset = 0;
+            while offset + header <= n {
+                // Safety: the kernel only hands out whole events, each a
+                // header followed by `len` bytes of name, and we've checked
+                // the header fits. read_unaligned copes with buf being
+                // unaligned.
+                let event = unsafe {
+                    std::ptr::read_unaligned(
+                        buf[offset..].as_ptr() as *const libc::inotify_event
+                    )
+                };
+                let start = offset + header;
+                let end = (start + event.len as usize).min(n);
+                // The name is padded out with NULs.
+                let name: Vec<u8> = buf[start..end]
+                    .iter()
+                    .copied()
+                    .take_while(|&b| b != 0)
+                    .collect();
+                if OsString::from_vec(name) == self.name {
+                    changed = true;
+                }
+                offset = end;
+            }
+        }
+    }
+}
+
+impl AsRawFd for FileWatcher {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Setting up our log output.
+//!
+//! Log messages can carry structured fields (like `reason` and `device` on the
+//! messages for hiding and showing the pointer). In the usual text format
+//! they're tacked onto the end of the line; in JSON they become fields of the
+//! object, for the benefit of people feeding our logs into something that can
+//! search them.
+
+use std::io::Write;
+
+use clap::ValueEnum;
+use log::kv::{Key, Value, VisitSource};
+use log::LevelFilter;
+use serde_json::Map;
+
+/// The ways we can write log messages.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// One line of human-readable text per message.
+    #[default]
+    Text,
+    /// One JSON object per line, with the message's timestamp, level, and
+    /// fields.
+    Json,
+}
+
+/// Starts logging messages at `level` and above to stderr. `RUST_LOG` can
+/// still fine-tune this, for anyone who knows the trick.
+pub fn init(level: LevelFilter, format: Format) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level).parse_default_env();
+    if format == Format::Json {
+        builder.format(|buf, record| {
+            let mut object = Map::new();
+            object.insert(
+                "timestamp".into(),
+                buf.timestamp_millis().to_string().into(),
+            );
+            object.insert("level".into(), record.level().as_str().into());
+            object.insert("target".into(), record.target().into());
+            object.insert("message".into(), record.args().to_string().into());
+            record.key_values().visit(&mut Fields(&mut object)).ok();
+            writeln!(buf, "{}", serde_json::Value::Object(object))
+        });
+    }
+    builder.init();
+}
+
+/// Collects a message's structured fields into a JSON object.
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: Key<'kvs>,
+        value: Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let value = serde_json::to_value(&value)
+            .map_err(|_| log::kv::Error::msg("can't convert field to JSON"))?;
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
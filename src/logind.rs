@@ -0,0 +1,47 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Noticing the machine waking up from sleep, by way of systemd-logind.
+//!
+//! Over a suspend and resume, the X server's idea of the pointer and the input
+//! devices can drift away from ours. logind sends `PrepareForSleep` on the
+//! system bus before going to sleep and again after waking up, so when it says
+//! we're awake, we ask the main loop to get back in step.
+
+use anyhow::Result;
+use log::{debug, warn};
+use rxbanish_core::Command;
+
+use crate::control::Remote;
+
+/// Starts listening for logind's sleep signals, on a thread of our own, and
+/// sends `Command::Resync` through `remote` after each wakeup. This carries on
+/// for as long as we're running.
+pub fn watch(remote: Remote) -> Result<()> {
+    let conn = zbus::blocking::Connection::system()?;
+    let proxy = zbus::blocking::Proxy::new(
+        &conn,
+        "org.freedesktop.login1",
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )?;
+    let signals = proxy.receive_signal("PrepareForSleep")?;
+    std::thread::Builder::new()
+        .name("logind".into())
+        .spawn(move || {
+            // The signal's only argument says whether we're about to sleep
+            // (true) or have just woken up (false).
+            for msg in signals {
+                match msg.body().deserialize::<bool>() {
+                    Ok(true) => debug!("going to sleep"),
+                    Ok(false) => {
+                        debug!("woke up from sleep");
+                        remote.send(Command::Resync);
+                    }
+                    Err(e) => warn!("can't make sense of PrepareForSleep: {e}"),
+                }
+            }
+        })?;
+    Ok(())
+}
@@ -7,14 +7,58 @@
 //! Inspired by xbanish, but using XCB, and with a lot fewer uses of
 //! uninitialized stack memory.
 
-use anyhow::{bail, Result};
-use clap::{Parser, ValueEnum};
-use xcb::{
-    x::{KeyButMask, Window, self},
-    xfixes,
-    xinput::{self, DeviceUse, InputClass, DeviceChange},
-    Connection, Event, Extension,
+mod backend;
+mod competitors;
+mod config;
+mod control;
+mod daemon;
+mod dbus;
+mod external;
+#[cfg(feature = "gnome")]
+mod gnome;
+mod hooks;
+mod hyprland;
+mod inotify;
+mod logging;
+mod logind;
+mod notify;
+mod river;
+mod signal;
+mod socket;
+mod sway;
+mod systemd;
+mod timer;
+#[cfg(feature = "tray")]
+mod tray;
+mod wayland;
+
+use std::collections::HashSet;
+use std::os::fd::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use log::{debug, error, info, warn, LevelFilter};
+use regex::Regex;
+use serde::Deserialize;
+use backend::Backend;
+use competitors::OnCompetitor;
+use control::Inbox;
+use hooks::Hooks;
+use inotify::FileWatcher;
+use rxbanish_core::hotkey::{self, Hotkey};
+use rxbanish_core::{
+    keysym, lost_connection, screenshot, Banish, Banisher, Change, Command,
+    Corner, HideOn, HideScope, Input, Method, Policy, Session, Settings,
+    Suspension, WindowType,
 };
+use signal::SignalFd;
+use socket::{ControlSocket, Request};
+use timer::Schedule;
+use xcb::x::KeyButMask;
+use xcb::xfixes;
+use xkeysym::Keysym;
 
 /// Basic program for hiding the X11 mouse pointer while you're typing.
 #[derive(Parser)]
@@ -24,11 +68,652 @@ struct Rxbanish {
     /// multiple modifiers, or use "all" as shorthand for everything.
     #[clap(short, long, value_enum, value_name = "MOD")]
     ignore_mod: Vec<Mod>,
+
+    /// Don't hide the pointer for keys pressed while holding one of these
+    /// modifiers, as in shortcuts like ctrl+C, without ignoring the
+    /// modifiers themselves the way --ignore-mod does. On its own, this
+    /// means ctrl, mod1 (alt), and mod4 (super). You can use this flag more
+    /// than once.
+    #[clap(
+        long,
+        value_enum,
+        value_name = "MOD",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_values = ["ctrl", "mod1", "mod4"],
+    )]
+    ignore_when_modified: Vec<Mod>,
+
+    /// Keys that shouldn't hide the pointer, named by keysym as printed by
+    /// xev, e.g. Print or XF86AudioRaiseVolume. You can use this flag more than
+    /// once to ignore several keys.
+    #[clap(
+        short = 'k',
+        long,
+        value_name = "KEYSYM",
+        value_parser = keysym::parse,
+    )]
+    ignore_key: Vec<Keysym>,
+
+    /// Read more keys that shouldn't hide the pointer from this file, one
+    /// keysym per line, with comments starting with #. These go along with
+    /// any from --ignore-key (or the config file). The file is read again
+    /// whenever the config file is.
+    #[clap(long, value_name = "PATH")]
+    ignore_key_file: Option<PathBuf>,
+
+    /// A key that always brings the pointer back when pressed, e.g. Escape,
+    /// whatever the rest of the settings say, for when you've lost it. It
+    /// still does whatever it normally does, too. You can use this flag more
+    /// than once.
+    #[clap(long, value_name = "KEYSYM", value_parser = keysym::parse)]
+    reveal_key: Vec<Keysym>,
+
+    /// Groups of keys that shouldn't hide the pointer, so you don't have to
+    /// list them all with --ignore-key. You can use this flag more than once.
+    #[clap(short = 'g', long, value_enum, value_name = "GROUP")]
+    ignore_group: Vec<keysym::Group>,
+
+    /// Don't hide the pointer for anything typed on the numeric keypad, for
+    /// entering numbers with one hand while using the mouse with the other.
+    /// This is short for --ignore-group=numpad.
+    #[clap(long)]
+    ignore_numpad: bool,
+
+    /// Leave the pointer alone while the focused window belongs to this
+    /// application, as named by either half of its WM_CLASS (ignoring case).
+    /// You can use this flag more than once.
+    #[clap(long, value_name = "CLASS")]
+    exclude_class: Vec<String>,
+
+    /// Leave the pointer alone while the focused window's title matches this
+    /// regular expression anywhere, e.g. "Meet|Figma". You can use this flag
+    /// more than once.
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new)]
+    exclude_title: Vec<Regex>,
+
+    /// The opposite of --exclude-class: only hide the pointer while the
+    /// focused window belongs to this application, and leave it alone
+    /// everywhere else. You can use this flag more than once.
+    #[clap(long, value_name = "CLASS")]
+    only_class: Vec<String>,
+
+    /// Only hide the pointer while it's on this monitor, named as listed by
+    /// `xrandr`, e.g. "DP-1". You can use this flag more than once.
+    #[clap(long, value_name = "OUTPUT")]
+    only_output: Vec<String>,
+
+    /// Only hide the pointer while it's over the focused window, so that one
+    /// parked off to the side, away from where you're typing, stays put.
+    #[clap(long)]
+    only_over_focus: bool,
+
+    /// Never hide the pointer while it's over a window belonging to this
+    /// application, named like --exclude-class, whatever has the focus. Handy
+    /// for drawing programs, where the pointer's position matters even while
+    /// you're typing shortcuts. You can use this flag more than once.
+    #[clap(long, value_name = "CLASS")]
+    exclude_hover_class: Vec<String>,
+
+    /// Where to hear about input from. The default, auto, snoops on each
+    /// input device with XInput 2, or XInput 1 on servers without it; xi2 and
+    /// legacy insist on one or the other, for working around drivers that get
+    /// one of them wrong. Servers that won't allow snooping may still let
+    /// xrecord watch with the RECORD extension instead, although then all
+    /// input seems to come from the core pointer and keyboard, so --device
+    /// and friends can't tell devices apart. evdev reads the devices in
+    /// /dev/input directly, which usually means being in the input group.
+    #[clap(long, value_enum, value_name = "SOURCE")]
+    input: Option<Input>,
+
+    /// Only let this input device hide or reveal the pointer, named as listed
+    /// by `xinput list`, e.g. "AT Translated Set 2 keyboard". You can use this
+    /// flag more than once to choose several devices.
+    #[clap(long, value_name = "NAME")]
+    device: Vec<String>,
+
+    /// Don't let this input device hide or reveal the pointer, named like
+    /// --device. You can use this flag more than once.
+    #[clap(long, value_name = "NAME")]
+    ignore_device: Vec<String>,
+
+    /// Ignore input devices whose names match this pattern, where * matches
+    /// anything, e.g. "Synergy*", for programs that feed in input through
+    /// virtual devices of their own. You can use this flag more than once.
+    #[clap(long, value_name = "PATTERN")]
+    virtual_device: Vec<String>,
+
+    /// Let synthetic input from programs like xdotool, which arrives through
+    /// the X server's XTEST devices, hide and reveal the pointer. Normally we
+    /// ignore it.
+    #[clap(long)]
+    no_virtual_detect: bool,
+
+    /// Leave the pointer alone while this screenshot tool, named like
+    /// --exclude-class, has a window up, even if it doesn't have the focus.
+    /// We know about flameshot, maim, spectacle, and several others already;
+    /// this adds to the list. You can use this flag more than once.
+    #[clap(long, value_name = "CLASS")]
+    screenshot_tool: Vec<String>,
+
+    /// Also hide the pointer after you've been idle for this many seconds, by
+    /// the X server's reckoning (the same one its screen saver uses) if it
+    /// has the MIT-SCREEN-SAVER extension.
+    #[clap(short, long, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Bring the pointer back automatically once it has been hidden for this
+    /// many seconds, in case you've forgotten where you left it.
+    #[clap(short, long, value_name = "SECS")]
+    max_hidden: Option<u64>,
+
+    /// Have the X server keep time for the timeouts, using alarms from its
+    /// SYNC extension, rather than keeping time ourselves. The idle timeout
+    /// then goes by the server's idle clock, which all input resets.
+    #[clap(long)]
+    sync_alarms: bool,
+
+    /// Don't bring the pointer back until it has moved more than this many
+    /// pixels from where it was hidden, so that a mouse that drifts a little
+    /// doesn't keep revealing it. Buttons still reveal it right away.
+    #[clap(short, long, value_name = "PX")]
+    jitter: Option<u16>,
+
+    /// Whether to hide the pointer as soon as a key goes down, or wait until
+    /// it comes back up (the default). Waiting means tapping a modifier in
+    /// --ignore-mod doesn't hide the pointer; when hiding on press, use
+    /// --ignore-key for modifiers instead.
+    #[clap(long, value_enum, value_name = "WHEN")]
+    hide_on: Option<HideOn>,
+
+    /// Only hide the pointer once you've typed this many keys in a row, each
+    /// within a second of the last, so that a stray keystroke doesn't send it
+    /// away.
+    #[clap(long, value_name = "N")]
+    min_keystrokes: Option<u32>,
+
+    /// Pay no attention to a held key repeating, so that holding it down
+    /// only counts as one keystroke.
+    #[clap(long)]
+    ignore_repeat: bool,
+
+    /// How to get the pointer out of the way: hide it, move it into a corner
+    /// of the screen (like xbanish and ratpoison do), or both.
+    #[clap(long, value_enum, value_name = "HOW")]
+    banish: Option<Banish>,
+
+    /// How to make the pointer invisible. The default, xfixes, works almost
+    /// everywhere. If it doesn't for you, window-cursor gives each window a
+    /// blank cursor instead, and cover-window puts a tiny invisible window
+    /// under the pointer, like unclutter; that one swallows the click that
+    /// brings the pointer back, though. command runs --hide-cmd and
+    /// --show-cmd instead.
+    #[clap(long, value_enum, value_name = "METHOD")]
+    method: Option<Method>,
+
+    /// A shell command to run instead of hiding the pointer. With
+    /// --show-cmd, this turns rxbanish into a way to do anything at all while
+    /// you type. Giving these implies --method command.
+    #[clap(long, value_name = "CMD", requires = "show_cmd")]
+    hide_cmd: Option<String>,
+
+    /// A shell command to run instead of showing the pointer. Goes with
+    /// --hide-cmd.
+    #[clap(long, value_name = "CMD", requires = "hide_cmd")]
+    show_cmd: Option<String>,
+
+    /// Which windows XFixes hides the pointer on: the root windows, which
+    /// covers everything, or only the focused window, moving with the focus,
+    /// for servers that don't take notice of it on the root.
+    #[clap(long, value_enum, value_name = "SCOPE")]
+    hide_scope: Option<HideScope>,
+
+    /// Which corner --banish sends the pointer to, of the monitor it's on.
+    /// The default is bottom-right.
+    #[clap(long, value_enum, value_name = "CORNER")]
+    corner: Option<Corner>,
+
+    /// Leave the pointer in the corner when it comes back, rather than
+    /// putting it back where it was before --banish moved it.
+    #[clap(long)]
+    no_restore: bool,
+
+    /// Put up pointer barriers around the corner that --banish sends the
+    /// pointer to, so that brushing the touchpad can't drag it back out
+    /// while it's hidden. Needs XFixes 5.
+    #[clap(long)]
+    park: bool,
+
+    /// Hide the pointer as soon as we start, rather than waiting for you to
+    /// type something.
+    #[clap(long)]
+    start_hidden: bool,
+
+    /// Don't stop hiding the pointer just because the focused window is
+    /// fullscreen. Normally we leave fullscreen windows (games and video
+    /// players, mostly) to manage the pointer themselves.
+    #[clap(long)]
+    no_fullscreen_detect: bool,
+
+    /// Leave the pointer alone while this kind of window has focus, going by
+    /// its _NET_WM_WINDOW_TYPE. You can use this flag more than once. The
+    /// default is every kind but normal, since dialogs, menus and the like
+    /// usually want clicking on.
+    #[clap(long, value_enum, value_name = "TYPE")]
+    skip_window_type: Vec<WindowType>,
+
+    /// Hide the pointer whatever kind of window has focus, instead of
+    /// skipping the ones --skip-window-type lists.
+    #[clap(long)]
+    no_window_type_detect: bool,
+
+    /// Leave the pointer alone while another program has grabbed it, as
+    /// menus and screenshot tools' region selectors do. X has no way to ask
+    /// about grabs, so checking means grabbing the pointer ourselves for a
+    /// moment before each hide, which other programs can see: windows get
+    /// leave and enter events, which can flicker hover effects or close
+    /// menus, and a program that tries to grab the pointer at that moment
+    /// will fail. Drags are spotted by the buttons being held either way.
+    #[clap(long)]
+    grab_detect: bool,
+
+    /// Only bring the pointer back when a button is pressed, not when it
+    /// moves, for desks (or laptops) that shake.
+    #[clap(long)]
+    no_motion_reveal: bool,
+
+    /// Don't bring the pointer back when you tap a touchscreen, only when you
+    /// use a mouse or touchpad.
+    #[clap(long)]
+    no_touch_reveal: bool,
+
+    /// Don't bring the pointer back when you scroll, with a wheel or a
+    /// touchpad, so that you can scroll through what you're reading without
+    /// it getting in the way.
+    #[clap(long)]
+    ignore_scrolling: bool,
+
+    /// A key that pauses and resumes rxbanish from anywhere, written as
+    /// modifiers and a keysym joined with +, e.g. super+F9. The modifiers are
+    /// shift, ctrl, alt, super, and mod1 through mod5.
+    #[clap(long, value_name = "KEY", value_parser = hotkey::parse)]
+    toggle_key: Option<Hotkey>,
+
+    /// A key that hides the pointer right away, whether or not you've typed
+    /// anything, written the same way as --toggle-key.
+    #[clap(long, value_name = "KEY", value_parser = hotkey::parse)]
+    hide_key: Option<Hotkey>,
+
+    /// Spot the hotkeys among ordinary key presses, rather than grabbing
+    /// them, so that they never clash with the window manager's key bindings.
+    /// The keys then also do whatever they'd normally do in the focused
+    /// window.
+    #[clap(long)]
+    no_grab_hotkeys: bool,
+
+    /// A shell command to run each time the pointer is hidden.
+    #[clap(long, value_name = "CMD")]
+    on_hide: Option<String>,
+
+    /// A shell command to run each time the pointer comes back.
+    #[clap(long, value_name = "CMD")]
+    on_show: Option<String>,
+
+    /// Put up a desktop notification when rxbanish starts leaving the pointer
+    /// alone by itself, e.g. for a fullscreen window, a screenshot tool, or
+    /// because another program asked, saying why; and another when it starts
+    /// hiding the pointer again.
+    #[clap(long)]
+    notify: bool,
+
+    /// Put an icon in the system tray showing what rxbanish is up to, with a
+    /// menu to pause and resume, and to open the config file. Clicking the
+    /// icon pauses or resumes.
+    #[clap(long)]
+    tray: bool,
+
+    /// Read settings from this file instead of the default,
+    /// ~/.config/rxbanish/config.toml. Flags given on the command line take
+    /// precedence over the file. rxbanish re-reads the file whenever it
+    /// changes, or when sent SIGHUP, and keeps its old settings if there's
+    /// anything wrong with the new ones.
+    #[clap(short, long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Only re-read the config file on SIGHUP, rather than whenever it
+    /// changes.
+    #[clap(long)]
+    no_watch_config: bool,
+
+    /// Start out with this profile from the config file, rather than the
+    /// settings at the top level. `rxbanish profile` switches profiles while
+    /// rxbanish is running.
+    #[clap(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// The X display to connect to, instead of the one in $DISPLAY.
+    #[clap(long, value_name = "DISPLAY")]
+    display: Option<String>,
+
+    /// What kind of session we're running in. By default, we work this out
+    /// from the environment.
+    #[clap(long, value_enum)]
+    backend: Option<Backend>,
+
+    /// If rxbanish is already running on this screen, take over from it
+    /// rather than giving up.
+    #[clap(short, long)]
+    replace: bool,
+
+    /// What to do if another program that hides the pointer, like unclutter
+    /// or xbanish, is already running.
+    #[clap(long, value_enum, value_name = "WHAT", default_value_t)]
+    on_competitor: OnCompetitor,
+
+    /// Once connected to X, fork into the background and detach from the
+    /// terminal.
+    #[clap(short, long)]
+    daemon: bool,
+
+    /// With --daemon, write our process ID to this file. It's removed again
+    /// when we exit.
+    #[clap(long, value_name = "PATH", requires = "daemon")]
+    pid_file: Option<PathBuf>,
+
+    /// With --daemon, send our output to this file rather than discarding it.
+    #[clap(long, value_name = "PATH", requires = "daemon")]
+    log_file: Option<PathBuf>,
+
+    /// Say more about what we're up to. Once shows each time the pointer is
+    /// hidden or shown; twice shows every event we get from X as well.
+    #[clap(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// Say nothing unless something goes wrong.
+    #[clap(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Don't actually hide or show the pointer, but log each time we would
+    /// have, and why. This is handy for trying out settings before trusting
+    /// them. There's no config file equivalent, so that reloading can't
+    /// switch it on or off behind our back.
+    #[clap(long)]
+    dry_run: bool,
+
+    /// Log every event we get from X: what it is, the input device it came
+    /// from, the key or button and modifiers involved, and what we decided to
+    /// do about it. This is for getting to the bottom of the pointer not
+    /// hiding (or not coming back) when it should.
+    #[clap(long)]
+    trace_events: bool,
+
+    /// How to write log messages. JSON output includes the reason for each
+    /// change, and the input device responsible, as separate fields.
+    #[clap(long, value_enum, value_name = "FORMAT", default_value_t)]
+    log_format: logging::Format,
+
+    #[clap(subcommand)]
+    command: Option<Cmd>,
+}
+
+/// Things to do other than hiding the pointer.
+#[derive(Subcommand)]
+enum Cmd {
+    /// Send a command to an rxbanish that's already running, and print its
+    /// answer. The commands are pause, resume, toggle, hide, show, resync,
+    /// profile, status, and stats.
+    Send {
+        command: String,
+    },
+    /// Ask an rxbanish that's already running what it's up to: whether it's
+    /// active, whether the pointer is hidden, and which input devices it's
+    /// listening to.
+    Status,
+    /// Tell an rxbanish that's already running to leave the pointer alone
+    /// until it's resumed.
+    Pause,
+    /// Tell a paused rxbanish to get back to work.
+    Resume,
+    /// Pause a running rxbanish, or resume it if it's paused.
+    Toggle,
+    /// Hide the pointer now. If rxbanish isn't running, the pointer stays
+    /// hidden until this is interrupted, since X brings it back as soon as
+    /// whoever hid it disconnects.
+    Hide,
+    /// Bring the pointer back now, if rxbanish has hidden it.
+    Show,
+    /// Switch a running rxbanish to another profile from its config file, or
+    /// back to the settings at the top level if no profile is given.
+    Profile {
+        name: Option<String>,
+    },
+    /// List the input devices the X server knows about, and whether (and
+    /// how) rxbanish would listen to each of them with the current settings.
+    Devices {
+        /// Print the list as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Check whether rxbanish could run here: that the config file is in
+    /// order, that the X server has the extensions it needs, and that there
+    /// are input devices to listen to. Prints PASS or FAIL for each, and exits
+    /// with 0 if everything passed, 1 if anything failed, or 2 if it couldn't
+    /// reach the X server at all.
+    Check,
+}
+
+/// Loads the config file (if any), with `profile` if one is given, and
+/// combines it with the command line into our settings and hooks. The command
+/// line wins.
+fn load_settings(
+    args: &Rxbanish,
+    profile: Option<&str>,
+) -> Result<(Settings, Hooks)> {
+    let config = match &args.config {
+        Some(path) => config::load(path, true, profile)?,
+        None => match config::default_path() {
+            Some(path) => config::load(&path, false, profile)?,
+            None if profile.is_some() => {
+                bail!("there's no config file, so there are no profiles")
+            }
+            None => config::Config::default(),
+        },
+    };
+
+    let hooks = Hooks {
+        on_hide: args.on_hide.clone().or(config.on_hide),
+        on_show: args.on_show.clone().or(config.on_show),
+        notify: args.notify || config.notify,
+    };
+
+    // Giving the commands is as good as asking for them to be used.
+    let hide_cmd = args.hide_cmd.clone().or(config.hide_cmd);
+    let show_cmd = args.show_cmd.clone().or(config.show_cmd);
+    let method = match args.method.or(config.method) {
+        Some(method) => method,
+        None if hide_cmd.is_some() || show_cmd.is_some() => Method::Command,
+        None => Method::default(),
+    };
+    if method == Method::Command {
+        if hide_cmd.is_none() || show_cmd.is_none() {
+            bail!("--method command needs both --hide-cmd and --show-cmd");
+        }
+    } else if hide_cmd.is_some() || show_cmd.is_some() {
+        warn!("--hide-cmd and --show-cmd only apply with --method command");
+    }
+
+    // Combine all user-specified ignore mods.
+    let ignored_mods = KeyButMask::from_bits_truncate(
+        cli_or_file(&args.ignore_mod, config.ignore_mod)
+            .into_iter()
+            .fold(0, |a, b| a | b as u32),
+    );
+    let mut ignored_groups =
+        cli_or_file(&args.ignore_group, config.ignore_group);
+    if args.ignore_numpad || config.ignore_numpad {
+        ignored_groups.push(keysym::Group::Numpad);
+    }
+    let chord_mods = KeyButMask::from_bits_truncate(
+        cli_or_file(&args.ignore_when_modified, config.ignore_when_modified)
+            .into_iter()
+            .fold(0, |a, b| a | b as u32),
+    );
+
+    // Keys from the file need parsing, which clap has already done for the
+    // command line.
+    let mut ignored_keys: HashSet<Keysym> = if args.ignore_key.is_empty() {
+        config.ignore_key
+            .iter()
+            .map(|name| keysym::parse(name).context("in config file"))
+            .collect::<Result<_>>()?
+    } else {
+        args.ignore_key.iter().copied().collect()
+    };
+    let key_file = args.ignore_key_file.clone().or(config.ignore_key_file);
+    if let Some(path) = key_file {
+        ignored_keys.extend(config::load_key_list(&path)?);
+    }
+
+    let reveal_keys = if args.reveal_key.is_empty() {
+        config.reveal_key
+            .iter()
+            .map(|name| keysym::parse(name).context("in config file"))
+            .collect::<Result<_>>()?
+    } else {
+        args.reveal_key.iter().copied().collect()
+    };
+
+    // Likewise title patterns.
+    let excluded_titles = if args.exclude_title.is_empty() {
+        config.exclude_title
+            .iter()
+            .map(|pattern| Regex::new(pattern).context("in config file"))
+            .collect::<Result<_>>()?
+    } else {
+        args.exclude_title.clone()
+    };
+
+    // Likewise hotkeys.
+    let hotkey = |cli: &Option<Hotkey>, file: Option<String>| {
+        match (cli, file) {
+            (Some(key), _) => Ok(Some(key.clone())),
+            (None, Some(text)) => {
+                hotkey::parse(&text).context("in config file").map(Some)
+            }
+            (None, None) => Ok(None),
+        }
+    };
+    let toggle_key = hotkey(&args.toggle_key, config.toggle_key)?;
+    let hide_key = hotkey(&args.hide_key, config.hide_key)?;
+    let mut profile_keys = config
+        .profile_key
+        .into_iter()
+        .map(|(name, text)| {
+            let key = hotkey::parse(&text).context("in config file")?;
+            Ok((key, name))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // Tables don't keep their order, so sort for the sake of consistency.
+    profile_keys.sort_by(|a, b| a.1.cmp(&b.1));
+
+    // Leaving out the window types means the defaults, so turning the check
+    // off takes a flag of its own.
+    let skipped_window_types =
+        if args.no_window_type_detect || config.no_window_type_detect {
+            vec![]
+        } else {
+            match cli_or_file(&args.skip_window_type, config.skip_window_type)
+            {
+                types if types.is_empty() => {
+                    Settings::default().skipped_window_types
+                }
+                types => types,
+            }
+        };
+
+    let settings = Settings {
+        ignored_mods,
+        chord_mods,
+        ignored_keys,
+        ignored_groups,
+        reveal_keys,
+        excluded_classes: cli_or_file(
+            &args.exclude_class,
+            config.exclude_class,
+        ),
+        excluded_titles,
+        only_classes: cli_or_file(&args.only_class, config.only_class),
+        only_outputs: cli_or_file(&args.only_output, config.only_output),
+        only_over_focus: args.only_over_focus || config.only_over_focus,
+        excluded_hover_classes: cli_or_file(
+            &args.exclude_hover_class,
+            config.exclude_hover_class,
+        ),
+        input: args.input.or(config.input).unwrap_or_default(),
+        devices: cli_or_file(&args.device, config.device),
+        ignored_devices: cli_or_file(&args.ignore_device, config.ignore_device),
+        virtual_detect: !(args.no_virtual_detect || config.no_virtual_detect),
+        virtual_devices: cli_or_file(
+            &args.virtual_device,
+            config.virtual_device,
+        ),
+        device_policies: config.device_policy,
+        screenshot_tools: screenshot::BUILTIN
+            .iter()
+            .map(|&name| name.to_string())
+            .chain(cli_or_file(&args.screenshot_tool, config.screenshot_tool))
+            .collect(),
+        idle_timeout: args.timeout.or(config.timeout)
+            .map(Duration::from_secs),
+        max_hidden: args.max_hidden.or(config.max_hidden)
+            .map(Duration::from_secs),
+        sync_alarms: args.sync_alarms || config.sync_alarms,
+        jitter: args.jitter.or(config.jitter),
+        hide_on: args.hide_on.or(config.hide_on).unwrap_or_default(),
+        min_keystrokes: args.min_keystrokes.or(config.min_keystrokes)
+            .unwrap_or(1),
+        ignore_repeat: args.ignore_repeat || config.ignore_repeat,
+        banish: args.banish.or(config.banish).unwrap_or_default(),
+        method,
+        hide_cmd,
+        show_cmd,
+        hide_scope: args.hide_scope.or(config.hide_scope).unwrap_or_default(),
+        corner: args.corner.or(config.corner).unwrap_or_default(),
+        restore: !(args.no_restore || config.no_restore),
+        park: args.park || config.park,
+        start_hidden: args.start_hidden || config.start_hidden,
+        fullscreen_detect: !(args.no_fullscreen_detect
+            || config.no_fullscreen_detect),
+        skipped_window_types,
+        grab_detect: args.grab_detect || config.grab_detect,
+        motion_reveals: !(args.no_motion_reveal || config.no_motion_reveal),
+        touch_reveals: !(args.no_touch_reveal || config.no_touch_reveal),
+        scroll_reveals: !(args.ignore_scrolling || config.ignore_scrolling),
+        toggle_key,
+        hide_key,
+        grab_hotkeys: !(args.no_grab_hotkeys || config.no_grab_hotkeys),
+        profile_keys,
+        dry_run: args.dry_run,
+        trace_events: args.trace_events,
+    };
+    Ok((settings, hooks))
+}
+
+/// Picks between a list from the command line and its counterpart from the
+/// config file. Command line lists replace, rather than add to, the ones from
+/// the file, so that you can override the file without editing it.
+fn cli_or_file<T: Clone>(cli: &[T], file: Vec<T>) -> Vec<T> {
+    if cli.is_empty() {
+        file
+    } else {
+        cli.to_vec()
+    }
 }
 
 /// Convenient clap-compatible names for modifier keys. This bridges between the
 /// enum used to generate the names on the commandline, and the X bits.
-#[derive(Copy, Clone, Debug, ValueEnum)]
+#[derive(Copy, Clone, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u32)]
 enum Mod {
     Shift = KeyButMask::SHIFT.bits(),
@@ -61,238 +746,1102 @@ impl From<Mod> for KeyButMask {
 fn main() -> Result<()> {
     let args = Rxbanish::parse();
 
-    // Combine all user-specified ignore mods.
-    let ignored_mods = KeyButMask::from_bits_truncate(args.ignore_mod
-        .into_iter()
-        .fold(0, |a, b| a | b as u32));
-
-    // Let's go!
-    let (conn, screen_num) = Connection::connect_with_extensions(
-        // Display choice
-        None,
-        // Mandatory extensions
-        &[Extension::XFixes, Extension::Input],
-        // Optional extensions
+    if let Some(cmd) = &args.command {
+        return run_command(&args, cmd);
+    }
+
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    logging::init(level, args.log_format);
+
+    // Report errors through the log, rather than letting main print them, so
+    // that they come out in the format the user asked for.
+    if let Err(e) = banish(args) {
+        error!("{e:#}");
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Carries out one of the subcommands, which mostly means talking to an
+/// rxbanish that's already running.
+fn run_command(args: &Rxbanish, cmd: &Cmd) -> Result<()> {
+    let display = args.display.as_deref();
+    let path = socket::default_path(display);
+    let socket = || {
+        path.as_deref()
+            .context("XDG_RUNTIME_DIR isn't set, so there's no socket")
+    };
+    match cmd {
+        Cmd::Send { command } => {
+            println!("{}", socket::send(socket()?, command)?);
+        }
+        Cmd::Status => show_status(socket()?)?,
+        Cmd::Pause => {
+            socket::send(socket()?, "pause")?;
+        }
+        Cmd::Resume => {
+            socket::send(socket()?, "resume")?;
+        }
+        Cmd::Toggle => {
+            socket::send(socket()?, "toggle")?;
+        }
+        Cmd::Profile { name } => {
+            let command = match name {
+                Some(name) => format!("profile {name}"),
+                None => "profile".to_string(),
+            };
+            socket::send(socket()?, &command)?;
+        }
+        Cmd::Hide => {
+            // If rxbanish is running, it had better do the hiding, so that it
+            // knows to bring the pointer back when it's used.
+            let done = match &path {
+                Some(path) => socket::try_send(path, "hide")?.is_some(),
+                None => false,
+            };
+            if !done {
+                hide_until_interrupted(display)?;
+            }
+        }
+        Cmd::Show => {
+            let done = match &path {
+                Some(path) => socket::try_send(path, "show")?.is_some(),
+                None => false,
+            };
+            if !done {
+                bail!(
+                    "rxbanish isn't running, so it hasn't hidden the pointer; \
+                     X only lets whoever hid it show it again"
+                );
+            }
+        }
+        Cmd::Devices { json } => {
+            let (settings, _) = load_settings(args, args.profile.as_deref())?;
+            show_devices(display, &settings, *json)?;
+        }
+        Cmd::Check => std::process::exit(check(args)),
+    }
+    Ok(())
+}
+
+/// Tells the user about the input devices on `display`, and what we'd make of
+/// them with `settings`.
+fn show_devices(
+    display: Option<&str>,
+    settings: &Settings,
+    json: bool,
+) -> Result<()> {
+    let (conn, _) = xcb::Connection::connect_with_extensions(
+        display,
+        &[xcb::Extension::Input],
         &[],
     )?;
+    let devices = rxbanish_core::describe_devices(&conn, settings)?;
+    if json {
+        println!("{}", serde_json::to_string(&devices)?);
+        return Ok(());
+    }
+
+    let width = devices.iter().map(|d| d.name.len()).max().unwrap_or(0);
+    println!(
+        "{:>3}  {:width$}  {:18}  {:6}  {:6}  CLASSES",
+        "ID", "NAME", "USE", "EVENTS", "POLICY",
+    );
+    for device in &devices {
+        // There's no policy for devices we don't listen to at all.
+        let (events, policy) = match device.snoop {
+            Some(snoop) => (snoop.as_str(), device.policy.as_str()),
+            None => ("-", "-"),
+        };
+        println!(
+            "{:>3}  {:width$}  {:18}  {:6}  {:6}  {}",
+            device.id,
+            device.name,
+            device.usage,
+            events,
+            policy,
+            device.classes.join(", "),
+        );
+    }
+    Ok(())
+}
+
+/// Checks that we could run with `args`, tells the user how that went, and
+/// returns the exit code to go with it.
+fn check(args: &Rxbanish) -> i32 {
+    let report = |pass: bool, what: String| {
+        println!("{}  {what}", if pass { "PASS" } else { "FAIL" });
+        pass
+    };
+
+    // A broken config file stops the daemon as surely as a missing extension,
+    // but doesn't stop us checking the rest, using the defaults.
+    let settings = match load_settings(args, args.profile.as_deref()) {
+        Ok((settings, _)) => {
+            report(true, "config file".into());
+            Some(settings)
+        }
+        Err(e) => {
+            report(false, format!("config file: {e:#}"));
+            None
+        }
+    };
+    let mut passed = settings.is_some();
+    let settings = settings.unwrap_or_default();
+
+    let conn = xcb::Connection::connect_with_extensions(
+        args.display.as_deref(),
+        &[],
+        &[xcb::Extension::XFixes, xcb::Extension::Input],
+    );
+    let conn = match conn {
+        Ok((conn, _)) => {
+            let vendor = conn.get_setup().vendor().to_utf8().into_owned();
+            report(true, format!("X server: {vendor}"));
+            conn
+        }
+        Err(e) => {
+            report(false, format!("X server: {e}"));
+            return 2;
+        }
+    };
+    let has = |ext| conn.active_extensions().any(|e| e == ext);
+
+    // We can get by without HideCursor, but not well.
+    let xfixes = if has(xcb::Extension::XFixes) {
+        conn.wait_for_reply(conn.send_request(&xfixes::QueryVersion {
+            client_major_version: 5,
+            client_minor_version: 0,
+        }))
+        .map(|v| (v.major_version(), v.minor_version()))
+        .ok()
+    } else {
+        None
+    };
+    passed &= match xfixes {
+        Some((major, minor)) if major >= 4 => {
+            report(true, format!("XFixes {major}.{minor}"))
+        }
+        Some((major, minor)) => report(
+            false,
+            format!("XFixes {major}.{minor}: need 4.0 for HideCursor"),
+        ),
+        None => report(false, "XFixes: not available".into()),
+    };
+
+    if !has(xcb::Extension::Input) {
+        report(false, "XInput: not available".into());
+        return 1;
+    }
+    let xi2 = conn.wait_for_reply(conn.send_request(
+        &xcb::xinput::XiQueryVersion {
+            major_version: 2,
+            minor_version: 2,
+        },
+    ));
+    match xi2 {
+        Ok(v) => report(
+            true,
+            format!(
+                "XInput {}.{}: raw events",
+                v.major_version(),
+                v.minor_version()
+            ),
+        ),
+        Err(_) => report(true, "XInput 1: listening device by device".into()),
+    };
+
+    passed &= match rxbanish_core::describe_devices(&conn, &settings) {
+        Ok(devices) => {
+            let hiding = devices
+                .iter()
+                .filter(|d| d.snoop.is_some() && d.policy.hides())
+                .count();
+            report(
+                hiding > 0,
+                format!(
+                    "input devices: {} found, {hiding} can hide the pointer",
+                    devices.len()
+                ),
+            )
+        }
+        Err(e) => report(false, format!("input devices: {e}")),
+    };
 
-    // Identify the root window. We'll use this for event registration and
-    // cursor manipulation. Basically everything.
-    let setup = conn.get_setup();
-    let screen = setup.roots().nth(screen_num as usize).unwrap();
-    let root = screen.root();
-
-    // Check the version of XFixes at the server. For reasons I don't understand
-    // this appears to be load-bearing; without it, the XFixes calls will return
-    // an error. That's particularly strange since the C programs I'm reading
-    // don't bother with this.
-    let xfvresp =
+    if passed {
+        0
+    } else {
+        1
+    }
+}
+
+/// Hides the pointer on `display` without the rest of rxbanish, until we get
+/// SIGINT or SIGTERM or lose the X server.
+///
+/// XFixes keeps the pointer hidden only as long as the client that hid it
+/// stays connected, so we can't just hide it and exit; the server would bring
+/// it straight back. Instead we hang around, and the pointer reappears by
+/// itself when we go.
+fn hide_until_interrupted(display: Option<&str>) -> Result<()> {
+    let signals = SignalFd::new(&[libc::SIGINT, libc::SIGTERM])?;
+    let (conn, _) = xcb::Connection::connect_with_extensions(
+        display,
+        &[xcb::Extension::XFixes],
+        &[],
+    )?;
+    // See Session::connect for why this matters.
+    let version =
         conn.wait_for_reply(conn.send_request(&xfixes::QueryVersion {
             client_major_version: 4,
             client_minor_version: 0,
         }))?;
-    if xfvresp.major_version() < 4 {
+    if version.major_version() < 4 {
         bail!("No compatible Xfixes version available");
     }
+    for screen in conn.get_setup().roots() {
+        conn.send_and_check_request(&xfixes::HideCursor {
+            window: screen.root(),
+        })?;
+    }
 
-    // Alright, snoop on all input devices. It's kind of terrifying that you can
-    // do this in X tbh.
-    let rawmotion = snoop_xinput(&conn, root)?;
-
-    // Avoid generating excess hide/show pointer calls by tracking state.
-    let mut state = State::Shown;
-
+    let sources = [
+        (Source::Signals, signals.as_raw_fd()),
+        (Source::Input, conn.as_raw_fd()),
+    ];
     loop {
-        let target_state = match conn.wait_for_event()? {
-            Event::Input(
-                xinput::Event::RawMotion(_) | xinput::Event::RawButtonPress(_)
-                | xinput::Event::DeviceValuator(_) | xinput::Event::DeviceMotionNotify(_)
-                | xinput::Event::DeviceButtonPress(_) | xinput::Event::DeviceButtonRelease(_)
-            ) => {
-                // Any movement or button is enough to reveal the cursor.
-                State::Shown
-            }
-            Event::Input(xinput::Event::DeviceKeyRelease(e)) => {
-                // We only hide the cursor on key _release_ because otherwise we
-                // can't distinguish e.g. tapping shift using the event
-                // interface that we're using.
-                if e.state().intersects(ignored_mods) {
-                    state
-                } else {
-                    State::Hidden
-                }
-            }
-            Event::Input(xinput::Event::DevicePresenceNotify(e)) => {
-                if e.devchange() == DeviceChange::Enabled {
-                    snoop_device(&conn, root, rawmotion, e.device_id())?;
+        for source in wait(&sources)? {
+            match source {
+                Source::Signals => {
+                    if signals.read()?.is_some() {
+                        return Ok(());
+                    }
                 }
-                state
-            }
-            Event::X(x::Event::MappingNotify(_)) => {
-                // We appear to get these as a side effect of device changes. We
-                // don't need them for anything.
-                state
+                // We didn't ask for any events, but we still need to notice
+                // if the server goes away.
+                _ => while conn.poll_for_event()?.is_some() {},
             }
-            e => {
-                // This is _really_ not supposed to happen if I did the X event
-                // registration correctly...
-                println!("OTHER {e:?}");
-                state
+        }
+    }
+}
+
+/// Asks the rxbanish listening at `path` what it's up to, and tells the user.
+fn show_status(path: &Path) -> Result<()> {
+    let status: control::Status =
+        serde_json::from_str(&socket::send(path, "status")?)
+            .context("can't make sense of rxbanish's status")?;
+    let state = if status.paused {
+        "paused"
+    } else if status.suspended {
+        "suspended"
+    } else {
+        "active"
+    };
+    println!("state:   {state}");
+    println!("pointer: {}", if status.hidden { "hidden" } else { "shown" });
+    if let Some(backend) = status.backend.and_then(|b| b.to_possible_value()) {
+        println!("backend: {}", backend.get_name());
+    }
+    if let Some(profile) = &status.profile {
+        println!("profile: {profile}");
+    }
+    println!("devices:");
+    for device in &status.devices {
+        println!("    {device}");
+    }
+    Ok(())
+}
+
+/// Does the actual work of hiding the pointer, until it's time to exit.
+fn banish(mut args: Rxbanish) -> Result<()> {
+    // Daemons don't stay in the directory they were started in, so make sure
+    // we can find the config file again when we reload it.
+    if args.daemon {
+        args.config = args.config.map(std::path::absolute).transpose()?;
+    }
+
+    let (settings, hooks) = load_settings(&args, args.profile.as_deref())?;
+
+    // We handle SIGHUP as a request to reload the config file, and SIGINT and
+    // SIGTERM as requests to exit tidily, so that we clean up after ourselves.
+    // Set this up early so that a HUP that arrives during startup doesn't kill
+    // us.
+    let signals =
+        SignalFd::new(&[libc::SIGHUP, libc::SIGINT, libc::SIGTERM])?;
+
+    // Work out what we're running under before trying to connect to X, so
+    // that if it's not going to work, we can say why rather than falling over
+    // with a confusing error from XCB.
+    let mut backend = match args.backend {
+        Some(backend) => backend,
+        None => Backend::detect(args.display.is_some())?,
+    };
+    // With no X server, or if the user asks, we can still work under a
+    // compositor we know how to ask. Xwayland stays the default where there
+    // is one, since it can do a lot that this can't; see `adapt`.
+    if backend == Backend::Wayland {
+        // The user's own commands stand in for any compositor.
+        let compositor = match (&settings.hide_cmd, &settings.show_cmd) {
+            (Some(hide), Some(show)) if settings.method == Method::Command => {
+                Some(wayland::Compositor::External {
+                    hide: hide.clone(),
+                    show: show.clone(),
+                })
             }
+            _ => wayland::Compositor::detect(),
         };
-        match (state, target_state) {
-            (State::Shown, State::Hidden) => {
-                hide_pointer(&conn, root)?;
+        if let Some(compositor) = compositor {
+            info!(
+                compositor:?, chosen = args.backend.is_some();
+                "not using X"
+            );
+            return wayland::banish(
+                compositor, args, settings, hooks, signals,
+            );
+        }
+    }
+    backend.check()?;
+
+    // Look out for other pointer hiders, which we'd only fight with.
+    competitors::check(args.on_competitor, args.display.as_deref())?;
+
+    // Let's go! If this first connection fails, the problem is more likely to
+    // be the user's setup than a server restart, so we don't retry it.
+    let session =
+        Session::connect(args.display.as_deref(), args.replace, &settings)?;
+    backend = backend.adapt(&session, args.backend.is_some());
+
+    // Now that we know we can talk to X, it's safe to disappear into the
+    // background. This has to happen before we start any threads.
+    let _pid_file = if args.daemon {
+        daemon::daemonize(args.log_file.as_deref(), args.pid_file.as_deref())?
+    } else {
+        None
+    };
+
+    let socket = socket::default_path(args.display.as_deref());
+    let server = XServer {
+        x: session,
+        banisher: Banisher::new(settings),
+        backend,
+        commands: external::Runner::default(),
+        pending: None,
+    };
+    let mut daemon = Daemon::new(args, server, hooks, signals, socket)?;
+
+    loop {
+        match daemon.serve() {
+            Ok(()) => return Ok(()),
+            Err(e) if lost_connection(&e) => {
+                warn!("lost connection to X server: {e}");
+            }
+            Err(e) => return Err(e),
+        }
+
+        // The server has gone away, probably because the user logged out or
+        // it crashed. Keep trying to get it back, backing off so that we're
+        // not hammering on the door if it's gone for good.
+        let mut delay = RECONNECT_DELAY_MIN;
+        daemon.hider.x = loop {
+            info!("reconnecting in {}s", delay.as_secs());
+            if daemon.sleep(delay)? {
+                return Ok(());
             }
-            (State::Hidden, State::Shown) => {
-                show_pointer(&conn, root)?;
+            let connected = Session::connect(
+                daemon.args.display.as_deref(),
+                daemon.args.replace,
+                daemon.hider.settings(),
+            );
+            match connected {
+                Ok(session) => break session,
+                Err(e) if lost_connection(&e) => {
+                    info!("can't reconnect to X server: {e}");
+                    delay = (delay * 2).min(RECONNECT_DELAY_MAX);
+                }
+                Err(e) => return Err(e),
             }
-            _ => (),
+        };
+        info!("reconnected to X server");
+    }
+}
+
+/// How long we wait before our first attempt at reconnecting to the X server
+/// after losing it. The wait doubles after each failed attempt, up to
+/// `RECONNECT_DELAY_MAX`.
+const RECONNECT_DELAY_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+
+/// Whatever hides the pointer for the main loop, and hears about the input
+/// that decides when. That's a `Banisher` on an X server, or, without one,
+/// `wayland::Typist`; `Daemon` takes care of everything else the same way for
+/// both.
+trait Hider {
+    /// Gets going, on a freshly connected server if there is one. This needs
+    /// doing before anything else.
+    fn start(&mut self) -> Result<()>;
+
+    /// What we're running under, for the status.
+    fn backend(&self) -> Backend;
+
+    /// The settings we're following.
+    fn settings(&self) -> &Settings;
+
+    /// Starts following `settings` instead. Follow this with
+    /// `settings_changed` to catch up, if we're connected.
+    fn set_settings(&mut self, settings: Settings);
+
+    /// Catches up with new settings.
+    fn settings_changed(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// What's been decided about the pointer, for the status.
+    fn policy(&self) -> &Policy;
+
+    /// Collects the times we've hidden or shown the pointer since last time.
+    fn take_changes(&mut self) -> Vec<Change>;
+
+    /// Collects the most recent request to switch profiles, if there's been
+    /// one since last time.
+    fn take_profile_switch(&mut self) -> Option<Option<String>>;
+
+    /// Why we're leaving the pointer alone for now, if we are.
+    fn suspension(&self) -> Option<Suspension> {
+        None
+    }
+
+    /// Names the input devices we're listening to, in order.
+    fn devices(&self) -> Vec<String>;
+
+    /// The file descriptors that become readable when there's input.
+    fn fds(&self) -> Vec<RawFd>;
+
+    /// Takes in all the input there is, without waiting for more. Returns
+    /// `true` if it's time to exit.
+    fn handle_input(&mut self) -> Result<bool>;
+
+    /// Carries out `cmd`. Profiles are the daemon's business.
+    fn run(&mut self, cmd: Command) -> Result<()>;
+
+    /// Acts on the deadline from `schedule` having passed. It's harmless to
+    /// call this early.
+    fn handle_timeout(&mut self) -> Result<()>;
+
+    /// Works out when `handle_timeout` next needs calling, if ever.
+    fn schedule(&mut self) -> Result<Option<Instant>>;
+
+    /// Gets everything out of the door before we wait. Returns `true` if
+    /// input has turned up in the meantime that waiting wouldn't wake us for.
+    fn flush(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Hiding the pointer on an X server.
+struct XServer {
+    x: Session,
+    banisher: Banisher,
+    backend: Backend,
+    /// The user's commands for hiding and showing the pointer, if they've
+    /// given us some.
+    commands: external::Runner,
+    /// An event read off the connection while we were getting ready to wait,
+    /// which still needs handling.
+    pending: Option<xcb::Event>,
+}
+
+impl XServer {
+    /// Runs the user's command to hide or show the pointer, if that's how
+    /// we're meant to be doing it.
+    fn run_command(&mut self, hidden: bool) {
+        let settings = self.banisher.settings();
+        if settings.method != Method::Command || settings.dry_run {
+            return;
+        }
+        let command = if hidden {
+            &settings.hide_cmd
+        } else {
+            &settings.show_cmd
+        };
+        if let Some(command) = command {
+            self.commands.run(command, hidden);
         }
-        state = target_state;
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-enum State { Hidden, Shown }
+impl Hider for XServer {
+    fn start(&mut self) -> Result<()> {
+        self.pending = None;
+        self.banisher.start(&self.x)
+    }
 
-/// Registers to be notified of all input events on a certain window, which in
-/// our case is always the root window.
-fn snoop_xinput(conn: &Connection, window: Window) -> anyhow::Result<bool> {
-    let mut rawmotion = false;
+    fn backend(&self) -> Backend {
+        self.backend
+    }
 
-    // Check what XInput version we've got. We want at least 2 for raw motion
-    // events, apparently.
-    let xiqv_response =
-        conn.wait_for_reply(conn.send_request(&xinput::XiQueryVersion {
-            major_version: 2,
-            minor_version: 0,
-        }));
-    if xiqv_response.is_ok() {
-        // Register for raw pointer-related events.
-        conn.send_and_check_request(&xinput::XiSelectEvents {
-            window,
-            masks: &[xinput::EventMaskBuf::new(
-                xinput::Device::AllMaster,
-                &[xinput::XiEventMask::RAW_MOTION
-                    | xinput::XiEventMask::RAW_BUTTON_PRESS],
-            )],
-        })?;
+    fn settings(&self) -> &Settings {
+        self.banisher.settings()
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        self.banisher.set_settings(settings);
+    }
+
+    fn settings_changed(&mut self) -> Result<()> {
+        self.banisher.settings_changed(&self.x)
+    }
+
+    fn policy(&self) -> &Policy {
+        self.banisher.policy()
+    }
+
+    /// With `Method::Command`, this is also where the pointer really comes
+    /// and goes.
+    fn take_changes(&mut self) -> Vec<Change> {
+        let changes = self.banisher.take_changes();
+        for change in &changes {
+            self.run_command(change.hidden);
+        }
+        changes
+    }
 
-        println!("using xinput2 raw motion events");
+    fn take_profile_switch(&mut self) -> Option<Option<String>> {
+        self.banisher.take_profile_switch()
+    }
 
-        rawmotion = true;
+    fn suspension(&self) -> Option<Suspension> {
+        self.banisher.suspension(&self.x)
     }
 
-    let list_reply =
-        conn.wait_for_reply(conn.send_request(&xinput::ListInputDevices {}))?;
+    fn devices(&self) -> Vec<String> {
+        self.banisher.devices(&self.x)
+    }
+
+    fn fds(&self) -> Vec<RawFd> {
+        self.x.fds()
+    }
 
-    for devinfo in list_reply.devices() {
-        if !matches!(
-            devinfo.device_use(),
-            DeviceUse::IsXExtensionKeyboard | DeviceUse::IsXExtensionPointer
-        ) {
-            continue;
+    fn handle_input(&mut self) -> Result<bool> {
+        // Events may already have been read off the connection, in which
+        // case its file descriptor won't say so, so we have to ask.
+        let pending = self.pending.take();
+        if let Some(event) = pending {
+            if self.banisher.handle_event(&mut self.x, event)? {
+                return Ok(true);
+            }
+        }
+        while let Some(event) = self.x.poll_for_event()? {
+            if self.banisher.handle_event(&mut self.x, event)? {
+                return Ok(true);
+            }
         }
-        snoop_device(conn, window, rawmotion, devinfo.device_id())?;
+        // Make sure the connection is still up before the watchdog hears that
+        // all's well.
+        self.x.conn().has_error()?;
+        Ok(false)
+    }
+
+    fn run(&mut self, cmd: Command) -> Result<()> {
+        self.banisher.run(&mut self.x, cmd)
     }
 
-    // Apparently secret code for Device Presence class, discovered by reading C
-    // headers.
-    const DEVICE_PRESENCE: u32 = 0x1_0000;
+    fn handle_timeout(&mut self) -> Result<()> {
+        self.banisher.handle_timeout(&mut self.x)
+    }
+
+    /// The user's commands need checking on too; see `external::Runner`.
+    fn schedule(&mut self) -> Result<Option<Instant>> {
+        let deadline = self.banisher.schedule(&mut self.x)?;
+        Ok([deadline, self.commands.poll()].into_iter().flatten().min())
+    }
 
-    conn.send_and_check_request(&xinput::SelectExtensionEvent {
-        window,
-        classes: &[DEVICE_PRESENCE],
-    })?;
+    fn flush(&mut self) -> Result<bool> {
+        // Make sure anything we've asked for actually goes out before we go
+        // to sleep waiting for the answer.
+        self.x.conn().flush()?;
+        // Waiting on replies may have read more events off the connection,
+        // which wouldn't wake us.
+        self.pending = self.x.poll_for_event()?;
+        Ok(self.pending.is_some())
+    }
+}
 
+/// Nobody but us knows to undo the user's command for hiding the pointer, so
+/// however we leave, it has to be shown on the way out. Dropping the runner
+/// afterwards waits for that.
+impl Drop for XServer {
+    fn drop(&mut self) {
+        if self.banisher.hidden() {
+            self.run_command(false);
+        }
+    }
+}
 
-    Ok(rawmotion)
+/// Everything the main loop keeps track of, around `hider`. With X, that all
+/// outlives any one connection to the server.
+struct Daemon<H: Hider> {
+    args: Rxbanish,
+    /// The profile from the config file we're using, if any.
+    profile: Option<String>,
+    hider: H,
+    hooks: hooks::Runner,
+    signals: SignalFd,
+    inbox: Inbox,
+    socket: Option<ControlSocket>,
+    /// A watch on the config file, so we can reload it when it changes.
+    config_watcher: Option<FileWatcher>,
+    /// Our D-Bus interface, if we have one.
+    bus: Option<dbus::Bus>,
+    #[cfg(feature = "tray")]
+    tray: Option<tray::Tray>,
+    notifier: Option<systemd::Notifier>,
+    watchdog: Option<Duration>,
+    timers: Schedule<Alarm>,
 }
 
-/// Registers to snoop on a specific device given by ID.
-fn snoop_device(
-    conn: &Connection,
-    window: Window,
-    rawmotion: bool,
-    device_id: u8,
-) -> Result<()> {
-    let dev_reply =
-        conn.wait_for_reply(conn.send_request(&xinput::OpenDevice {
-            device_id,
-        }))?;
+/// Things we set timers for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Alarm {
+    /// The hider's next deadline, for its timeouts.
+    Hider,
+    /// Time to tell systemd we're still alive.
+    Watchdog,
+    /// Time to try the X server again.
+    Reconnect,
+}
+
+impl<H: Hider> Daemon<H> {
+    /// Sets up everything around `hider` that lets people see and control
+    /// what we're doing, with our control socket at `socket`, if we're to
+    /// have one. Most of that is a nicety, so if something's missing, like
+    /// a session bus, we carry on without it.
+    fn new(
+        args: Rxbanish,
+        hider: H,
+        hooks: Hooks,
+        signals: SignalFd,
+        socket: Option<PathBuf>,
+    ) -> Result<Self> {
+        // Let people control us over D-Bus.
+        let (remote, inbox) = control::channel()?;
+        // Also a nicety: hearing from logind when the machine wakes up, so
+        // that we can get back in step with the X server, or at least show
+        // the pointer.
+        if let Err(e) = logind::watch(remote.clone()) {
+            warn!("not watching for sleep and wakeup: {e}");
+        }
+        let bus = match dbus::serve(remote.clone()) {
+            Ok(bus) => Some(bus),
+            Err(e) => {
+                warn!("not providing D-Bus interface: {e}");
+                None
+            }
+        };
+        // ...and through a socket, for the benefit of `rxbanish send`.
+        let socket = socket.and_then(|path| match ControlSocket::bind(&path) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                warn!("not providing control socket: {e}");
+                None
+            }
+        });
+
+        // Reload the config file whenever it changes, if there's one to
+        // watch. Editors often save a file by replacing it, so we watch its
+        // directory, which needn't exist if nobody's written a config file.
+        let config_path = args.config.clone().or_else(config::default_path);
+        let config_watcher = match config_path.clone() {
+            Some(path) if !args.no_watch_config => {
+                match FileWatcher::new(&path) {
+                    Ok(watcher) => Some(watcher),
+                    Err(e) => {
+                        debug!("not watching config file: {e}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        // Another nicety: an icon in the tray, if the user wants one.
+        #[cfg(feature = "tray")]
+        let tray = match args.tray {
+            true => match tray::Tray::start(remote, config_path) {
+                Ok(tray) => Some(tray),
+                Err(e) => {
+                    warn!("not showing tray icon: {e}");
+                    None
+                }
+            },
+            false => None,
+        };
+        #[cfg(not(feature = "tray"))]
+        if args.tray {
+            warn!(
+                "not showing tray icon: rxbanish was built without tray support"
+            );
+        }
+
+        // If we're a systemd service, now's the time to say we're ready,
+        // since the hider is all set up.
+        let notifier = systemd::Notifier::from_env()?;
+        if let Some(notifier) = &notifier {
+            notifier.ready()?;
+        }
+        let watchdog = notifier.as_ref().and_then(|n| n.watchdog_interval());
+        let mut timers = Schedule::new()?;
+        timers.set(
+            Alarm::Watchdog,
+            watchdog.map(|interval| Instant::now() + interval),
+        )?;
+
+        Ok(Self {
+            profile: args.profile.clone(),
+            args,
+            hider,
+            hooks: hooks::Runner::new(hooks),
+            signals,
+            inbox,
+            socket,
+            config_watcher,
+            bus,
+            #[cfg(feature = "tray")]
+            tray,
+            notifier,
+            watchdog,
+            timers,
+        })
+    }
+
+    /// Hides and shows the pointer until it's time to exit, at which point this
+    /// returns `Ok`. Losing the connection to the X server shows up as an
+    /// error, which the caller can recover from by reconnecting.
+    fn serve(&mut self) -> Result<()> {
+        self.hider.start()?;
+
+        loop {
+            // We take all the input there is before doing anything else, so
+            // that the work below happens once per wakeup rather than once
+            // per event.
+            if self.hider.handle_input()? {
+                return Ok(());
+            }
+            // A hotkey may have asked for another profile.
+            self.take_profile_switch()?;
 
-    let mut event_list = vec![];
-
-    for c in dev_reply.class_info() {
-        match c.class_id() {
-            InputClass::Key => {
-                // We don't actually need key press events.
-                //event_list.push(make_event_code(devinfo.device_id(), c.event_type_base()));
-
-                // Apparently event_type_base + 1 for key inputs is release?
-                // I learned this by READING C HEADERS. Not sure where
-                // you're supposed to learn it.
-                event_list.push(make_event_code(
-                        device_id,
-                        c.event_type_base() + 1,
-                ));
-            }
-            InputClass::Valuator => {
-                if rawmotion {
-                    continue;
+            // We check for anything that's due every time round, rather than
+            // waiting for the timer to wake us, so that a flood of input
+            // can't hold it up.
+            for alarm in self.timers.due()? {
+                match alarm {
+                    Alarm::Hider => self.hider.handle_timeout()?,
+                    Alarm::Watchdog => self.feed_watchdog()?,
+                    // Only set while we're between servers.
+                    Alarm::Reconnect => (),
                 }
-                event_list.push(make_event_code(
-                        device_id,
-                        c.event_type_base(),
-                ));
-            }
-            InputClass::Button => {
-                if rawmotion {
-                    continue;
+            }
+            self.publish();
+
+            self.timers.set(Alarm::Hider, self.hider.schedule()?)?;
+            if self.hider.flush()? {
+                continue;
+            }
+
+            for source in wait(&self.sources(true))? {
+                if self.dispatch(source)? {
+                    return Ok(());
                 }
-                event_list.push(make_event_code(
-                        device_id,
-                        c.event_type_base(),
-                ));
-                // Here again, event type base + 1 appears to be "release."
-                event_list.push(make_event_code(
-                        device_id,
-                        c.event_type_base() + 1,
-                ));
             }
-            _ => (),
         }
     }
 
-    conn.send_and_check_request(&xinput::CloseDevice {
-        device_id,
-    })?;
+    /// Lists what there is to wait on. While we're between X servers, and
+    /// not `connected`, that's only signals and timers; commands wait in line
+    /// until we're back.
+    fn sources(&self, connected: bool) -> Vec<(Source, RawFd)> {
+        let mut sources = vec![
+            (Source::Signals, self.signals.as_raw_fd()),
+            (Source::Timers, self.timers.as_raw_fd()),
+        ];
+        if connected {
+            let fds = self.hider.fds().into_iter();
+            sources.extend(fds.map(|fd| (Source::Input, fd)));
+            sources.push((Source::Inbox, self.inbox.as_raw_fd()));
+            if let Some(socket) = &self.socket {
+                sources.push((Source::Socket, socket.as_raw_fd()));
+            }
+            if let Some(watcher) = &self.config_watcher {
+                sources.push((Source::Config, watcher.as_raw_fd()));
+            }
+        }
+        sources
+    }
 
-    conn.send_and_check_request(&xinput::SelectExtensionEvent {
-        window,
-        classes: &event_list,
-    })?;
+    /// Takes care of whatever `source` has for us. Returns `true` if it's time
+    /// to exit.
+    fn dispatch(&mut self, source: Source) -> Result<bool> {
+        match source {
+            // Input and timers get taken care of at the top of the loop.
+            Source::Input | Source::Timers => (),
+            Source::Signals => {
+                if self.handle_signals()? {
+                    return Ok(true);
+                }
+                // The settings may have changed if we reloaded.
+                self.hider.settings_changed()?;
+            }
+            Source::Config => {
+                // The watcher is only a source if we have one.
+                if self.config_watcher.as_ref().unwrap().changed()? {
+                    debug!("config file has changed");
+                    self.reload();
+                    self.hider.settings_changed()?;
+                }
+            }
+            Source::Inbox => {
+                let cmds: Vec<Command> = self.inbox.recv().collect();
+                for cmd in cmds {
+                    self.hider.run(cmd)?;
+                    self.take_profile_switch()?;
+                }
+            }
+            Source::Socket => {
+                // The socket is only a source if we have one.
+                while let Some(mut client) =
+                    self.socket.as_ref().unwrap().accept()?
+                {
+                    let reply = match client.request() {
+                        // Unlike other commands, this can go wrong in ways
+                        // the client should hear about.
+                        Ok(Request::Command(Command::Profile(name))) => {
+                            match self.switch_profile(name) {
+                                Ok(()) => "ok".to_string(),
+                                Err(e) => format!("error: {e:#}"),
+                            }
+                        }
+                        Ok(Request::Command(cmd)) => {
+                            self.hider.run(cmd)?;
+                            "ok".to_string()
+                        }
+                        Ok(Request::Status) => {
+                            serde_json::to_string(&self.status())?
+                        }
+                        Ok(Request::Stats) => {
+                            serde_json::to_string(self.hider.policy().stats())?
+                        }
+                        Err(e) => format!("error: {e}"),
+                    };
+                    client.reply(&reply);
+                }
+            }
+        }
+        Ok(false)
+    }
 
-    Ok(())
-}
+    /// Lets anyone holding a `Remote` know how we're doing, and runs the
+    /// user's hooks if the pointer has come or gone, or notifications if
+    /// we've stopped or started leaving it alone.
+    fn publish(&mut self) {
+        for change in self.hider.take_changes() {
+            self.hooks.run(&change);
+        }
+        self.hooks.suspend(self.hider.suspension());
+        self.hooks.reap();
+        let status = self.status();
+        // The tray comes asking for the new status when it hears about it,
+        // so it has to be published first.
+        self.inbox.publish(status.clone(), self.hider.policy().stats());
+        if let Some(bus) = &mut self.bus {
+            bus.update(&status);
+        }
+        #[cfg(feature = "tray")]
+        if let Some(tray) = &mut self.tray {
+            tray.update(&status);
+        }
+    }
 
-/// Makes an operand suitable for use with SelectExtensionEvent, which appears
-/// to not be documented anywhere except C macros, hooray X11.
-fn make_event_code(device_id: u8, event_type: u8) -> u32 {
-    u32::from(device_id) << 8 | u32::from(event_type)
-}
+    /// Sums up what we're up to.
+    fn status(&self) -> control::Status {
+        let policy = self.hider.policy();
+        control::Status {
+            paused: policy.paused(),
+            suspended: self.hider.suspension().is_some(),
+            hidden: policy.hidden(),
+            backend: Some(self.hider.backend()),
+            devices: self.hider.devices(),
+            profile: self.profile.clone(),
+        }
+    }
+
+    /// Switches profiles if the hider has been asked to, through a hotkey or
+    /// D-Bus. Nobody's waiting to hear whether that worked, so problems only
+    /// go in the log.
+    fn take_profile_switch(&mut self) -> Result<()> {
+        if let Some(name) = self.hider.take_profile_switch() {
+            if let Err(e) = self.switch_profile(name) {
+                if lost_connection(&e) {
+                    return Err(e);
+                }
+                warn!("not switching profiles: {e:#}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches to the profile `name` from the config file, or back to the
+    /// top-level settings.
+    fn switch_profile(&mut self, name: Option<String>) -> Result<()> {
+        let (settings, hooks) = load_settings(&self.args, name.as_deref())?;
+        match &name {
+            Some(name) => info!("switched to profile {name:?}"),
+            None => info!("switched back to the usual settings"),
+        }
+        self.hider.set_settings(settings);
+        self.hooks.set_hooks(hooks);
+        self.profile = name;
+        self.hider.settings_changed()
+    }
 
-fn show_pointer(conn: &Connection, root: Window) -> Result<()> {
-    println!("showing pointer");
+    /// Feeds the watchdog, and sets the timer for next time. We do this from
+    /// the main loop, rather than a separate thread, so that if we get stuck
+    /// -- waiting on a reply from a wedged X server, say -- systemd notices.
+    fn feed_watchdog(&mut self) -> Result<()> {
+        if let (Some(notifier), Some(interval)) =
+            (&self.notifier, self.watchdog)
+        {
+            notifier.ping()?;
+            self.timers
+                .set(Alarm::Watchdog, Some(Instant::now() + interval))?;
+        }
+        Ok(())
+    }
 
-    conn.send_and_check_request(&xfixes::ShowCursor { window: root })?;
-    Ok(())
+    /// Collects waiting signals from the signalfd and acts on them. Returns
+    /// `true` if we've been asked to exit.
+    fn handle_signals(&mut self) -> Result<bool> {
+        // Drain the signalfd; several HUPs in a row only need one reload.
+        let mut reload = false;
+        while let Some(sig) = self.signals.read()? {
+            if sig == libc::SIGHUP {
+                reload = true;
+            } else {
+                // Exiting by returning, rather than calling exit, lets
+                // everything clean up after itself on the way out.
+                info!("exiting on signal {sig}");
+                return Ok(true);
+            }
+        }
+        if reload {
+            self.reload();
+        }
+        Ok(false)
+    }
+
+    /// Loads the config file again. A broken config file shouldn't take us
+    /// down, since we've got perfectly good settings already, so if there's
+    /// anything wrong with it, we carry on with those.
+    fn reload(&mut self) {
+        match load_settings(&self.args, self.profile.as_deref()) {
+            Ok((settings, hooks)) => {
+                info!("reloaded configuration");
+                self.hider.set_settings(settings);
+                self.hooks.set_hooks(hooks);
+            }
+            Err(e) => {
+                warn!("not reloading configuration: {e:#}");
+            }
+        }
+    }
+}
+
+impl Daemon<XServer> {
+    /// Waits for `delay` while we're between X servers, still answering
+    /// signals and keeping the watchdog fed. Returns `true` if we've been asked
+    /// to exit in the meantime.
+    fn sleep(&mut self, delay: Duration) -> Result<bool> {
+        self.timers.set(Alarm::Reconnect, Some(Instant::now() + delay))?;
+        loop {
+            let mut done = false;
+            for alarm in self.timers.due()? {
+                match alarm {
+                    Alarm::Reconnect => done = true,
+                    Alarm::Watchdog => self.feed_watchdog()?,
+                    // There's nothing to banish without a server, but the
+                    // user's commands may still need checking on.
+                    Alarm::Hider => {
+                        let commands = self.hider.commands.poll();
+                        self.timers.set(Alarm::Hider, commands)?
+                    }
+                }
+            }
+            if done {
+                return Ok(false);
+            }
+            let ready = wait(&self.sources(false))?;
+            if ready.contains(&Source::Signals) && self.handle_signals()? {
+                return Ok(true);
+            }
+        }
+    }
 }
 
-fn hide_pointer(conn: &Connection, root: Window) -> Result<()> {
-    println!("hiding pointer");
+/// Things the main loop waits on.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Source {
+    /// Wherever the hider hears about input: the connection to the X server,
+    /// or the input devices.
+    Input,
+    /// The signalfd.
+    Signals,
+    /// Our timers.
+    Timers,
+    /// The control inbox, for commands from D-Bus.
+    Inbox,
+    /// The control socket.
+    Socket,
+    /// The watch on the config file.
+    Config,
+}
 
-    conn.send_and_check_request(&xfixes::HideCursor { window: root })?;
-    Ok(())
+/// Waits for at least one of `sources` to have something for us, and returns
+/// the ones that do.
+///
+/// `Connection::wait_for_event` can only block forever, and doesn't know about
+/// anything but X, so the main loop does its waiting here instead, polling the
+/// connection's file descriptor alongside our others.
+///
+/// This is all the event loop we need. Everything we wait on is a file
+/// descriptor, signals and timers included (see `signal` and `timer`), and
+/// everything gets handled on this one thread, so the X connection never has
+/// more than one user. The exception is D-Bus, where zbus insists on a thread
+/// of its own, but that only ever hands us commands through the inbox. An
+/// event loop crate like calloop would do the same job behind a trait per
+/// source; it isn't among our dependencies, and a `match` in `dispatch` has
+/// so far been easier to follow.
+fn wait<S: Copy>(sources: &[(S, RawFd)]) -> Result<Vec<S>> {
+    let mut pfds: Vec<libc::pollfd> = sources
+        .iter()
+        .map(|&(_, fd)| libc::pollfd { fd, events: libc::POLLIN, revents: 0 })
+        .collect();
+    // Safety: we're passing a valid pointer to an array of pollfds along with
+    // its length.
+    let r = unsafe {
+        libc::poll(pfds.as_mut_ptr(), pfds.len() as libc::nfds_t, -1)
+    };
+    if r < 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err.into());
+        }
+    }
+    Ok(sources
+        .iter()
+        .zip(&pfds)
+        .filter(|(_, pfd)| pfd.revents != 0)
+        .map(|(&(source, _), _)| source)
+        .collect())
 }
@@ -0,0 +1,82 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Telling the user why we've stopped hiding the pointer, by way of desktop
+//! notifications.
+//!
+//! When we leave the pointer alone of our own accord, for a fullscreen window
+//! or a screenshot tool, say, it looks a lot like we've stopped working. So if
+//! the user asks, we put up a notification saying why, and another when we get
+//! going again. Each one replaces the last, so that they don't pile up.
+//!
+//! Talking to the notification server takes a round trip over the session bus,
+//! and it can be slow to answer, so that happens on a thread of our own.
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+use anyhow::Result;
+use log::{debug, warn};
+use zbus::zvariant::Value;
+
+/// A notification's summary and body.
+type Message = (&'static str, &'static str);
+
+/// Sends notifications to the notification server.
+pub struct Notifier {
+    tx: mpsc::Sender<Message>,
+}
+
+impl Notifier {
+    /// Connects to the session bus and starts a thread to send notifications
+    /// on, which carries on for as long as we're running.
+    pub fn start() -> Result<Self> {
+        let conn = zbus::blocking::Connection::session()?;
+        let proxy = zbus::blocking::Proxy::new(
+            &conn,
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            "org.freedesktop.Notifications",
+        )?;
+        let (tx, rx) = mpsc::channel::<Message>();
+        std::thread::Builder::new()
+            .name("notify".into())
+            .spawn(move || {
+                // Zero asks for a new notification, rather than replacing one.
+                let mut id = 0u32;
+                for (summary, body) in rx {
+                    // These are only for the user's information, so they
+                    // shouldn't be pushy about it.
+                    let hints = HashMap::from([("urgency", Value::U8(0))]);
+                    let reply = proxy.call::<_, _, u32>(
+                        "Notify",
+                        &(
+                            "rxbanish",
+                            id,
+                            "",
+                            summary,
+                            body,
+                            Vec::<&str>::new(),
+                            hints,
+                            -1i32,
+                        ),
+                    );
+                    match reply {
+                        Ok(new_id) => {
+                            debug!(id = new_id; "sent notification");
+                            id = new_id;
+                        }
+                        Err(e) => warn!("can't send notification: {e}"),
+                    }
+                }
+            })?;
+        Ok(Self { tx })
+    }
+
+    /// Puts up a notification, replacing the last one if it's still there.
+    pub fn send(&self, summary: &'static str, body: &'static str) {
+        // The thread only stops if it panics, and it'll have said so.
+        let _ = self.tx.send((summary, body));
+    }
+}
@@ -0,0 +1,39 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hiding the pointer under river, by way of riverctl.
+//!
+//! Most wlroots compositors have no way for another program to hide the
+//! pointer; wlroots itself offers none, and neither does any Wayland protocol.
+//! river is an exception, since everything about it is set at run time with
+//! riverctl, including `hide-cursor`. As with sway, a timeout of a millisecond
+//! hides the pointer at once, and no timeout shows it again. riverctl has no
+//! way to ask what the timeout was before, so a timeout of your own is lost.
+//!
+//! riverctl is a program rather than a socket, so running it is a job for
+//! `External`.
+
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+
+use crate::external::External;
+use crate::signal;
+
+const HIDE: &str = "riverctl hide-cursor timeout 1";
+const SHOW: &str = "riverctl hide-cursor timeout 0";
+
+/// Checks that riverctl is there and works, and gets ready to run it.
+pub fn connect() -> Result<External> {
+    let mut cmd = Command::new("riverctl");
+    cmd.args(["hide-cursor", "timeout", "0"]);
+    signal::unblock_in_child(&mut cmd);
+    let status = cmd
+        .status()
+        .context("can't run riverctl (is it installed, and on PATH?)")?;
+    if !status.success() {
+        bail!("riverctl failed: {status}");
+    }
+    Ok(External::new(HIDE, SHOW))
+}
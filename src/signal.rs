@@ -0,0 +1,110 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Signal handling, the boring way.
+//!
+//! Rather than installing signal handlers and trying to do something useful
+//! from inside them, we block the signals we care about and receive them
+//! through a `signalfd` instead. That lets the main loop wait on signals the
+//! same way it waits on the X connection.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use anyhow::Result;
+
+/// A file descriptor that becomes readable when one of a chosen set of signals
+/// arrives.
+pub struct SignalFd {
+    fd: OwnedFd,
+}
+
+impl SignalFd {
+    /// Blocks `signals` for the process and returns a `SignalFd` that will
+    /// receive them instead.
+    ///
+    /// This must be called before any threads are spawned, since it only
+    /// changes the signal mask of the calling thread.
+    pub fn new(signals: &[libc::c_int]) -> Result<Self> {
+        // Safety: sigset_t is a plain bag of bits that sigemptyset fully
+        // initializes, and we only pass valid pointers to these functions.
+        unsafe {
+            let mut set = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&mut set);
+            for &sig in signals {
+                libc::sigaddset(&mut set, sig);
+            }
+            let r = libc::pthread_sigmask(
+                libc::SIG_BLOCK,
+                &set,
+                std::ptr::null_mut(),
+            );
+            if r != 0 {
+                return Err(std::io::Error::from_raw_os_error(r).into());
+            }
+
+            let fd = libc::signalfd(
+                -1,
+                &set,
+                libc::SFD_NONBLOCK | libc::SFD_CLOEXEC,
+            );
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(Self { fd: OwnedFd::from_raw_fd(fd) })
+        }
+    }
+
+    /// Collects the next pending signal, if any, without blocking.
+    pub fn read(&self) -> Result<Option<libc::c_int>> {
+        // Safety: signalfd_siginfo is plain old data, and we're asking read
+        // for no more bytes than it holds.
+        unsafe {
+            let mut info = std::mem::zeroed::<libc::signalfd_siginfo>();
+            let size = std::mem::size_of_val(&info);
+            let n = libc::read(
+                self.fd.as_raw_fd(),
+                &mut info as *mut _ as *mut libc::c_void,
+                size,
+            );
+            if n < 0 {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == std::io::ErrorKind::WouldBlock {
+                    return Ok(None);
+                }
+                return Err(err.into());
+            }
+            Ok(Some(info.ssi_signo as libc::c_int))
+        }
+    }
+}
+
+impl AsRawFd for SignalFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// Arranges for `cmd` to start with no signals blocked.
+///
+/// We block the signals we care about so that they come through the signalfd,
+/// and children inherit the signal mask, which would leave anything we start
+/// deaf to SIGTERM and friends.
+pub fn unblock_in_child(cmd: &mut Command) {
+    // Safety: this runs between fork and exec, where only async-signal-safe
+    // functions are allowed, which these are.
+    unsafe {
+        cmd.pre_exec(|| {
+            let mut set = std::mem::zeroed::<libc::sigset_t>();
+            libc::sigemptyset(&mut set);
+            if libc::sigprocmask(libc::SIG_SETMASK, &set, std::ptr::null_mut())
+                < 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,265 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A Unix socket for controlling rxbanish from the command line.
+//!
+//! The protocol is about as simple as it gets: the client connects, sends a
+//! single line naming a command, and gets a single line back. Commands are
+//! `pause`, `resume`, `toggle`, `hide`, `show`, `resync`, `profile` (followed
+//! by the name of a profile, or nothing to go back to the usual settings),
+//! `status`, and `stats`. `status` answers with a JSON object describing what
+//! we're up to, and `stats` with one of counters of what we've done;
+//! everything else answers `ok`. Problems are reported as a line starting with
+//! `error:`.
+
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rxbanish_core::Command;
+
+/// How long we'll wait on a client to send its request or read our reply. The
+/// main loop stops while we talk to a client, so we can't afford to be
+/// patient.
+const CLIENT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Things a client can ask of us.
+#[derive(Clone, Debug)]
+pub enum Request {
+    Command(Command),
+    Status,
+    Stats,
+}
+
+impl std::str::FromStr for Request {
+    type Err = anyhow::Error;
+
+    /// Parses a request line. Whitespace around it, including the newline
+    /// that ends it, doesn't matter.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        Ok(match s {
+            "pause" => Request::Command(Command::Pause),
+            "resume" => Request::Command(Command::Resume),
+            "toggle" => Request::Command(Command::Toggle),
+            "hide" => Request::Command(Command::Hide),
+            "show" => Request::Command(Command::Show),
+            "resync" => Request::Command(Command::Resync),
+            "profile" => Request::Command(Command::Profile(None)),
+            "status" => Request::Status,
+            "stats" => Request::Stats,
+            _ => match s.strip_prefix("profile ") {
+                Some(name) => Request::Command(Command::Profile(Some(
+                    name.trim().to_string(),
+                ))),
+                None => bail!("unknown command: {s}"),
+            },
+        })
+    }
+}
+
+/// Works out where the socket lives. It goes in the user's runtime directory,
+/// so that other users can't poke at it; if there isn't one, we don't offer a
+/// socket at all.
+///
+/// If the user has chosen a particular display, it gets a socket of its own,
+/// so that they can run an rxbanish on each display and still tell them
+/// apart.
+pub fn default_path(display: Option<&str>) -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").filter(|d| !d.is_empty())?;
+    let name = match display {
+        // Display names are things like ":1" or "otherhost:0.1". Colons are
+        // legal in filenames, but slashes (for local socket paths) are not.
+        Some(display) => {
+            format!("rxbanish-{}.sock", display.replace('/', "_"))
+        }
+        None => "rxbanish.sock".to_string(),
+    };
+    Some(PathBuf::from(dir).join(name))
+}
+
+/// The listening end of the control socket. The socket file is removed when
+/// this is dropped.
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlSocket {
+    /// Starts listening at `path`.
+    ///
+    /// If there's already a socket there, we check whether anyone's listening
+    /// on it. If so, that's an error, since it's probably another rxbanish. If
+    /// not, it's left over from an rxbanish that didn't exit cleanly, and we
+    /// replace it.
+    pub fn bind(path: &Path) -> Result<Self> {
+        let listener = match UnixListener::bind(path) {
+            Ok(l) => l,
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                if UnixStream::connect(path).is_ok() {
+                    bail!("another rxbanish is using {}", path.display());
+                }
+                std::fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, path: path.to_owned() })
+    }
+
+    /// Accepts the next waiting client, if there is one.
+    pub fn accept(&self) -> Result<Option<Client>> {
+        match self.listener.accept() {
+            Ok((stream, _)) => {
+                // The listener being nonblocking doesn't make the accepted
+                // stream nonblocking, but let's be sure.
+                stream.set_nonblocking(false)?;
+                stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+                stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+                Ok(Some(Client { stream: BufReader::new(stream) }))
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl AsRawFd for ControlSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// A connected client.
+pub struct Client {
+    stream: BufReader<UnixStream>,
+}
+
+impl Client {
+    /// Reads the client's request.
+    pub fn request(&mut self) -> Result<Request> {
+        let mut line = String::new();
+        self.stream.read_line(&mut line)?;
+        line.parse()
+    }
+
+    /// Sends our reply, and that's the end of the conversation. If the client
+    /// has lost interest in the meantime, that's their problem, not ours.
+    pub fn reply(mut self, text: &str) {
+        self.stream.get_mut().write_all(format!("{text}\n").as_bytes()).ok();
+    }
+}
+
+/// Sends `command` to the rxbanish listening at `path`, and returns its reply.
+pub fn send(path: &Path, command: &str) -> Result<String> {
+    try_send(path, command)?.with_context(|| {
+        format!("can't reach rxbanish at {} (is it running?)", path.display())
+    })
+}
+
+/// Like `send`, but if nobody's listening at `path`, returns `None` rather
+/// than failing, for callers that can manage without.
+pub fn try_send(path: &Path, command: &str) -> Result<Option<String>> {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e)
+            if matches!(
+                e.kind(),
+                ErrorKind::NotFound | ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("can't reach rxbanish at {}", path.display())
+            });
+        }
+    };
+    stream.write_all(format!("{command}\n").as_bytes())?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    let reply = reply.trim_end();
+    if let Some(err) = reply.strip_prefix("error:") {
+        return Err(anyhow!("{}", err.trim()));
+    }
+    Ok(Some(reply.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Request;
+    use rxbanish_core::Command;
+
+    fn parse(s: &str) -> Request {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse("pause"), Request::Command(Command::Pause)));
+        assert!(matches!(parse("resume"), Request::Command(Command::Resume)));
+        assert!(matches!(parse("toggle"), Request::Command(Command::Toggle)));
+        assert!(matches!(parse("hide"), Request::Command(Command::Hide)));
+        assert!(matches!(parse("show"), Request::Command(Command::Show)));
+        assert!(matches!(parse("resync"), Request::Command(Command::Resync)));
+    }
+
+    #[test]
+    fn queries() {
+        assert!(matches!(parse("status"), Request::Status));
+        assert!(matches!(parse("stats"), Request::Stats));
+    }
+
+    #[test]
+    fn profile() {
+        assert!(matches!(
+            parse("profile"),
+            Request::Command(Command::Profile(None))
+        ));
+        assert!(matches!(
+            parse("profile work"),
+            Request::Command(Command::Profile(Some(name))) if name == "work"
+        ));
+        assert!(matches!(
+            parse("profile   two words  "),
+            Request::Command(Command::Profile(Some(name)))
+                if name == "two words"
+        ));
+    }
+
+    #[test]
+    fn whitespace() {
+        assert!(matches!(parse("pause\n"), Request::Command(Command::Pause)));
+        assert!(matches!(parse("  stats \r\n"), Request::Stats));
+        assert!(matches!(
+            parse("profile work\n"),
+            Request::Command(Command::Profile(Some(name))) if name == "work"
+        ));
+        assert!(matches!(
+            parse("profile \n"),
+            Request::Command(Command::Profile(None))
+        ));
+    }
+
+    #[test]
+    fn unknown() {
+        assert!("".parse::<Request>().is_err());
+        assert!("Pause".parse::<Request>().is_err());
+        assert!("pause now".parse::<Request>().is_err());
+        assert!("profiles".parse::<Request>().is_err());
+        assert!("explode".parse::<Request>().is_err());
+    }
+}
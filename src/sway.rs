@@ -0,0 +1,240 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hiding the pointer under sway, by way of its IPC socket.
+//!
+//! sway can hide the pointer by itself after a timeout, or while typing, but
+//! not in the way our settings describe. It has no command to hide the pointer
+//! right now, so we make do with `hide_cursor`: a timeout of a millisecond
+//! hides it at once, and putting the timeout back the way it was shows it
+//! again. sway brings the pointer back by itself if it moves in between, which
+//! is what we'd have done anyway.
+//!
+//! "The way it was" comes from the user's sway config, which sway will hand
+//! over on request. Only the timeout is ours to change; `hide_cursor
+//! when-typing` is left alone.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+
+use crate::wayland::Cursor;
+
+/// Every i3-ipc message starts with this.
+const MAGIC: &[u8; 6] = b"i3-ipc";
+/// The message type for running commands.
+const RUN_COMMAND: u32 = 0;
+/// The message type for fetching the config file.
+const GET_CONFIG: u32 = 9;
+
+/// A connection to sway.
+pub struct Sway {
+    socket: UnixStream,
+    /// The commands that put the user's `hide_cursor` timeouts back.
+    restore: String,
+}
+
+/// sway's answer to each command it ran.
+#[derive(Deserialize)]
+struct Outcome {
+    success: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// sway's config, and, in newer versions, the files it includes.
+#[derive(Deserialize)]
+struct Config {
+    config: String,
+    #[serde(default)]
+    included_configs: Vec<Included>,
+}
+
+#[derive(Deserialize)]
+struct Included {
+    raw_contents: String,
+}
+
+impl Sway {
+    /// Connects to the sway that `SWAYSOCK` points to.
+    pub fn connect() -> Result<Self> {
+        let path = std::env::var_os("SWAYSOCK").context("SWAYSOCK isn't set")?;
+        let socket = UnixStream::connect(&path).with_context(|| {
+            format!("can't connect to sway at {}", path.to_string_lossy())
+        })?;
+        let mut sway = Self {
+            socket,
+            restore: String::new(),
+        };
+        let config: Config =
+            serde_json::from_slice(&sway.request(GET_CONFIG, "")?)?;
+        let timeouts = std::iter::once(config.config.as_str())
+            .chain(config.included_configs.iter().map(|i| &*i.raw_contents))
+            .flat_map(hide_cursor_timeouts);
+        // Start from sway's default, which is not to hide it at all.
+        sway.restore = std::iter::once("seat * hide_cursor 0".to_string())
+            .chain(timeouts)
+            .collect::<Vec<_>>()
+            .join("; ");
+        debug!(restore = sway.restore; "showing the pointer under sway");
+        sway.show()?;
+        Ok(sway)
+    }
+
+    /// Runs `command`, as though from swaymsg.
+    fn run(&mut self, command: &str) -> Result<()> {
+        let outcomes: Vec<Outcome> =
+            serde_json::from_slice(&self.request(RUN_COMMAND, command)?)?;
+        for outcome in outcomes {
+            if !outcome.success {
+                bail!(
+                    "sway wouldn't run `{command}`: {}",
+                    outcome.error.as_deref().unwrap_or("no reason given")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a message of type `kind`, and returns the reply.
+    fn request(&mut self, kind: u32, payload: &str) -> Result<Vec<u8>> {
+        let mut message = MAGIC.to_vec();
+        message.extend((payload.len() as u32).to_ne_bytes());
+        message.extend(kind.to_ne_bytes());
+        message.extend(payload.as_bytes());
+        self.socket.write_all(&message)?;
+
+        let mut header = [0; 14];
+        self.socket.read_exact(&mut header)?;
+        if &header[..6] != MAGIC {
+            bail!("sway sent something that isn't i3-ipc");
+        }
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+        let mut reply = vec![0; len as usize];
+        self.socket.read_exact(&mut reply)?;
+        // We never subscribe to events, so anything else is sway confused.
+        let reply_kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        if reply_kind != kind {
+            bail!("sway answered message type {kind} with type {reply_kind}");
+        }
+        Ok(reply)
+    }
+}
+
+impl Cursor for Sway {
+    fn hide(&mut self) -> Result<()> {
+        self.run("seat * hide_cursor 1")
+    }
+
+    fn show(&mut self) -> Result<()> {
+        let restore = self.restore.clone();
+        self.run(&restore)
+    }
+}
+
+/// Finds the `hide_cursor` timeouts set in `config`, whether as one-line
+/// `seat` commands or inside `seat` blocks, and returns the commands that set
+/// them.
+fn hide_cursor_timeouts(config: &str) -> Vec<String> {
+    let mut commands = vec![];
+    // The seat whose block we're in, if any.
+    let mut block: Option<&str> = None;
+    for line in config.lines() {
+        let words: Vec<&str> = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect();
+        let (seat, rest) = match (block, words.as_slice()) {
+            (_, ["}"]) => {
+                block = None;
+                continue;
+            }
+            (None, ["seat", seat, "{"]) => {
+                block = Some(seat);
+                continue;
+            }
+            (None, ["seat", seat, rest @ ..]) => (*seat, rest),
+            (Some(seat), rest) => (seat, rest),
+            _ => continue,
+        };
+        if let ["hide_cursor", timeout] = rest {
+            if timeout.parse::<u32>().is_ok() {
+                commands.push(format!("seat {seat} hide_cursor {timeout}"));
+            }
+        }
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hide_cursor_timeouts;
+
+    #[test]
+    fn all_seats() {
+        assert_eq!(
+            hide_cursor_timeouts("seat * hide_cursor 3000\n"),
+            ["seat * hide_cursor 3000"]
+        );
+    }
+
+    #[test]
+    fn per_seat() {
+        let config = "
+            seat seat0 hide_cursor 1000
+            seat seat1 {
+                xcursor_theme Adwaita 24
+                hide_cursor 2000
+            }
+            output * bg #000000 solid_color
+        ";
+        assert_eq!(
+            hide_cursor_timeouts(config),
+            ["seat seat0 hide_cursor 1000", "seat seat1 hide_cursor 2000"]
+        );
+    }
+
+    #[test]
+    fn when_typing() {
+        // That one's left alone entirely, so it's nothing to restore.
+        let config = "
+            seat * hide_cursor when-typing enable
+            seat seat0 {
+                hide_cursor when-typing disable
+            }
+        ";
+        assert!(hide_cursor_timeouts(config).is_empty());
+    }
+
+    #[test]
+    fn comments() {
+        let config = "
+            # seat * hide_cursor 1000
+            seat * hide_cursor 500 # was 1000
+            seat seat0 { # the laptop
+                # hide_cursor 100
+            }
+        ";
+        assert_eq!(hide_cursor_timeouts(config), ["seat * hide_cursor 500"]);
+    }
+
+    #[test]
+    fn nothing() {
+        assert!(hide_cursor_timeouts("").is_empty());
+        let config = "
+            set $mod Mod4
+            bindsym $mod+Return exec foot
+            seat * xcursor_theme Adwaita 24
+            seat * hide_cursor
+            seat * hide_cursor soon
+            hide_cursor 1000
+        ";
+        assert!(hide_cursor_timeouts(config).is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Talking to systemd when we're run as a service.
+//!
+//! This is the `sd_notify` protocol, which is simple enough that it's not worth
+//! linking libsystemd for: systemd tells us where to send datagrams through
+//! `$NOTIFY_SOCKET`, and we send it little `KEY=value` messages. We use it to
+//! say when we're ready (for `Type=notify` units) and to keep the watchdog fed
+//! (for units with `WatchdogSec=`).
+
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use anyhow::Result;
+
+/// A connection to systemd's notification socket.
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Checks whether systemd wants to hear from us, returning `None` if we're
+    /// not running under systemd (or it's not interested).
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        // Paths starting with @ are in the abstract namespace.
+        let path = path.as_encoded_bytes();
+        let addr = match path.strip_prefix(b"@") {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(std::str::from_utf8(path)?)?,
+        };
+
+        // The watchdog is only meant for us if WATCHDOG_PID is either missing
+        // or names us; otherwise we've inherited it from someone else.
+        let for_us = std::env::var("WATCHDOG_PID")
+            .map_or(true, |pid| pid == std::process::id().to_string());
+        let watchdog = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| for_us)
+            .and_then(|usec| usec.parse().ok())
+            .map(Duration::from_micros);
+
+        Ok(Some(Self {
+            socket: UnixDatagram::unbound()?,
+            addr,
+            watchdog,
+        }))
+    }
+
+    /// How often we should feed the watchdog, if there is one. systemd
+    /// recommends pinging at twice the rate it actually demands, so that a bit
+    /// of scheduling jitter doesn't get us killed.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+
+    /// Tells systemd that we're up and running.
+    pub fn ready(&self) -> Result<()> {
+        self.send("READY=1")
+    }
+
+    /// Tells systemd that we're still alive.
+    pub fn ping(&self) -> Result<()> {
+        self.send("WATCHDOG=1")
+    }
+
+    fn send(&self, msg: &str) -> Result<()> {
+        self.socket.send_to_addr(msg.as_bytes(), &self.addr)?;
+        Ok(())
+    }
+}
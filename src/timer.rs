@@ -0,0 +1,147 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Timers, the same way as signals.
+//!
+//! Everything that needs to happen at a particular time gets a deadline in a
+//! `Schedule`, which keeps a `timerfd` set for the earliest of them. The main
+//! loop waits on that alongside its other file descriptors, and asks the
+//! schedule what's due whenever it wakes up.
+
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+/// A set of deadlines, each known by a key of type `K`, and a file descriptor
+/// that becomes readable when the earliest of them passes.
+pub struct Schedule<K> {
+    fd: OwnedFd,
+    deadlines: Vec<(K, Instant)>,
+}
+
+impl<K: Copy + PartialEq> Schedule<K> {
+    /// Makes an empty schedule.
+    pub fn new() -> Result<Self> {
+        // Safety: timerfd_create has no memory safety requirements, and we
+        // take ownership of the fd it returns.
+        unsafe {
+            let fd = libc::timerfd_create(
+                libc::CLOCK_MONOTONIC,
+                libc::TFD_NONBLOCK | libc::TFD_CLOEXEC,
+            );
+            if fd < 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            Ok(Self {
+                fd: OwnedFd::from_raw_fd(fd),
+                deadlines: vec![],
+            })
+        }
+    }
+
+    /// Sets `key`'s deadline, replacing any it had before. `None` cancels it.
+    /// The timerfd only needs setting again if this changes which deadline
+    /// comes first, which most of the time it doesn't.
+    pub fn set(&mut self, key: K, deadline: Option<Instant>) -> Result<()> {
+        let before = self.earliest();
+        self.deadlines.retain(|&(k, _)| k != key);
+        if let Some(deadline) = deadline {
+            self.deadlines.push((key, deadline));
+        }
+        if self.earliest() != before {
+            self.arm()?;
+        }
+        Ok(())
+    }
+
+    /// Removes and returns the keys whose deadlines have passed, earliest
+    /// first. This checks the clock, rather than trusting the file descriptor,
+    /// so it's fine to call whenever.
+    pub fn due(&mut self) -> Result<Vec<K>> {
+        let fired = self.drain()?;
+        let now = Instant::now();
+        let mut due: Vec<(K, Instant)> = self
+            .deadlines
+            .iter()
+            .copied()
+            .filter(|&(_, deadline)| deadline <= now)
+            .collect();
+        due.sort_by_key(|&(_, deadline)| deadline);
+        self.deadlines.retain(|&(_, deadline)| deadline > now);
+        // If the timerfd went off, it's disarmed itself, and if anything's
+        // come off the schedule, it's set for the wrong time; otherwise it's
+        // still right.
+        if fired || !due.is_empty() {
+            self.arm()?;
+        }
+        Ok(due.into_iter().map(|(k, _)| k).collect())
+    }
+
+    /// Finds the earliest deadline, if there is one.
+    fn earliest(&self) -> Option<Instant> {
+        self.deadlines.iter().map(|&(_, d)| d).min()
+    }
+
+    /// Sets the timerfd for the earliest deadline, or disarms it if there
+    /// isn't one.
+    fn arm(&self) -> Result<()> {
+        let next = self.earliest();
+        // timerfd counts a zero time as "disarm," so a deadline that's already
+        // passed needs to be a hair in the future instead.
+        let left = match next {
+            None => Duration::ZERO,
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_nanos(1)),
+        };
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec { tv_sec: 0, tv_nsec: 0 },
+            it_value: libc::timespec {
+                tv_sec: left.as_secs() as libc::time_t,
+                tv_nsec: left.subsec_nanos() as libc::c_long,
+            },
+        };
+        // Safety: we pass a valid itimerspec, and no old value pointer.
+        let r = unsafe {
+            libc::timerfd_settime(
+                self.fd.as_raw_fd(),
+                0,
+                &spec,
+                std::ptr::null_mut(),
+            )
+        };
+        if r < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// Clears the timerfd's readiness, if it's gone off. Returns whether it
+    /// had.
+    fn drain(&self) -> Result<bool> {
+        let mut expirations = 0u64;
+        // Safety: we're asking read for no more bytes than a u64 holds.
+        let n = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                &mut expirations as *mut u64 as *mut libc::c_void,
+                std::mem::size_of::<u64>(),
+            )
+        };
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() != std::io::ErrorKind::WouldBlock {
+                return Err(err.into());
+            }
+        }
+        Ok(n > 0)
+    }
+}
+
+impl<K> AsRawFd for Schedule<K> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -0,0 +1,414 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An icon in the system tray, by way of StatusNotifierItem.
+//!
+//! Desktops with a tray (or a panel applet that does the same job) find the
+//! icons to show through `org.kde.StatusNotifierWatcher`, which we register
+//! with. Our icon shows whether we're hiding the pointer as usual, leaving it
+//! alone for now, or have it hidden at the moment. Clicking it pauses or
+//! resumes, and it has a menu, served over `com.canonical.dbusmenu`, to do the
+//! same or to open the config file.
+//!
+//! As with the control interface, zbus answers calls on its own thread, and
+//! forwards anything the user asks for to the main loop through a `Remote`.
+//! The main loop tells us when its status changes, so that we can pass that on
+//! to the tray.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Command as Process;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::{debug, warn};
+use rxbanish_core::Command;
+use zbus::names::BusName;
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+
+use crate::control::{Remote, Status};
+use crate::signal;
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+const MENU_INTERFACE: &str = "com.canonical.dbusmenu";
+
+/// Menu item IDs. The root of the menu is always zero.
+const PAUSE: i32 = 1;
+const CONFIG: i32 = 2;
+
+/// What the icon is showing, so that we only bother the tray when that
+/// changes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct Shown {
+    paused: bool,
+    suspended: bool,
+    hidden: bool,
+}
+
+impl From<&Status> for Shown {
+    fn from(status: &Status) -> Self {
+        Self {
+            paused: status.paused,
+            suspended: status.suspended,
+            hidden: status.hidden,
+        }
+    }
+}
+
+impl Shown {
+    /// Names a themed icon for this state.
+    fn icon(self) -> &'static str {
+        if self.paused || self.suspended {
+            "media-playback-pause"
+        } else if self.hidden {
+            "input-keyboard"
+        } else {
+            "input-mouse"
+        }
+    }
+
+    /// Says what's going on, for the tooltip.
+    fn describe(self) -> &'static str {
+        if self.paused {
+            "Paused"
+        } else if self.suspended {
+            "Leaving the pointer alone for now"
+        } else if self.hidden {
+            "The pointer is hidden"
+        } else {
+            "Hiding the pointer while you type"
+        }
+    }
+}
+
+/// Our icon in the tray. It goes away when this is dropped.
+pub struct Tray {
+    conn: zbus::blocking::Connection,
+    shown: Shown,
+    /// The menu's layout revision, which goes up whenever it changes.
+    revision: Arc<AtomicU32>,
+}
+
+impl Tray {
+    /// Puts an icon in the tray that sends the user's requests through
+    /// `remote`. `config` is the config file to offer to open, if any.
+    pub fn start(remote: Remote, config: Option<PathBuf>) -> Result<Self> {
+        let revision = Arc::new(AtomicU32::new(1));
+        // The spec asks for a well-known name like this, unique to us.
+        let name = format!(
+            "org.kde.StatusNotifierItem-{}-1",
+            std::process::id()
+        );
+        let conn = zbus::blocking::connection::Builder::session()?
+            .name(name.as_str())?
+            .serve_at(
+                ITEM_PATH,
+                Item {
+                    remote: remote.clone(),
+                },
+            )?
+            .serve_at(
+                MENU_PATH,
+                Menu {
+                    remote,
+                    config,
+                    revision: revision.clone(),
+                },
+            )?
+            .build()?;
+        conn.call_method(
+            Some("org.kde.StatusNotifierWatcher"),
+            "/StatusNotifierWatcher",
+            Some("org.kde.StatusNotifierWatcher"),
+            "RegisterStatusNotifierItem",
+            &name,
+        )?;
+        Ok(Self {
+            conn,
+            shown: Shown::default(),
+            revision,
+        })
+    }
+
+    /// Tells the tray about `status`, if it's changed in a way that shows.
+    pub fn update(&mut self, status: &Status) {
+        let shown = Shown::from(status);
+        if shown == self.shown {
+            return;
+        }
+        self.shown = shown;
+        let revision = self.revision.fetch_add(1, Ordering::Relaxed) + 1;
+        // The tray asks for the details when it hears about the change, and
+        // it's no great loss if it doesn't.
+        let signals = [
+            self.emit(ITEM_PATH, ITEM_INTERFACE, "NewIcon", &()),
+            self.emit(ITEM_PATH, ITEM_INTERFACE, "NewToolTip", &()),
+            self.emit(
+                MENU_PATH,
+                MENU_INTERFACE,
+                "LayoutUpdated",
+                &(revision, 0i32),
+            ),
+        ];
+        if let Some(Err(e)) = signals.into_iter().find(Result::is_err) {
+            debug!("can't update tray icon: {e}");
+        }
+    }
+
+    fn emit<B>(
+        &self,
+        path: &str,
+        interface: &str,
+        signal: &str,
+        body: &B,
+    ) -> zbus::Result<()>
+    where
+        B: serde::Serialize + zbus::zvariant::DynamicType,
+    {
+        self.conn
+            .emit_signal(None::<BusName>, path, interface, signal, body)
+    }
+}
+
+struct Item {
+    remote: Remote,
+}
+
+/// An icon name, an icon as pixels (width, height, and ARGB data, which we
+/// never have), a title, and some text.
+type ToolTip = (&'static str, Vec<(i32, i32, Vec<u8>)>, String, String);
+
+#[zbus::interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    /// The icon was clicked: pause if active, resume if paused.
+    fn activate(&self, _x: i32, _y: i32) {
+        self.remote.send(Command::Toggle);
+    }
+
+    fn secondary_activate(&self, _x: i32, _y: i32) {}
+
+    fn scroll(&self, _delta: i32, _orientation: &str) {}
+
+    #[zbus(property)]
+    fn category(&self) -> &'static str {
+        "ApplicationStatus"
+    }
+
+    #[zbus(property)]
+    fn id(&self) -> &'static str {
+        "rxbanish"
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> &'static str {
+        "rxbanish"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &'static str {
+        "Active"
+    }
+
+    #[zbus(property)]
+    fn icon_name(&self) -> &'static str {
+        Shown::from(&self.remote.status()).icon()
+    }
+
+    #[zbus(property)]
+    fn tool_tip(&self) -> ToolTip {
+        let shown = Shown::from(&self.remote.status());
+        (shown.icon(), vec![], "rxbanish".into(), shown.describe().into())
+    }
+
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> ObjectPath<'static> {
+        ObjectPath::from_static_str_unchecked(MENU_PATH)
+    }
+}
+
+struct Menu {
+    remote: Remote,
+    config: Option<PathBuf>,
+    revision: Arc<AtomicU32>,
+}
+
+/// A menu item's properties, by name.
+type Properties = HashMap<&'static str, Value<'static>>;
+
+/// A menu item's ID, properties, and children, each of which is another of
+/// these, wrapped in a variant.
+type Layout = (i32, Properties, Vec<Value<'static>>);
+
+impl Menu {
+    /// Looks up the properties of menu item `id`, if there's such an item.
+    fn properties(&self, id: i32) -> Option<Properties> {
+        match id {
+            0 => Some(HashMap::from([("children-display", "submenu".into())])),
+            PAUSE => {
+                let paused = self.remote.status().paused;
+                Some(HashMap::from([
+                    ("label", "Paused".into()),
+                    ("toggle-type", "checkmark".into()),
+                    ("toggle-state", i32::from(paused).into()),
+                ]))
+            }
+            CONFIG => Some(HashMap::from([
+                ("label", "Open Config File".into()),
+                ("enabled", self.config.is_some().into()),
+            ])),
+            _ => None,
+        }
+    }
+
+    /// Does what menu item `id` is for.
+    fn clicked(&self, id: i32) {
+        match id {
+            PAUSE => self.remote.send(Command::Toggle),
+            CONFIG => {
+                if let Some(path) = &self.config {
+                    open(path.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[zbus::interface(name = "com.canonical.dbusmenu")]
+impl Menu {
+    /// Describes the menu from `parent_id` down. Ours is only one level deep.
+    fn get_layout(
+        &self,
+        parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> zbus::fdo::Result<(u32, Layout)> {
+        let revision = self.revision.load(Ordering::Relaxed);
+        let Some(properties) = self.properties(parent_id) else {
+            return Err(no_such_item(parent_id));
+        };
+        let children = if parent_id == 0 {
+            [PAUSE, CONFIG]
+                .into_iter()
+                .filter_map(|id| {
+                    let properties = self.properties(id)?;
+                    Some(Value::from((id, properties, Vec::<Value>::new())))
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        Ok((revision, (parent_id, properties, children)))
+    }
+
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, Properties)> {
+        ids.into_iter()
+            .filter_map(|id| Some((id, self.properties(id)?)))
+            .collect()
+    }
+
+    fn get_property(
+        &self,
+        id: i32,
+        name: &str,
+    ) -> zbus::fdo::Result<Value<'static>> {
+        self.properties(id)
+            .ok_or_else(|| no_such_item(id))?
+            .remove(name)
+            .ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("no property {name}"))
+            })
+    }
+
+    fn event(&self, id: i32, event_id: &str, _data: Value<'_>, _time: u32) {
+        if event_id == "clicked" {
+            self.clicked(id);
+        }
+    }
+
+    /// Handles a batch of events, and returns the IDs of any items that
+    /// don't exist.
+    fn event_group(
+        &self,
+        events: Vec<(i32, String, OwnedValue, u32)>,
+    ) -> Vec<i32> {
+        let mut missing = vec![];
+        for (id, event_id, _, _) in events {
+            if self.properties(id).is_none() {
+                missing.push(id);
+            } else if event_id == "clicked" {
+                self.clicked(id);
+            }
+        }
+        missing
+    }
+
+    /// The menu's about to open. Ours never needs updating at the last
+    /// moment.
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (vec![], vec![])
+    }
+
+    #[zbus(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[zbus(property)]
+    fn text_direction(&self) -> &'static str {
+        "ltr"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &'static str {
+        "normal"
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+fn no_such_item(id: i32) -> zbus::fdo::Error {
+    zbus::fdo::Error::InvalidArgs(format!("no menu item {id}"))
+}
+
+/// Opens `path` in whatever the user likes to edit it with, without holding
+/// up zbus while they do.
+fn open(path: PathBuf) {
+    let opened = std::thread::Builder::new()
+        .name("xdg-open".into())
+        .spawn(move || {
+            let mut cmd = Process::new("xdg-open");
+            cmd.arg(&path);
+            signal::unblock_in_child(&mut cmd);
+            match cmd.status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    warn!("can't open {}: xdg-open {status}", path.display())
+                }
+                Err(e) => warn!("can't open {}: {e}", path.display()),
+            }
+        });
+    if let Err(e) = opened {
+        warn!("can't open config file: {e}");
+    }
+}
@@ -0,0 +1,474 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Hiding the pointer under a Wayland compositor, with no X server.
+//!
+//! Wayland doesn't let one client see another's input, or hide the pointer on
+//! the compositor's behalf, so this takes two things from outside the
+//! protocol: the input devices in /dev/input, read directly, and some way of
+//! asking the particular compositor to hide the pointer, which is a `Cursor`.
+//! The user's own commands make a `Cursor` too, which works anywhere.
+//!
+//! What goes on in between is up to the same `Policy` that the banisher
+//! follows with X, so keys hide the pointer, pointer activity brings it back,
+//! and the timeouts work as usual. There's no keymap without X, so settings
+//! that go by keysym don't apply, and neither do the ones about windows, since
+//! we can't see any; we say so at startup, rather than quietly ignoring them.
+//! Modifiers are recognized by where they usually are on the keyboard.
+//!
+//! Everything else works as it does with X: the control socket and D-Bus
+//! interface, the tray icon, hooks, profiles, and reloading the config file.
+//! The control socket also stands in for the X selection that keeps us to one
+//! rxbanish at a time.
+
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::{info, trace, warn};
+use rxbanish_core::evdev::{Activity, Reader};
+use rxbanish_core::{
+    screenshot, Action, Banish, Change, Command, HideScope, Input, Method,
+    Policy, Reason, Settings, State,
+};
+use xcb::x::KeyButMask;
+
+use crate::backend::Backend;
+use crate::external::External;
+use crate::hooks::Hooks;
+use crate::signal::SignalFd;
+use crate::socket;
+use crate::{daemon, Daemon, Hider, Rxbanish};
+
+/// How long we'll wait for another rxbanish to exit when taking over from it,
+/// as with X.
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Something that can hide and show the pointer for us, by asking the
+/// compositor.
+pub trait Cursor {
+    fn hide(&mut self) -> Result<()>;
+    fn show(&mut self) -> Result<()>;
+
+    /// Catches up on anything that was left to finish in the background, and
+    /// says when that next needs doing, if ever.
+    fn poll(&mut self) -> Option<Instant> {
+        None
+    }
+}
+
+/// The compositors we know how to ask.
+#[derive(Clone, Debug)]
+pub enum Compositor {
+    /// Whatever the user's commands to hide and show the pointer do; see
+    /// `External`.
+    External { hide: String, show: String },
+    /// sway, over its IPC socket.
+    Sway,
+    /// Hyprland, over its IPC socket.
+    Hyprland,
+    /// river, by way of riverctl.
+    River,
+    /// GNOME Shell, with our extension installed.
+    #[cfg(feature = "gnome")]
+    Gnome,
+}
+
+impl Compositor {
+    /// Works out which compositor we're running under, going by the
+    /// environment, if it's one we know.
+    pub fn detect() -> Option<Self> {
+        if std::env::var_os("SWAYSOCK").is_some_and(|v| !v.is_empty()) {
+            return Some(Compositor::Sway);
+        }
+        let hyprland = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE");
+        if hyprland.is_some_and(|v| !v.is_empty()) {
+            return Some(Compositor::Hyprland);
+        }
+        // river doesn't announce itself, but sessions started for it usually
+        // say which desktop they're for.
+        let desktop = std::env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+        if desktop.split(':').any(|d| d.eq_ignore_ascii_case("river")) {
+            return Some(Compositor::River);
+        }
+        #[cfg(feature = "gnome")]
+        if desktop.split(':').any(|d| d == "GNOME") {
+            return Some(Compositor::Gnome);
+        }
+        None
+    }
+
+    /// Gets ready to ask the compositor to hide the pointer.
+    ///
+    /// Every compositor's `connect` shows the pointer before handing it over,
+    /// so that we start out knowing where we stand, and so that anything a
+    /// previous run left behind (say, one that was killed while the pointer
+    /// was hidden) is undone. The user's commands are the exception, since
+    /// there's no telling what running one unasked would do.
+    fn connect(self) -> Result<Box<dyn Cursor>> {
+        match self {
+            Compositor::External { hide, show } => {
+                Ok(Box::new(External::new(&hide, &show)))
+            }
+            Compositor::Sway => Ok(Box::new(crate::sway::Sway::connect()?)),
+            Compositor::Hyprland => {
+                Ok(Box::new(crate::hyprland::Hyprland::connect()?))
+            }
+            Compositor::River => Ok(Box::new(crate::river::connect()?)),
+            #[cfg(feature = "gnome")]
+            Compositor::Gnome => Ok(Box::new(crate::gnome::Shell::connect()?)),
+        }
+    }
+}
+
+/// Does the actual work of hiding the pointer under `compositor`, until it's
+/// time to exit.
+pub fn banish(
+    compositor: Compositor,
+    args: Rxbanish,
+    settings: Settings,
+    hooks: Hooks,
+    signals: SignalFd,
+) -> Result<()> {
+    let reader = Reader::new()?;
+    // Only one of us at a time, as with X.
+    let socket_path = socket::default_path(None);
+    if let Some(path) = &socket_path {
+        make_way(path, args.replace)?;
+    }
+    // As with X, only go into the background once we know we can do our job,
+    // and before there are any threads.
+    let _pid_file = if args.daemon {
+        daemon::daemonize(args.log_file.as_deref(), args.pid_file.as_deref())?
+    } else {
+        None
+    };
+    info!(compositor:?; "hiding the pointer without X");
+    let cursor = compositor.connect()?;
+    warn_unsupported(&settings);
+    // Everything else goes as it does with X; see `crate::Daemon`.
+    let typist = Typist::new(cursor, reader, settings);
+    Daemon::new(args, typist, hooks, signals, socket_path)?.serve()
+}
+
+/// Gets another rxbanish that's already running out of the way, if `replace`
+/// says to, or fails if not. Without X, the control socket at `path` is how we
+/// find it.
+fn make_way(path: &Path, replace: bool) -> Result<()> {
+    let Some(pid) = listener_pid(path) else {
+        return Ok(());
+    };
+    if !replace {
+        bail!(
+            "another rxbanish (pid {pid}) is already running; use --replace \
+             to take over from it"
+        );
+    }
+    info!(pid; "asking the other rxbanish to exit");
+    // Safety: kill has no memory safety preconditions.
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    let deadline = Instant::now() + REPLACE_TIMEOUT;
+    while alive(pid) {
+        if Instant::now() >= deadline {
+            bail!("the other rxbanish (pid {pid}) won't exit");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+/// Checks whether there's still a process with id `pid`.
+///
+/// All we have to go on is the number, so if the process exits and the number
+/// is handed to some new process before we look, this can't tell the two
+/// apart. That's unlikely in the moment `make_way` spends waiting, and the
+/// worst it can do there is make us wait out `REPLACE_TIMEOUT` and give up:
+/// we only ever send this process signal 0, which checks it exists without
+/// doing anything to it.
+fn alive(pid: libc::pid_t) -> bool {
+    // Safety: kill has no memory safety preconditions.
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/// Finds out which process is listening on the socket at `path`, if any.
+fn listener_pid(path: &Path) -> Option<libc::pid_t> {
+    let stream = UnixStream::connect(path).ok()?;
+    // Safety: ucred is plain old data, so all zeroes is a valid one.
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of_val(&cred) as libc::socklen_t;
+    // Safety: we're passing a valid pointer to a ucred, along with its size.
+    let r = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (r == 0 && cred.pid > 0).then_some(cred.pid)
+}
+
+/// Says which of `settings` we can't honor without X, so that nobody's left
+/// wondering why they don't work. Only settings the user has changed count;
+/// the rest go in one line at the end.
+fn warn_unsupported(settings: &Settings) {
+    let s = settings;
+    let default = Settings::default();
+    let unsupported = [
+        (!s.ignored_keys.is_empty(), "--ignore-key"),
+        (!s.ignored_groups.is_empty(), "--ignore-group"),
+        (!s.reveal_keys.is_empty(), "--reveal-key"),
+        (!s.excluded_classes.is_empty(), "--exclude-class"),
+        (!s.excluded_titles.is_empty(), "--exclude-title"),
+        (!s.only_classes.is_empty(), "--only-class"),
+        (!s.only_outputs.is_empty(), "--only-output"),
+        (s.only_over_focus, "--only-over-focus"),
+        (!s.excluded_hover_classes.is_empty(), "--exclude-hover-class"),
+        (!matches!(s.input, Input::Auto | Input::Evdev), "--input"),
+        (
+            s.screenshot_tools.len() > screenshot::BUILTIN.len(),
+            "--screenshot-tool",
+        ),
+        (s.sync_alarms, "--sync-alarms"),
+        (s.jitter.is_some(), "--jitter"),
+        (s.banish != Banish::Hide, "--banish"),
+        (
+            !matches!(s.method, Method::Xfixes | Method::Command),
+            "--method",
+        ),
+        (s.hide_scope != HideScope::Root, "--hide-scope"),
+        (s.park, "--park"),
+        (
+            s.skipped_window_types != default.skipped_window_types,
+            "--skip-window-type",
+        ),
+        (!s.touch_reveals, "--no-touch-reveal"),
+        (s.toggle_key.is_some(), "--toggle-key"),
+        (s.hide_key.is_some(), "--hide-key"),
+        (!s.profile_keys.is_empty(), "--profile-key"),
+    ];
+    for (_, name) in unsupported.into_iter().filter(|&(set, _)| set) {
+        warn!("ignoring {name}, which needs X");
+    }
+    if !(s.ignored_mods.is_empty() && s.chord_mods.is_empty()) {
+        warn!(
+            "without X, modifiers are recognized by where they usually are on \
+             the keyboard, which may not match your layout"
+        );
+    }
+    info!(
+        "without X, there's no leaving the pointer alone for fullscreen \
+         windows, window types, screenshot tools, or pointer grabs"
+    );
+}
+
+/// Without X, there's only the one seat, as far as `Policy` is concerned.
+const SEAT: u16 = 0;
+
+/// Hides and shows the pointer with a `Cursor`, as the policy says, from what
+/// the user does on the devices `reader` reads.
+struct Typist {
+    cursor: Box<dyn Cursor>,
+    reader: Reader,
+    settings: Settings,
+    policy: Policy,
+    /// The modifiers held down, by our best guess.
+    mods: KeyButMask,
+}
+
+impl Typist {
+    fn new(
+        cursor: Box<dyn Cursor>,
+        reader: Reader,
+        settings: Settings,
+    ) -> Self {
+        Self {
+            cursor,
+            reader,
+            settings,
+            policy: Policy::default(),
+            mods: KeyButMask::empty(),
+        }
+    }
+
+    /// Takes in `what` the user did, on the device called `device`.
+    fn handle(&mut self, device: &str, what: Activity) -> Result<()> {
+        let policy = self.settings.device_policy(device);
+        if self.settings.trace_events {
+            trace!(device, what:?, policy = policy.as_str(); "input");
+        }
+        let (action, own) = match what {
+            Activity::Key {
+                code,
+                pressed,
+                repeat,
+                ..
+            } => (Action::Key { pressed, repeat }, modifier_mask(code)),
+            Activity::Button { .. } => (Action::Button, KeyButMask::empty()),
+            Activity::Scroll(_) => (Action::Scroll, KeyButMask::empty()),
+            Activity::Motion => (Action::Motion, KeyButMask::empty()),
+        };
+        // A modifier counts as held for its own keystroke, either way.
+        let mods = self.mods | own;
+        if let Action::Key { pressed, .. } = action {
+            if pressed {
+                self.mods |= own;
+            } else {
+                self.mods -= own;
+            }
+        }
+        // The kernel can't tell us which devices are touchscreens, but then
+        // those that are don't often come with a keyboard to type on.
+        if !Policy::admits(&self.settings, policy, action, false) {
+            return Ok(());
+        }
+        let (target, reason) = match action {
+            Action::Key { .. } => {
+                match self.policy.keystroke(&self.settings, SEAT, mods, own) {
+                    Some(target) => (target, Reason::Key),
+                    None => return Ok(()),
+                }
+            }
+            _ => {
+                let state = self.policy.state();
+                let target = self.policy.pointer(
+                    &self.settings,
+                    SEAT,
+                    true,
+                    action,
+                    state,
+                );
+                (target, Reason::Motion)
+            }
+        };
+        self.change(target, reason, Some(device))
+    }
+
+    /// Moves the pointer to `target`, because of `reason`, and on account of
+    /// `device`, if there was one, unless we've been paused.
+    fn change(
+        &mut self,
+        target: State,
+        reason: Reason,
+        device: Option<&str>,
+    ) -> Result<()> {
+        let (target, reason) = self.policy.settle(target, reason, false);
+        let settings = &self.settings;
+        match self.policy.commit(settings, target, reason, device, None) {
+            _ if settings.dry_run => Ok(()),
+            Some(true) => self.cursor.hide(),
+            Some(false) => self.cursor.show(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Hider for Typist {
+    fn start(&mut self) -> Result<()> {
+        if self.settings.start_hidden {
+            self.change(State::Hidden, Reason::Start, None)?;
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> Backend {
+        Backend::Wayland
+    }
+
+    fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    fn set_settings(&mut self, settings: Settings) {
+        warn_unsupported(&settings);
+        self.settings = settings;
+    }
+
+    fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    fn take_changes(&mut self) -> Vec<Change> {
+        self.policy.take_changes()
+    }
+
+    fn take_profile_switch(&mut self) -> Option<Option<String>> {
+        self.policy.take_profile_switch()
+    }
+
+    fn devices(&self) -> Vec<String> {
+        self.reader.devices()
+    }
+
+    fn fds(&self) -> Vec<RawFd> {
+        vec![self.reader.as_raw_fd()]
+    }
+
+    fn handle_input(&mut self) -> Result<bool> {
+        let mut activity = vec![];
+        self.reader.poll(|name, what| {
+            activity.push((name.to_string(), what));
+        })?;
+        for (name, what) in activity {
+            self.handle(&name, what)?;
+        }
+        Ok(false)
+    }
+
+    fn run(&mut self, cmd: Command) -> Result<()> {
+        let target = match cmd {
+            // There's no server to get back in step with, so all that's left
+            // is to show the pointer.
+            Command::Resync => State::Shown,
+            cmd => self.policy.command(cmd),
+        };
+        self.change(target, Reason::Command, None)
+    }
+
+    fn handle_timeout(&mut self) -> Result<()> {
+        match self.policy.expired(&self.settings, Instant::now()) {
+            Some((target, reason)) => self.change(target, reason, None),
+            None => Ok(()),
+        }
+    }
+
+    /// The cursor may have work to catch up on in the background too.
+    fn schedule(&mut self) -> Result<Option<Instant>> {
+        let deadline = self.policy.deadline(&self.settings, false);
+        Ok([deadline, self.cursor.poll()].into_iter().flatten().min())
+    }
+}
+
+/// The compositor remembers the pointer's hidden after we've gone, so however
+/// we leave, by error or panic as well as by signal, it has to be shown
+/// again on the way out. (Nothing can help with SIGKILL; see the README for
+/// how to get the pointer back by hand.)
+impl Drop for Typist {
+    fn drop(&mut self) {
+        if let Err(e) = self.change(State::Shown, Reason::Exit, None) {
+            warn!("can't show the pointer on the way out: {e:#}");
+        }
+    }
+}
+
+/// Guesses which modifier the key with the kernel's keycode `code` is,
+/// going by the usual layout, since there's no keymap to ask.
+fn modifier_mask(code: u16) -> KeyButMask {
+    match code {
+        // Left and right shift.
+        42 | 54 => KeyButMask::SHIFT,
+        // Left and right control.
+        29 | 97 => KeyButMask::CONTROL,
+        // Left alt.
+        56 => KeyButMask::MOD1,
+        // Left and right super.
+        125 | 126 => KeyButMask::MOD4,
+        // Right alt, which is usually AltGr.
+        100 => KeyButMask::MOD5,
+        _ => KeyButMask::empty(),
+    }
+}